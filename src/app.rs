@@ -1,5 +1,17 @@
 mod file_list;
+#[cfg(feature = "metadata")]
+mod organizer;
 mod player_state;
+mod playlists;
+mod queue;
+mod recent;
+pub mod repeat;
+mod save_playlist;
+pub mod shuffle;
+mod stats;
+#[cfg(feature = "metadata")]
+mod tag_editor;
+pub mod timer;
 mod user_input;
 use crate::controls::{Metadata, PlaybackStatus};
 use crate::*;
@@ -28,6 +40,18 @@ pub enum Focusable {
   FileList,
   Dir,
   Search,
+  Sleep,
+  Alarm,
+  Queue,
+  SavePlaylist,
+  Playlists,
+  PlaylistName,
+  History,
+  Stats,
+  #[cfg(feature = "metadata")]
+  TagEditor,
+  #[cfg(feature = "metadata")]
+  Organizer,
 }
 
 pub enum AppCommand {
@@ -38,8 +62,13 @@ pub enum AppCommand {
   PlayPause,
   Next,
   Prev,
+  SetShuffle(bool), // from media controls, off or in the first mode
+  SetRepeat(repeat::Mode),
 }
 
+// What to do with a folder's files once they've all been read.
+pub type Then = fn(&mut App, Arc<Node>);
+
 pub struct App {
   pub backend: Box<dyn AudioBackend>,
   pub library: Library,
@@ -51,6 +80,20 @@ pub struct App {
   pub progress: (f64, u64, u64),
   pub playing: Option<Arc<Node>>,
   pub play_index: usize,
+  pub timers: timer::Timers,
+  pub queue: queue::Queue,
+  pub shuffle: shuffle::Shuffle,
+  pub repeat: repeat::Repeat,
+  pub playlists: playlists::Playlists,
+  pub recent: recent::Recent,
+  pub stats: stats::Stats,
+  #[cfg(feature = "metadata")]
+  pub tag_editor: tag_editor::TagEditor,
+  #[cfg(feature = "metadata")]
+  pub organizer: organizer::Organizer,
+  pub saving: Vec<Arc<Node>>, // for the save playlist prompt
+  // a folder whose files are wanted once the scanner's read it, and what for
+  waiting: Option<(Arc<Node>, Then)>,
   commands: (Arc<Sender<AppCommand>>, Receiver<AppCommand>),
   last_played: Option<Arc<Node>>,
   status_tx: Sender<PlaybackStatus>,
@@ -59,19 +102,45 @@ pub struct App {
 impl App {
   pub fn new() -> Self {
     let path = std::env::current_dir().expect("Could not get current dir.");
-    let backend = backends::load();
+    let mut app = Self::with_backend(backends::load(), &path);
+
+    let (status_tx, status_rx) = crossbeam_channel::unbounded();
+    controls::event_listener(app.commands.0.clone(), status_rx);
+    app.status_tx = status_tx;
+    #[cfg(feature = "scrobble")]
+    scrobble::start();
+
+    if let Ok(meta) = Meta::load() {
+      app.timers = meta.timers;
+      app.shuffle.mode = meta.shuffle;
+      app.repeat.mode = meta.repeat;
+      app.playlists.target = meta.playlist_target;
+      if let Some(last_path) = meta.last_path {
+        app.focus = Focusable::Dir;
+        app.input = last_path.display().to_string();
+        user_input::process_cmd(&mut app);
+      }
+    }
+    let _ = app.status_tx.send(PlaybackStatus::Shuffle(
+      app.shuffle.mode != shuffle::Mode::Off,
+    ));
+    let _ = app.status_tx.send(PlaybackStatus::Repeat(app.repeat.mode));
+
+    app.library.rebuild();
 
+    app
+  }
+
+  // The app on `path`, without media controls or what was saved last time.
+  fn with_backend(backend: Box<dyn AudioBackend>, path: &Path) -> Self {
     let progress = backend.progress();
 
     let (sender, receiver) = crossbeam_channel::unbounded();
-    let sender = Arc::new(sender);
+    let (status_tx, _) = crossbeam_channel::unbounded();
 
-    let (status_tx, status_rx) = crossbeam_channel::unbounded();
-    controls::event_listener(sender.clone(), status_rx);
-
-    let mut app = Self {
+    Self {
       backend,
-      library: Library::new(&path),
+      library: Library::new(path),
       playing: None,
       height: 0,
       focus: Focusable::FileList,
@@ -80,23 +149,23 @@ impl App {
       window_offset: 0,
       progress,
       play_index: 0,
-      commands: (sender, receiver),
+      timers: timer::Timers::default(),
+      queue: queue::Queue::default(),
+      shuffle: shuffle::Shuffle::default(),
+      repeat: repeat::Repeat::default(),
+      playlists: playlists::Playlists::default(),
+      recent: recent::Recent::default(),
+      stats: stats::Stats::default(),
+      #[cfg(feature = "metadata")]
+      tag_editor: tag_editor::TagEditor::default(),
+      #[cfg(feature = "metadata")]
+      organizer: organizer::Organizer::default(),
+      saving: vec![],
+      waiting: None,
+      commands: (Arc::new(sender), receiver),
       last_played: None,
       status_tx,
-    };
-    // app.set_root(&path);
-
-    if let Ok(meta) = Meta::load() {
-      if let Some(last_path) = meta.last_path {
-        app.focus = Focusable::Dir;
-        app.input = last_path.display().to_string();
-        user_input::process_cmd(&mut app);
-      }
     }
-
-    app.library.rebuild();
-
-    app
   }
 
   pub fn message(&self, msg: AppCommand) {
@@ -110,6 +179,8 @@ impl App {
         library.expand(path);
       }
     }
+    #[cfg(feature = "metadata")]
+    library.set_view(self.library.view);
     library.rebuild();
     self.library = library;
   }
@@ -156,6 +227,11 @@ impl App {
     let alt = key.modifiers.contains(KeyModifiers::ALT);
 
     match (key.code, ctrl, alt) {
+      // typed into the tag editor
+      #[cfg(feature = "metadata")]
+      (KeyCode::Char(_), false, false) if self.focus == Focusable::TagEditor => {
+        tag_editor::handle_input(self, key)
+      }
       (KeyCode::Char('q'), _, _) => {
         disable_raw_mode()?;
         execute!(
@@ -179,7 +255,20 @@ impl App {
       }
       _ => match self.focus {
         Focusable::FileList => file_list::handle_input(self, key),
-        Focusable::Dir | Focusable::Search => user_input::handle_input(self, key),
+        Focusable::Queue => queue::handle_input(self, key),
+        Focusable::Playlists => playlists::handle_input(self, key),
+        Focusable::History => recent::handle_input(self, key),
+        Focusable::Stats => stats::handle_input(self, key),
+        #[cfg(feature = "metadata")]
+        Focusable::TagEditor => tag_editor::handle_input(self, key),
+        #[cfg(feature = "metadata")]
+        Focusable::Organizer => organizer::handle_input(self, key),
+        Focusable::Dir
+        | Focusable::Search
+        | Focusable::Sleep
+        | Focusable::Alarm
+        | Focusable::SavePlaylist
+        | Focusable::PlaylistName => user_input::handle_input(self, key),
       },
     }
 
@@ -195,7 +284,12 @@ impl App {
       self.height = f.size().height as i32 - 1;
 
       let v_constraints = match self.focus {
-        Focusable::Dir | Focusable::Search => {
+        Focusable::Dir
+        | Focusable::Search
+        | Focusable::Sleep
+        | Focusable::Alarm
+        | Focusable::SavePlaylist
+        | Focusable::PlaylistName => {
           vec![
             Constraint::Length(3),
             Constraint::Min(1),
@@ -211,13 +305,28 @@ impl App {
         .split(f.size());
 
       match self.focus {
-        Focusable::Dir | Focusable::Search => {
+        Focusable::Dir
+        | Focusable::Search
+        | Focusable::Sleep
+        | Focusable::Alarm
+        | Focusable::SavePlaylist
+        | Focusable::PlaylistName => {
           user_input::render(self, chunks[0], f);
         }
         _ => {}
       }
 
-      file_list::render_file_list(self, chunks[chunks.len() - 2], f, list_state);
+      match self.focus {
+        Focusable::Queue => queue::render(self, chunks[chunks.len() - 2], f),
+        Focusable::Playlists => playlists::render(self, chunks[chunks.len() - 2], f),
+        Focusable::History => recent::render(self, chunks[chunks.len() - 2], f),
+        Focusable::Stats => stats::render(self, chunks[chunks.len() - 2], f),
+        #[cfg(feature = "metadata")]
+        Focusable::TagEditor => tag_editor::render(self, chunks[chunks.len() - 2], f),
+        #[cfg(feature = "metadata")]
+        Focusable::Organizer => organizer::render(self, chunks[chunks.len() - 2], f),
+        _ => file_list::render_file_list(self, chunks[chunks.len() - 2], f, list_state),
+      }
       player_state::render(self, &chunks.last().unwrap(), f);
     })?;
 
@@ -229,7 +338,11 @@ impl App {
 
     self.progress = self.backend.progress();
 
+    timer::update(self);
     self.ensure_continue();
+    let visible = self.view_range();
+    self.library.tag_visible(&visible);
+    self.refresh(list_state);
 
     let messages: Vec<AppCommand> = self.commands.1.try_iter().collect();
     for msg in messages {
@@ -237,6 +350,23 @@ impl App {
         Select(index) => {
           self.select(index, list_state);
         }
+        SelectDelta(delta) if self.focus == Focusable::Queue => {
+          queue::select_delta(self, delta);
+        }
+        SelectDelta(delta) if self.focus == Focusable::Playlists => {
+          playlists::select_delta(self, delta);
+        }
+        SelectDelta(delta) if self.focus == Focusable::History => {
+          recent::select_delta(self, delta);
+        }
+        #[cfg(feature = "metadata")]
+        SelectDelta(delta) if self.focus == Focusable::TagEditor => {
+          tag_editor::select_delta(self, delta);
+        }
+        #[cfg(feature = "metadata")]
+        SelectDelta(delta) if self.focus == Focusable::Organizer => {
+          organizer::select_delta(self, delta);
+        }
         SelectDelta(delta) => {
           let index = self.selected.unwrap_or(0) as i64 + delta;
           self.select(index.max(0) as usize, list_state);
@@ -244,14 +374,62 @@ impl App {
         Play(_path) => self.backend.play(None)?,
         PlayPause => self.backend.play_pause(),
         Pause => self.backend.pause(),
-        Next => self.play(self.play_index + 1),
-        Prev => self.play(self.play_index.saturating_sub(1)),
+        Next => self.next(),
+        Prev => self.prev(),
+        SetShuffle(on) => shuffle::toggle(self, on),
+        SetRepeat(mode) => repeat::set(self, mode),
       }
     }
 
     Ok(())
   }
 
+  // Follows changes on disk, keeping the highlighted and playing files where
+  // they are in the tree.
+  fn refresh(&mut self, list_state: &mut ListState) {
+    let highlighted = self.highlighted().map(|node| node.path.clone());
+    let playing = self
+      .library
+      .file_list()
+      .get(self.play_index)
+      .map(|(node, _)| node.path.clone());
+    if !self.library.refresh() {
+      return;
+    }
+
+    let position = |library: &Library, path: Option<PathBuf>| {
+      let path = path?;
+      library.file_list().iter().position(|(n, _)| n.path == path)
+    };
+    if let Some(index) = position(&self.library, playing) {
+      self.play_index = index;
+    }
+    match position(&self.library, highlighted) {
+      Some(index) => self.select(index, list_state),
+      None => self.select(self.selected.unwrap_or(0), list_state),
+    }
+
+    if let Some((folder, then)) = self.waiting.take() {
+      then(self, folder);
+    }
+  }
+
+  // Every file under a folder, from what the scanner's read. While it's
+  // still reading some of it that's None, and `then` is given the folder
+  // again once it's done.
+  pub fn all_files(&mut self, folder: &Arc<Node>, then: Then) -> Option<Vec<Arc<Node>>> {
+    let files = self.library.all_files(folder);
+    if files.is_none() {
+      self.waiting = Some((folder.clone(), then));
+    }
+    files
+  }
+
+  pub fn describe_waiting(&self) -> Option<String> {
+    let (folder, _) = self.waiting.as_ref()?;
+    Some(format!("Reading {}…", folder.title()))
+  }
+
   pub fn highlighted(&mut self) -> Option<Arc<Node>> {
     if let Some(selected) = self.selected {
       if let Some((node, _)) = self.library.file_list().get(selected) {
@@ -262,29 +440,87 @@ impl App {
   }
 
   pub fn play(&mut self, index: usize) {
-    self.play_index = index;
-    match self.library.file_list().get(index) {
-      Some((node, _)) => {
-        if node.is_file() {
-          let _ = self.status_tx.send(PlaybackStatus::Playing(Some(Metadata {
-            title: Some(node.title().to_owned()),
-            ..Default::default()
-          })));
-          self.last_played = Some(node.clone());
-          let _ = self.backend.play(Some(&node.path));
-          return;
-        }
+    self.queue.leave();
+    self.repeat.forget_folder();
+    self.walk(index, false);
+  }
 
-        self.expand(index);
-        self.play(index + 1);
+  // Plays the first file from `index` on, expanding folders on the way.
+  // Moving on from a track (`bounded`) also wraps around what's repeating.
+  fn walk(&mut self, mut index: usize, bounded: bool) {
+    let mut wrapped = false;
+    loop {
+      self.play_index = index;
+      let out_of_bounds = match bounded {
+        true => repeat::out_of_bounds(self, index),
+        false => self.library.file_list().get(index).is_none(),
+      };
+
+      if out_of_bounds {
+        match repeat::wrap(self) {
+          // only once, in case there's nothing left to play
+          Some(start) if !wrapped => {
+            wrapped = true;
+            index = start;
+            continue;
+          }
+          _ => break,
+        }
       }
-      None => {
-        self.pause();
+
+      let node = self.library.file_list()[index].0.clone();
+      if node.is_file() {
+        self.play_node(&node);
+        return;
       }
+      // the next file's needed now, not once the scanner gets to it
+      self.library.load(&node.path);
+      self.expand(index);
+      index += 1;
     }
+
+    self.pause();
     self.message(AppCommand::Select(index));
   }
 
+  // Plays a file without moving the tree's place, as the queue does.
+  pub fn play_node(&mut self, node: &Arc<Node>) {
+    recent::start(self, node);
+    self.last_played = Some(node.clone());
+    let _ = self.backend.play(Some(&node.path));
+    let title = self.backend.title().unwrap_or(node.title());
+    #[allow(unused_mut)]
+    let mut metadata = Metadata {
+      title: Some(title.to_owned()),
+      ..Default::default()
+    };
+    // it may be played before the tagger's got to it
+    #[cfg(feature = "metadata")]
+    let tags = match node.tagged {
+      true => node.metadata.clone(),
+      false => library::index::metadata(&node.path),
+    };
+    #[cfg(feature = "metadata")]
+    if let Some(tags) = &tags {
+      metadata.artist = tags.artist.clone();
+      metadata.album = tags.album.clone();
+    }
+    let _ = self.status_tx.send(PlaybackStatus::Playing(Some(metadata)));
+  }
+
+  // The queue goes first, then shuffle, then the tree carries on from where it was.
+  pub fn next(&mut self) {
+    if !queue::next(self) && !shuffle::next(self) {
+      self.walk(self.play_index + 1, true);
+    }
+  }
+
+  pub fn prev(&mut self) {
+    if !queue::prev(self) && !shuffle::prev(self) {
+      self.play(self.play_index.saturating_sub(1));
+    }
+  }
+
   pub fn play_path(&mut self, path: impl AsRef<Path>) {
     let path = path.as_ref();
     if !path.starts_with(&self.library.root.path) {
@@ -303,6 +539,9 @@ impl App {
         _ => break,
       };
     }
+    for path in &bubble_paths {
+      self.library.load(path);
+    }
     self.library.expand_all(&bubble_paths);
 
     let index = self
@@ -363,7 +602,90 @@ impl App {
   #[inline]
   fn ensure_continue(&mut self) {
     if self.backend.track_finished() {
-      self.play(self.play_index + 1);
+      recent::finish(self, true);
+      if timer::sleep_on_finish(self) {
+        return;
+      }
+      if repeat::replays(self) {
+        if let Some(node) = self.last_played.clone() {
+          self.play_node(&node);
+          return;
+        }
+      }
+      self.next();
     }
   }
 }
+
+// An app on a tree of empty files in a temporary folder of its own, with
+// the folders holding them open. The tree goes once it's dropped, even when
+// a test fails.
+#[cfg(test)]
+pub struct TestApp {
+  app: App,
+  pub root: PathBuf,
+}
+
+#[cfg(test)]
+impl TestApp {
+  pub fn new(files: &[&str]) -> Self {
+    Self::with_backend(files, backends::Silent::default())
+  }
+
+  pub fn with_backend(files: &[&str], backend: backends::Silent) -> Self {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    static COUNT: AtomicUsize = AtomicUsize::new(0);
+    let root = std::env::temp_dir().join(format!(
+      "aquinas-app-{}-{}",
+      std::process::id(),
+      COUNT.fetch_add(1, Ordering::SeqCst)
+    ));
+    let _ = fs::remove_dir_all(&root);
+    fs::create_dir_all(&root).unwrap();
+    let mut folders = vec![];
+    for file in files {
+      let path = root.join(file);
+      for folder in path.ancestors().skip(1).take_while(|p| *p != root) {
+        if !folders.iter().any(|f| f == folder) {
+          folders.push(folder.to_owned());
+        }
+      }
+      fs::create_dir_all(path.parent().unwrap()).unwrap();
+      fs::write(path, "").unwrap();
+    }
+
+    let mut app = App::with_backend(Box::new(backend), &root);
+    for folder in &folders {
+      app.library.load(folder);
+    }
+    app.library.expand_all(&folders);
+    Self { app, root }
+  }
+
+  pub fn row(&self, path: &Path) -> usize {
+    let list = self.app.library.file_list();
+    list.iter().position(|(node, _)| node.path == path).unwrap()
+  }
+}
+
+#[cfg(test)]
+impl std::ops::Deref for TestApp {
+  type Target = App;
+  fn deref(&self) -> &App {
+    &self.app
+  }
+}
+
+#[cfg(test)]
+impl std::ops::DerefMut for TestApp {
+  fn deref_mut(&mut self) -> &mut App {
+    &mut self.app
+  }
+}
+
+#[cfg(test)]
+impl Drop for TestApp {
+  fn drop(&mut self) {
+    let _ = fs::remove_dir_all(&self.root);
+  }
+}
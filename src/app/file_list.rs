@@ -23,8 +23,12 @@ pub fn render_file_list<'a, B: Backend>(
     .map(|(node, depth)| render_list_item(state, node, *depth))
     .collect();
 
+  #[allow(unused_mut)]
   let mut title = state.library.root.title().to_owned();
-  title.push_str(&" ".repeat(area.width as usize - title.len()));
+  #[cfg(feature = "metadata")]
+  if state.library.view != library::browse::View::Folders {
+    title = format!("{} · {}", title, state.library.view.name());
+  }
 
   let list = List::new(list_items)
     .block(
@@ -35,7 +39,7 @@ pub fn render_file_list<'a, B: Backend>(
           _ => Color::White,
         }))
         .title(Span::styled(
-          title,
+          format!("{:width$}", title, width = area.width as usize),
           Style::default()
             .bg(Color::Blue)
             .add_modifier(Modifier::BOLD),
@@ -64,18 +68,62 @@ fn render_list_item<'a>(state: &'a App, node: &'a Node, depth: usize) -> ListIte
           .fg(Color::Cyan)
           .add_modifier(Modifier::BOLD),
       ),
+      Span::styled(
+        match node.missing {
+          0 => String::new(),
+          n => format!(" ({} missing)", n),
+        },
+        Style::default().fg(Color::Red),
+      ),
+      Span::styled(
+        match node.scanning {
+          true => " scanning…",
+          false => "",
+        },
+        Style::default().fg(Color::DarkGray),
+      ),
     ]),
-    (false, Some(lp)) if *lp == node.path => Spans::from(vec![Span::styled(
-      format!("{}{}", " ".repeat(depth * 2), node.title()),
-      Style::default().bg(Color::White).fg(Color::Black),
+    (false, _) if node.missing > 0 => Spans::from(vec![Span::styled(
+      format!("{}✗ {} (missing)", " ".repeat(depth * 2), node.title()),
+      Style::default().fg(Color::Red),
     )]),
+    (false, Some(lp)) if *lp == node.path => Spans::from(vec![
+      Span::styled(
+        format!("{}{}", " ".repeat(depth * 2), node.title()),
+        Style::default().bg(Color::White).fg(Color::Black),
+      ),
+      render_rating(node),
+      render_plays(node),
+    ]),
     _ => Spans::from(vec![
       Span::from(" ".repeat(depth * 2)),
       Span::from(node.title().as_ref()),
+      render_rating(node),
+      render_plays(node),
     ]),
   })
 }
 
+fn render_rating<'a>(node: &Node) -> Span<'a> {
+  let rating = ratings::get(node);
+  let mut text = String::new();
+  if rating.stars > 0 {
+    text = format!(" {}", "★".repeat(rating.stars as usize));
+  }
+  if rating.favourite {
+    text.push_str(" ♥");
+  }
+  Span::styled(text, Style::default().fg(Color::Yellow))
+}
+
+fn render_plays<'a>(node: &Node) -> Span<'a> {
+  let text = match history::plays(&node.path) {
+    0 => String::new(),
+    plays => format!(" ×{}", plays),
+  };
+  Span::styled(text, Style::default().fg(Color::DarkGray))
+}
+
 pub fn handle_input<'a>(state: &'a mut App, key: &KeyEvent) {
   let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
 
@@ -109,8 +157,44 @@ pub fn handle_input<'a>(state: &'a mut App, key: &KeyEvent) {
       }
     }
     (KeyCode::Char(' '), _) => state.play_pause(),
+    (KeyCode::Char('e'), _) => queue::enqueue(state),
+    (KeyCode::Char('E'), _) => queue::play_next(state),
+    (KeyCode::Char('w'), _) => state.focus = Focusable::Queue,
+    (KeyCode::Char('z'), _) => shuffle::cycle(state),
+    (KeyCode::Char('r'), _) => repeat::cycle(state),
+    (KeyCode::Char('p'), _) => save_playlist::open(state),
+    (KeyCode::Char('l'), _) => playlists::open_view(state),
+    (KeyCode::Char('L'), _) => playlists::add_highlighted(state),
+    (KeyCode::Char('h'), _) => recent::open_view(state),
+    (KeyCode::Char('S'), _) => stats::open_view(state),
+    #[cfg(feature = "metadata")]
+    (KeyCode::Char('v'), _) => {
+      state.library.set_view(state.library.view.next());
+      state.message(AppCommand::Select(0));
+    }
+    #[cfg(feature = "metadata")]
+    (KeyCode::Char('T'), _) => tag_editor::open(state),
+    #[cfg(feature = "metadata")]
+    (KeyCode::Char('U'), _) => tag_editor::undo(state),
+    #[cfg(feature = "metadata")]
+    (KeyCode::Char('o'), _) => organizer::open(state),
+    (KeyCode::Char('+'), _) => rate(state, 1),
+    (KeyCode::Char('-'), _) => rate(state, -1),
+    (KeyCode::Char('*'), _) => {
+      if let Some(node) = state.highlighted().filter(|n| n.is_file()) {
+        ratings::toggle_favourite(&node);
+      }
+    }
     (KeyCode::Char('d'), _) => state.focus = Focusable::Dir,
     (KeyCode::Char('s'), _) => state.focus = Focusable::Search,
+    (KeyCode::Char('t'), _) => state.focus = Focusable::Sleep,
+    (KeyCode::Char('a'), _) => state.focus = Focusable::Alarm,
     _ => {}
   }
 }
+
+fn rate(state: &mut App, delta: i8) {
+  if let Some(node) = state.highlighted().filter(|n| n.is_file()) {
+    ratings::rate(&node, delta);
+  }
+}
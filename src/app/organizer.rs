@@ -0,0 +1,251 @@
+use super::*;
+use crate::metadata::Metadata;
+use crate::organize::{self, Move};
+use crossterm::event::KeyCode;
+use tui::{
+  layout::Rect,
+  style::{Color, Modifier, Style},
+  terminal::Frame,
+  text::{Span, Spans},
+  widgets::{Block, Borders, List, ListItem, ListState},
+};
+
+// Proposes moving a folder's files where `organize_layout` says by their
+// tags, and makes the moves that don't clash once they've been looked over.
+#[derive(Default)]
+pub struct Organizer {
+  moves: Vec<(Move, Option<&'static str>)>, // with why a move can't be made
+  selected: usize,
+  title: String,
+  error: Option<String>,
+  unread: Vec<PathBuf>, // files whose tags couldn't be read, left alone
+}
+
+// Plans the highlighted folder, or the highlighted file's.
+pub fn open(state: &mut App) {
+  let node = match state.highlighted() {
+    Some(node) => node,
+    None => return,
+  };
+  let folder = match node.is_dir() {
+    true => node,
+    false => match node.path.parent() {
+      Some(parent) => state.library.folder(parent),
+      None => return,
+    },
+  };
+  // their files are somewhere else, or not files of their own
+  if playlist::is_playlist(&folder.path)
+    || archive::is_dir(&folder.path)
+    || playlist::smart::is_smart(&folder.path)
+  {
+    return;
+  }
+  plan(state, folder);
+}
+
+fn plan(state: &mut App, folder: Arc<Node>) {
+  let nodes: Vec<Arc<Node>> = match state.all_files(&folder, plan) {
+    Some(files) => files,
+    None => return,
+  }
+  .into_iter()
+  .filter(|node| node.path.is_file())
+  .collect();
+
+  // moving files by tags that haven't been read would put them in Unknown,
+  // so the tagger gets to them first and the folder's planned again after
+  let untagged: Vec<Arc<Node>> = nodes
+    .iter()
+    .filter(|node| !node.tagged && library::index::indexed_metadata(&node.path).is_none())
+    .cloned()
+    .collect();
+  if !untagged.is_empty() {
+    state.library.tag_now(&untagged);
+    state.waiting = Some((folder, plan));
+    return;
+  }
+  let mut files: Vec<(PathBuf, Metadata)> = vec![];
+  let mut unread: Vec<PathBuf> = vec![];
+  for node in &nodes {
+    let tags = match node.tagged {
+      true => node.metadata.clone(),
+      false => library::index::indexed_metadata(&node.path).flatten(),
+    };
+    match tags {
+      Some(tags) => files.push((node.path.clone(), tags)),
+      None => unread.push(node.path.clone()),
+    }
+  }
+
+  let root = state.library.root.path.clone();
+  let layout = state.library.config.organize_layout.clone();
+  let organizer = &mut state.organizer;
+  match organize::plan(&root, &layout, &files) {
+    Ok(moves) => {
+      organizer.moves = moves;
+      organizer.error = None;
+    }
+    Err(error) => {
+      organizer.moves = vec![];
+      organizer.error = Some(error.to_string());
+    }
+  }
+  let name = folder
+    .path
+    .file_name()
+    .unwrap_or_default()
+    .to_string_lossy();
+  organizer.title = format!("{} files in {}", nodes.len(), name);
+  organizer.unread = unread;
+  organizer.selected = 0;
+  state.focus = Focusable::Organizer;
+}
+
+fn apply(state: &mut App) {
+  let moves: Vec<Move> = state
+    .organizer
+    .moves
+    .iter()
+    .filter(|(_, clash)| clash.is_none())
+    .map(|(step, _)| step.clone())
+    .collect();
+  if moves.is_empty() {
+    return;
+  }
+  let root = state.library.root.path.clone();
+  let (done, failed) = organize::apply(&root, &moves);
+  finish(state, &done, failed);
+}
+
+// Moves the latest batch back where it was.
+fn undo(state: &mut App) {
+  let (done, failed) = organize::undo();
+  finish(state, &done, failed);
+}
+
+fn finish(state: &mut App, done: &[Move], failed: Vec<String>) {
+  reread(state, done);
+  match failed.first() {
+    Some(error) => {
+      state.organizer.moves = vec![];
+      state.organizer.unread = vec![];
+      state.organizer.error = Some(format!("{} not moved: {}", failed.len(), error));
+    }
+    None => state.focus = Focusable::FileList,
+  }
+}
+
+// Has the library read the folders moved out of and into again, and those
+// above them, whose folders have changed.
+fn reread(state: &mut App, moves: &[Move]) {
+  let root = state.library.root.path.clone();
+  let mut folders = HashSet::new();
+  for path in moves.iter().flat_map(|step| [&step.from, &step.to]) {
+    for folder in path.ancestors().skip(1) {
+      if !folder.starts_with(&root) || !folders.insert(folder.to_owned()) {
+        break;
+      }
+    }
+  }
+  for folder in &folders {
+    state.library.invalidate(folder);
+  }
+  state.library.rebuild();
+}
+
+pub fn select_delta(state: &mut App, delta: i64) {
+  let organizer = &mut state.organizer;
+  let last = organizer.moves.len().saturating_sub(1) as i64;
+  organizer.selected = (organizer.selected as i64 + delta).clamp(0, last) as usize;
+}
+
+pub fn render<B: Backend>(state: &mut App, area: Rect, frame: &mut Frame<B>) {
+  let organizer = &state.organizer;
+  let root = &state.library.root.path;
+  let relative = |path: &Path| {
+    path
+      .strip_prefix(root)
+      .unwrap_or(path)
+      .display()
+      .to_string()
+  };
+  let dim = Style::default().fg(Color::DarkGray);
+
+  let mut items: Vec<ListItem> = organizer
+    .moves
+    .iter()
+    .map(|(step, clash)| {
+      let mut spans = vec![
+        Span::from(relative(&step.from)),
+        Span::styled(" → ", dim),
+        Span::styled(relative(&step.to), Style::default().fg(Color::Green)),
+      ];
+      if let Some(clash) = clash {
+        spans[2].style = Style::default().fg(Color::Red);
+        spans.push(Span::styled(
+          format!(" ({}, left)", clash),
+          Style::default().fg(Color::Red),
+        ));
+      }
+      ListItem::new(Spans::from(spans))
+    })
+    .collect();
+  for path in &organizer.unread {
+    items.push(ListItem::new(Spans::from(vec![
+      Span::from(relative(path)),
+      Span::styled(
+        " (tags couldn't be read, left)",
+        Style::default().fg(Color::Red),
+      ),
+    ])));
+  }
+  if organizer.moves.is_empty() && organizer.unread.is_empty() && organizer.error.is_none() {
+    items.push(ListItem::new(Span::styled("Everything's in place", dim)));
+  }
+  items.push(ListItem::new(""));
+  if let Some(error) = &organizer.error {
+    items.push(ListItem::new(Span::styled(
+      error.clone(),
+      Style::default().fg(Color::Red),
+    )));
+  }
+  let movable = organizer.moves.iter().filter(|(_, c)| c.is_none()).count();
+  let mut help = format!("Enter move {} · Esc close", movable);
+  if organize::can_undo() {
+    help.push_str(" · u undo last move");
+  }
+  items.push(ListItem::new(Span::styled(help, dim)));
+
+  let title = format!("Organize: {}", organizer.title);
+  let list = List::new(items)
+    .block(
+      Block::default().borders(Borders::RIGHT).title(Span::styled(
+        format!("{:width$}", title, width = area.width as usize),
+        Style::default()
+          .bg(Color::Blue)
+          .add_modifier(Modifier::BOLD),
+      )),
+    )
+    .highlight_style(
+      Style::default()
+        .bg(Color::LightGreen)
+        .fg(Color::Black)
+        .add_modifier(Modifier::BOLD),
+    );
+
+  let mut list_state = ListState::default();
+  if !organizer.moves.is_empty() {
+    list_state.select(Some(organizer.selected));
+  }
+  frame.render_stateful_widget(list, area, &mut list_state);
+}
+
+pub fn handle_input(state: &mut App, key: &KeyEvent) {
+  match key.code {
+    KeyCode::Enter => apply(state),
+    KeyCode::Char('u') => undo(state),
+    KeyCode::Char('o') | KeyCode::Esc => state.focus = Focusable::FileList,
+    _ => {}
+  }
+}
@@ -19,16 +19,37 @@ pub fn render<'a, B: Backend>(state: &'a mut App, area: &Rect, frame: &mut Frame
   let dur_min = dur / 60;
   let dur_sec = dur % 60;
 
+  let mut block = Block::default().borders(Borders::TOP);
+  let modes: Vec<String> = [
+    shuffle::describe(state),
+    repeat::describe(state),
+    timer::describe(state),
+    playlists::describe(state),
+    state.describe_waiting(),
+    state
+      .library
+      .watch_error()
+      .map(|error| format!("Not following changes: {}", error)),
+  ]
+  .into_iter()
+  .flatten()
+  .collect();
+  if !modes.is_empty() {
+    block = block.title(modes.join(" · "));
+  }
+
   let gauge = Gauge::default()
-    .block(Block::default().borders(Borders::TOP))
+    .block(block)
     .gauge_style(Style::default().fg(Color::Blue))
     .percent(((pct * 100.) as u16).min(100))
     .label(format!(
       "{}{}:{:0>2}/{}:{:0>2}",
       state
-        .playing
-        .as_ref()
-        .map(|p| format!("{} - ", p))
+        .backend
+        .title()
+        .map(|t| t.to_owned())
+        .or_else(|| state.playing.as_ref().map(|p| p.to_string()))
+        .map(|t| format!("{} - ", t))
         .unwrap_or(String::new()),
       pos_min,
       pos_sec,
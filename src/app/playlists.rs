@@ -0,0 +1,281 @@
+use super::*;
+use crate::playlist::{
+  is_missing,
+  managed::{self, Managed, Track},
+};
+use crossterm::event::KeyCode;
+use tui::{
+  layout::Rect,
+  style::{Color, Modifier, Style},
+  terminal::Frame,
+  text::Span,
+  widgets::{Block, Borders, List, ListItem, ListState},
+};
+
+// Playlists kept in the data directory. The view lists them, and shows the
+// tracks of the one that's open. Adding from the file list goes to the
+// target: the playlist opened last.
+#[derive(Default)]
+pub struct Playlists {
+  pub target: Option<String>,
+  names: Vec<String>,
+  open: Option<Managed>,
+  selected: usize,
+  renaming: Option<String>, // None when the name prompt makes a new one
+  error: Option<String>,    // why adding to the target last failed
+}
+
+const DEFAULT_NAME: &str = "Playlist";
+
+pub fn open_view(state: &mut App) {
+  state.playlists.names = managed::names();
+  state.playlists.selected = 0;
+  state.focus = Focusable::Playlists;
+}
+
+fn set_target(state: &mut App, name: Option<&str>) {
+  state.playlists.target = name.map(str::to_owned);
+  let mut meta = Meta::load().unwrap_or_default();
+  meta.playlist_target = state.playlists.target.clone();
+  let _ = meta.save();
+}
+
+// Adds the highlighted file, or everything in the highlighted folder, to the target.
+pub fn add_highlighted(state: &mut App) {
+  if let Some(node) = state.highlighted() {
+    add(state, node);
+  }
+}
+
+fn add(state: &mut App, node: Arc<Node>) {
+  let nodes = match node.is_dir() {
+    true => match state.all_files(&node, add) {
+      Some(files) => files,
+      None => return,
+    },
+    false => vec![node],
+  };
+  let name = state
+    .playlists
+    .target
+    .clone()
+    .unwrap_or(DEFAULT_NAME.to_owned());
+
+  // a playlist that can't be read is left alone rather than started over
+  let loaded = Managed::load(&name).or_else(|error| {
+    match error.downcast_ref::<std::io::Error>().map(|e| e.kind()) {
+      Some(std::io::ErrorKind::NotFound) => Ok(Managed::new(&name)),
+      _ => Err(error),
+    }
+  });
+  let saved = loaded.and_then(|mut managed| {
+    managed
+      .tracks
+      .extend(nodes.iter().map(|node| Track::new(node)));
+    managed.save()
+  });
+  state.playlists.error = match saved {
+    Ok(()) => {
+      set_target(state, Some(&name));
+      None
+    }
+    Err(error) => Some(format!("Not added to {}: {}", name, error)),
+  };
+}
+
+pub fn describe(state: &App) -> Option<String> {
+  state.playlists.error.clone()
+}
+
+fn len(state: &App) -> usize {
+  match &state.playlists.open {
+    Some(managed) => managed.tracks.len(),
+    None => state.playlists.names.len(),
+  }
+}
+
+pub fn select_delta(state: &mut App, delta: i64) {
+  let last = len(state).saturating_sub(1) as i64;
+  let playlists = &mut state.playlists;
+  playlists.selected = (playlists.selected as i64 + delta).clamp(0, last) as usize;
+}
+
+fn open(state: &mut App, name: &str) {
+  let mut managed = match Managed::load(name) {
+    Ok(managed) => managed,
+    Err(_) => return,
+  };
+  if managed.relocate(&state.library.root.path) {
+    let _ = managed.save();
+  }
+  set_target(state, Some(name));
+  state.playlists.open = Some(managed);
+  state.playlists.selected = 0;
+}
+
+fn close(state: &mut App) {
+  let closed = state.playlists.open.take().map(|m| m.name);
+  state.playlists.names = managed::names();
+  state.playlists.selected = closed
+    .and_then(|name| state.playlists.names.iter().position(|n| *n == name))
+    .unwrap_or(0);
+}
+
+// Queues the open playlist from the selected track on, and plays it.
+fn play(state: &mut App) {
+  let managed = match &state.playlists.open {
+    Some(managed) => managed,
+    None => return,
+  };
+  let config = &state.library.config;
+  let nodes: Vec<Arc<Node>> = managed
+    .tracks
+    .iter()
+    .skip(state.playlists.selected)
+    .filter(|track| !is_missing(&track.path))
+    .map(|track| Node::listed(track.entry(), config))
+    .collect();
+
+  // ahead of anything already queued, which carries on afterwards
+  state.queue.entries.splice(0..0, nodes);
+  queue::next(state);
+}
+
+fn prompt(state: &mut App, renaming: Option<String>) {
+  state.input = renaming.clone().unwrap_or_default();
+  state.playlists.renaming = renaming;
+  state.focus = Focusable::PlaylistName;
+}
+
+// Makes a new playlist, or renames one, from the name prompt.
+pub fn process_name(state: &mut App, input: &str) {
+  let name = input.trim();
+  let renaming = state.playlists.renaming.take();
+  if name.is_empty() {
+    return;
+  }
+
+  let saved = match &renaming {
+    Some(old) => Managed::load(old).and_then(|mut managed| managed.rename(name)),
+    None => Managed::new(name).save(),
+  };
+  if saved.is_ok() && (renaming.is_none() || renaming == state.playlists.target) {
+    set_target(state, Some(name));
+  }
+  state.playlists.names = managed::names();
+}
+
+pub fn render<B: Backend>(state: &mut App, area: Rect, frame: &mut Frame<B>) {
+  let playlists = &state.playlists;
+  let (title, items): (String, Vec<ListItem>) = match &playlists.open {
+    Some(managed) => (
+      format!("{} ({})", managed.name, managed.tracks.len()),
+      managed
+        .tracks
+        .iter()
+        .enumerate()
+        .map(|(i, track)| match is_missing(&track.path) {
+          true => ListItem::new(Span::styled(
+            format!("{:>2}. ✗ {} (missing)", i + 1, track.title),
+            Style::default().fg(Color::Red),
+          )),
+          false => ListItem::new(format!("{:>2}. {}", i + 1, track.title)),
+        })
+        .collect(),
+    ),
+    None => (
+      format!("Playlists ({})", playlists.names.len()),
+      playlists
+        .names
+        .iter()
+        .map(|name| match Some(name) == playlists.target.as_ref() {
+          true => ListItem::new(format!("★ {}", name)),
+          false => ListItem::new(format!("  {}", name)),
+        })
+        .collect(),
+    ),
+  };
+
+  let list = List::new(items)
+    .block(
+      Block::default().borders(Borders::RIGHT).title(Span::styled(
+        format!("{:width$}", title, width = area.width as usize),
+        Style::default()
+          .bg(Color::Blue)
+          .add_modifier(Modifier::BOLD),
+      )),
+    )
+    .highlight_style(
+      Style::default()
+        .bg(Color::LightGreen)
+        .fg(Color::Black)
+        .add_modifier(Modifier::BOLD),
+    );
+
+  let mut list_state = ListState::default();
+  if len(state) > 0 {
+    list_state.select(Some(state.playlists.selected));
+  }
+  frame.render_stateful_widget(list, area, &mut list_state);
+}
+
+pub fn handle_input(state: &mut App, key: &KeyEvent) {
+  let selected = state.playlists.selected;
+  let len = len(state);
+
+  if let Some(managed) = &mut state.playlists.open {
+    let changed = match key.code {
+      KeyCode::Enter if selected < len => {
+        play(state);
+        false
+      }
+      KeyCode::Char('x') | KeyCode::Delete if selected < len => {
+        managed.tracks.remove(selected);
+        true
+      }
+      KeyCode::Char('K') if selected > 0 && selected < len => {
+        managed.tracks.swap(selected, selected - 1);
+        state.playlists.selected -= 1;
+        true
+      }
+      KeyCode::Char('J') if selected + 1 < len => {
+        managed.tracks.swap(selected, selected + 1);
+        state.playlists.selected += 1;
+        true
+      }
+      KeyCode::Esc => {
+        close(state);
+        false
+      }
+      KeyCode::Char('l') => {
+        state.focus = Focusable::FileList;
+        false
+      }
+      _ => false,
+    };
+    if changed {
+      if let Some(managed) = &state.playlists.open {
+        let _ = managed.save();
+      }
+      select_delta(state, 0);
+    }
+    return;
+  }
+
+  let name = state.playlists.names.get(selected).cloned();
+  match (key.code, name) {
+    (KeyCode::Enter, Some(name)) => open(state, &name),
+    (KeyCode::Char('n'), _) => prompt(state, None),
+    (KeyCode::Char('R'), Some(name)) => prompt(state, Some(name)),
+    (KeyCode::Char('D'), Some(name)) => {
+      let _ = Managed::delete(&name);
+      if state.playlists.target.as_ref() == Some(&name) {
+        set_target(state, None);
+      }
+      state.playlists.names = managed::names();
+      select_delta(state, 0);
+    }
+    (KeyCode::Char('l') | KeyCode::Esc, _) => state.focus = Focusable::FileList,
+    _ => {}
+  }
+}
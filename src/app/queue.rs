@@ -0,0 +1,275 @@
+use super::*;
+use crossterm::event::KeyCode;
+use tui::{
+  layout::Rect,
+  style::{Color, Modifier, Style},
+  terminal::Frame,
+  text::Span,
+  widgets::{Block, Borders, List, ListItem, ListState},
+};
+
+// Tracks queued up to play before the file tree carries on. Entries are
+// consumed as they play, and kept in `history` so Prev can step back.
+#[derive(Default)]
+pub struct Queue {
+  pub entries: Vec<Arc<Node>>,
+  pub current: Option<Arc<Node>>,
+  history: Vec<Arc<Node>>,
+  selected: usize,
+}
+
+impl Queue {
+  // Playing from the tree leaves the queue's place.
+  pub fn leave(&mut self) {
+    self.current = None;
+    self.history.clear();
+  }
+}
+
+// A file on its own, or every file in a folder once they've been read.
+fn files(state: &mut App, node: Arc<Node>, then: Then) -> Option<Vec<Arc<Node>>> {
+  match node.is_dir() {
+    true => state.all_files(&node, then),
+    false => Some(vec![node]),
+  }
+}
+
+pub fn enqueue(state: &mut App) {
+  if let Some(node) = state.highlighted() {
+    enqueue_node(state, node);
+  }
+}
+
+fn enqueue_node(state: &mut App, node: Arc<Node>) {
+  if let Some(files) = files(state, node, enqueue_node) {
+    state.queue.entries.extend(files);
+  }
+}
+
+pub fn play_next(state: &mut App) {
+  if let Some(node) = state.highlighted() {
+    play_node_next(state, node);
+  }
+}
+
+fn play_node_next(state: &mut App, node: Arc<Node>) {
+  if let Some(files) = files(state, node, play_node_next) {
+    state.queue.entries.splice(0..0, files);
+  }
+}
+
+// Plays the next queued track, returning false when the queue has run out.
+pub fn next(state: &mut App) -> bool {
+  let queue = &mut state.queue;
+  if queue.entries.is_empty() {
+    queue.leave();
+    return false;
+  }
+
+  let node = queue.entries.remove(0);
+  if let Some(previous) = queue.current.replace(node.clone()) {
+    queue.history.push(previous);
+  }
+  queue.selected = queue.selected.saturating_sub(1);
+  state.play_node(&node);
+  true
+}
+
+// Steps back through the queue, returning false when not playing from it.
+pub fn prev(state: &mut App) -> bool {
+  let queue = &mut state.queue;
+  let current = match queue.current.take() {
+    Some(current) => current,
+    None => return false,
+  };
+  queue.entries.insert(0, current);
+
+  match queue.history.pop() {
+    Some(node) => {
+      queue.current = Some(node.clone());
+      state.play_node(&node);
+    }
+    // back to where the tree was when the queue started
+    None => {
+      if let Some((node, _)) = state.library.file_list().get(state.play_index) {
+        let node = node.clone();
+        state.play_node(&node);
+      }
+    }
+  }
+  true
+}
+
+pub fn select_delta(state: &mut App, delta: i64) {
+  let queue = &mut state.queue;
+  let last = queue.entries.len().saturating_sub(1) as i64;
+  queue.selected = (queue.selected as i64 + delta).clamp(0, last) as usize;
+}
+
+pub fn render<B: Backend>(state: &mut App, area: Rect, frame: &mut Frame<B>) {
+  let queue = &state.queue;
+  let mut items = vec![];
+  if let Some(current) = &queue.current {
+    items.push(ListItem::new(Span::styled(
+      format!("▶ {}", current.title()),
+      Style::default().bg(Color::White).fg(Color::Black),
+    )));
+  }
+  items.extend(
+    queue
+      .entries
+      .iter()
+      .enumerate()
+      .map(|(i, node)| ListItem::new(format!("{:>2}. {}", i + 1, node.title()))),
+  );
+
+  let title = format!("Queue ({})", queue.entries.len());
+  let list = List::new(items)
+    .block(
+      Block::default().borders(Borders::RIGHT).title(Span::styled(
+        format!("{:width$}", title, width = area.width as usize),
+        Style::default()
+          .bg(Color::Blue)
+          .add_modifier(Modifier::BOLD),
+      )),
+    )
+    .highlight_style(
+      Style::default()
+        .bg(Color::LightGreen)
+        .fg(Color::Black)
+        .add_modifier(Modifier::BOLD),
+    );
+
+  let mut list_state = ListState::default();
+  if !queue.entries.is_empty() {
+    let offset = queue.current.is_some() as usize;
+    list_state.select(Some(queue.selected + offset));
+  }
+  frame.render_stateful_widget(list, area, &mut list_state);
+}
+
+pub fn handle_input(state: &mut App, key: &KeyEvent) {
+  let queue = &mut state.queue;
+  let selected = queue.selected;
+
+  match key.code {
+    KeyCode::Enter if selected < queue.entries.len() => {
+      let node = queue.entries.remove(selected);
+      queue.entries.insert(0, node);
+      next(state);
+    }
+    KeyCode::Char('x') | KeyCode::Delete if selected < queue.entries.len() => {
+      queue.entries.remove(selected);
+      select_delta(state, 0);
+    }
+    KeyCode::Char('K') if selected > 0 && selected < queue.entries.len() => {
+      queue.entries.swap(selected, selected - 1);
+      queue.selected -= 1;
+    }
+    KeyCode::Char('J') if selected + 1 < queue.entries.len() => {
+      queue.entries.swap(selected, selected + 1);
+      queue.selected += 1;
+    }
+    KeyCode::Char('c') => {
+      queue.entries.clear();
+      queue.selected = 0;
+    }
+    KeyCode::Char(' ') => state.play_pause(),
+    KeyCode::Char('p') => save_playlist::open_queue(state),
+    KeyCode::Char('w') | KeyCode::Esc => state.focus = Focusable::FileList,
+    _ => {}
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crossterm::event::KeyModifiers;
+
+  const FILES: [&str; 3] = ["a/1.mp3", "a/2.mp3", "b/3.mp3"];
+
+  fn queued(app: &App) -> Vec<PathBuf> {
+    app
+      .queue
+      .entries
+      .iter()
+      .map(|node| node.path.clone())
+      .collect()
+  }
+
+  fn press(app: &mut App, code: KeyCode) {
+    handle_input(app, &KeyEvent::new(code, KeyModifiers::NONE));
+  }
+
+  #[test]
+  fn folders_queue_and_files_go_next() {
+    let mut app = TestApp::new(&FILES);
+    let root = app.root.clone();
+    app.selected = Some(app.row(&root.join("a")));
+    enqueue(&mut app);
+    assert_eq!(queued(&app), [root.join("a/1.mp3"), root.join("a/2.mp3")]);
+
+    app.selected = Some(app.row(&root.join("b/3.mp3")));
+    play_next(&mut app);
+    assert_eq!(
+      queued(&app),
+      [
+        root.join("b/3.mp3"),
+        root.join("a/1.mp3"),
+        root.join("a/2.mp3")
+      ]
+    );
+  }
+
+  #[test]
+  fn the_view_reorders_and_removes() {
+    let mut app = TestApp::new(&FILES);
+    let root = app.root.clone();
+    app.selected = Some(app.row(&root.join("a")));
+    enqueue(&mut app);
+    app.selected = Some(app.row(&root.join("b/3.mp3")));
+    enqueue(&mut app);
+
+    press(&mut app, KeyCode::Char('J'));
+    assert_eq!(
+      queued(&app),
+      [
+        root.join("a/2.mp3"),
+        root.join("a/1.mp3"),
+        root.join("b/3.mp3")
+      ]
+    );
+    assert_eq!(app.queue.selected, 1);
+    press(&mut app, KeyCode::Char('K'));
+    assert_eq!(app.queue.selected, 0);
+    press(&mut app, KeyCode::Char('x'));
+    assert_eq!(queued(&app), [root.join("a/2.mp3"), root.join("b/3.mp3")]);
+    press(&mut app, KeyCode::Char('c'));
+    assert!(queued(&app).is_empty());
+  }
+
+  #[test]
+  fn next_and_prev_take_from_the_queue_first() {
+    let mut app = TestApp::new(&FILES);
+    let root = app.root.clone();
+    let playing = |app: &App| app.last_played.as_ref().unwrap().path.clone();
+    let row = app.row(&root.join("a/1.mp3"));
+    app.play(row);
+    app.selected = Some(app.row(&root.join("b/3.mp3")));
+    enqueue(&mut app);
+
+    app.next();
+    assert_eq!(playing(&app), root.join("b/3.mp3"));
+    assert!(queued(&app).is_empty());
+    // back to where the tree was, with the track queued again
+    app.prev();
+    assert_eq!(playing(&app), root.join("a/1.mp3"));
+    assert_eq!(queued(&app), [root.join("b/3.mp3")]);
+
+    app.next();
+    assert_eq!(playing(&app), root.join("b/3.mp3"));
+    // with the queue run out, the tree carries on
+    app.next();
+    assert_eq!(playing(&app), root.join("a/2.mp3"));
+  }
+}
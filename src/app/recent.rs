@@ -0,0 +1,144 @@
+use super::*;
+use crate::history::{self, Play};
+use chrono::{Local, TimeZone};
+use crossterm::event::KeyCode;
+use tui::{
+  layout::Rect,
+  style::{Color, Modifier, Style},
+  terminal::Frame,
+  text::Span,
+  widgets::{Block, Borders, List, ListItem, ListState},
+};
+
+// How many plays the history view goes back.
+const SHOWN: usize = 200;
+
+// Records what's playing into the history, and shows the latest plays.
+#[derive(Default)]
+pub struct Recent {
+  listening: Option<(Arc<Node>, i64)>, // (node, start)
+  plays: Vec<(Play, Arc<Node>)>,
+  selected: usize,
+}
+
+// Called as a track starts, which ends whatever was playing before.
+pub fn start(state: &mut App, node: &Arc<Node>) {
+  finish(state, false);
+  state.recent.listening = Some((node.clone(), history::now()));
+}
+
+// Ends the current play: `completed` when it played to the end, otherwise
+// it counts as skipped if it stopped before half way, or before its length
+// was known.
+pub fn finish(state: &mut App, completed: bool) {
+  let (node, start) = match state.recent.listening.take() {
+    Some(listening) => listening,
+    None => return,
+  };
+  let (_, pos, dur) = state.progress;
+  let listened = match (completed, dur) {
+    (true, _) => dur,
+    // no length known yet, so however long it was playing
+    (false, 0) => (history::now() - start).max(0) as u64,
+    (false, _) => pos.min(dur),
+  };
+  history::record(Play {
+    start,
+    path: node.path.clone(),
+    listened,
+    // without a length there's no telling it got half way
+    skipped: !completed && (dur == 0 || listened * 2 < dur),
+  });
+  // plays and what was played last are among their rules
+  state.library.played();
+  #[cfg(feature = "scrobble")]
+  scrobble::record(&node, start, listened, dur);
+}
+
+pub fn open_view(state: &mut App) {
+  state.recent.plays = history::recent(SHOWN)
+    .into_iter()
+    .map(|play| {
+      let node = Node::new(&play.path, &state.library.config);
+      (play, node)
+    })
+    .collect();
+  state.recent.selected = 0;
+  state.focus = Focusable::History;
+}
+
+pub fn select_delta(state: &mut App, delta: i64) {
+  let recent = &mut state.recent;
+  let last = recent.plays.len().saturating_sub(1) as i64;
+  recent.selected = (recent.selected as i64 + delta).clamp(0, last) as usize;
+}
+
+pub fn render<B: Backend>(state: &mut App, area: Rect, frame: &mut Frame<B>) {
+  let recent = &state.recent;
+  let items: Vec<ListItem> = recent
+    .plays
+    .iter()
+    .map(|(play, node)| {
+      let when = Local
+        .timestamp_opt(play.start, 0)
+        .single()
+        .map(|t| t.format("%Y-%m-%d %H:%M").to_string())
+        .unwrap_or_default();
+      let text = format!("{}  {}", when, node.title());
+      match play.skipped {
+        true => ListItem::new(Span::styled(
+          format!("{} (skipped)", text),
+          Style::default().fg(Color::DarkGray),
+        )),
+        false => ListItem::new(text),
+      }
+    })
+    .collect();
+
+  let title = format!("History ({})", recent.plays.len());
+  let list = List::new(items)
+    .block(
+      Block::default().borders(Borders::RIGHT).title(Span::styled(
+        format!("{:width$}", title, width = area.width as usize),
+        Style::default()
+          .bg(Color::Blue)
+          .add_modifier(Modifier::BOLD),
+      )),
+    )
+    .highlight_style(
+      Style::default()
+        .bg(Color::LightGreen)
+        .fg(Color::Black)
+        .add_modifier(Modifier::BOLD),
+    );
+
+  let mut list_state = ListState::default();
+  if !recent.plays.is_empty() {
+    list_state.select(Some(recent.selected));
+  }
+  frame.render_stateful_widget(list, area, &mut list_state);
+}
+
+pub fn handle_input(state: &mut App, key: &KeyEvent) {
+  let selected = state.recent.selected;
+
+  match key.code {
+    KeyCode::Enter => {
+      if let Some((_, node)) = state.recent.plays.get(selected) {
+        let node = node.clone();
+        if !playlist::is_missing(&node.path) {
+          state.queue.entries.insert(0, node);
+          queue::next(state);
+        }
+      }
+    }
+    KeyCode::Char('e') => {
+      if let Some((_, node)) = state.recent.plays.get(selected) {
+        state.queue.entries.push(node.clone());
+      }
+    }
+    KeyCode::Char(' ') => state.play_pause(),
+    KeyCode::Char('h') | KeyCode::Esc => state.focus = Focusable::FileList,
+    _ => {}
+  }
+}
@@ -0,0 +1,189 @@
+use super::*;
+use serde_derive::{Deserialize, Serialize};
+
+// Named after MPRIS loop statuses, with Folder between Track and Playlist.
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Debug, Default)]
+pub enum Mode {
+  #[default]
+  Off,
+  One,
+  Folder,
+  All,
+}
+
+#[derive(Default)]
+pub struct Repeat {
+  pub mode: Mode,
+  // the folder being repeated, taken from the track playing when it's needed
+  folder: Option<PathBuf>,
+}
+
+impl Repeat {
+  // Picking a track starts repeating its folder instead.
+  pub fn forget_folder(&mut self) {
+    self.folder = None;
+  }
+}
+
+pub fn cycle(state: &mut App) {
+  let mode = match state.repeat.mode {
+    Mode::Off => Mode::One,
+    Mode::One => Mode::Folder,
+    Mode::Folder => Mode::All,
+    Mode::All => Mode::Off,
+  };
+  set(state, mode);
+}
+
+pub fn set(state: &mut App, mode: Mode) {
+  state.repeat.mode = mode;
+  state.repeat.folder = None;
+  let _ = state.status_tx.send(PlaybackStatus::Repeat(mode));
+
+  let mut meta = Meta::load().unwrap_or_default();
+  meta.repeat = mode;
+  let _ = meta.save();
+}
+
+// The mode's MPRIS LoopStatus, where repeating a folder counts as a playlist.
+pub fn loop_status(mode: Mode) -> &'static str {
+  match mode {
+    Mode::Off => "None",
+    Mode::One => "Track",
+    Mode::Folder | Mode::All => "Playlist",
+  }
+}
+
+// The mode a LoopStatus set over MPRIS asks for. Playlist keeps repeating
+// the folder if that's what's on.
+pub fn from_loop_status(status: &str, mode: Mode) -> Option<Mode> {
+  Some(match status {
+    "None" => Mode::Off,
+    "Track" => Mode::One,
+    "Playlist" if mode == Mode::Folder => Mode::Folder,
+    "Playlist" => Mode::All,
+    _ => return None,
+  })
+}
+
+// Whether a finished track should play again instead of moving on.
+pub fn replays(state: &App) -> bool {
+  state.repeat.mode == Mode::One
+}
+
+// Whether the tree walk has left what's repeating, at `index` in the file list.
+pub fn out_of_bounds(state: &mut App, index: usize) -> bool {
+  let node = match state.library.file_list().get(index) {
+    Some((node, _)) => node.clone(),
+    None => return true,
+  };
+  match folder(state) {
+    Some(folder) => !node.path.starts_with(folder),
+    None => false,
+  }
+}
+
+// Where the tree walk starts over, or None to stop at the end.
+pub fn wrap(state: &mut App) -> Option<usize> {
+  match state.repeat.mode {
+    Mode::All => Some(0),
+    Mode::Folder => {
+      let folder = folder(state)?;
+      let list = state.library.file_list();
+      // the root isn't in the list itself
+      Some(
+        match list.iter().position(|(node, _)| node.path == folder) {
+          Some(index) => index + 1,
+          None => 0,
+        },
+      )
+    }
+    Mode::Off | Mode::One => None,
+  }
+}
+
+fn folder(state: &mut App) -> Option<PathBuf> {
+  if state.repeat.mode != Mode::Folder {
+    return None;
+  }
+  if state.repeat.folder.is_none() {
+    let playing = state.last_played.as_ref()?;
+    state.repeat.folder = playing.path.parent().map(Path::to_owned);
+  }
+  state.repeat.folder.clone()
+}
+
+pub fn describe(state: &App) -> Option<String> {
+  match state.repeat.mode {
+    Mode::Off => None,
+    Mode::One => Some("Repeat one".to_owned()),
+    Mode::Folder => Some(match &state.repeat.folder {
+      Some(folder) => format!(
+        "Repeat {}",
+        folder.file_name().unwrap_or_default().to_string_lossy()
+      ),
+      None => "Repeat folder".to_owned(),
+    }),
+    Mode::All => Some("Repeat all".to_owned()),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // An app on a/1, a/2 and b/3, with both folders open, and a/1 last played.
+  fn app() -> TestApp {
+    let mut app = TestApp::new(&["a/1.mp3", "a/2.mp3", "b/3.mp3"]);
+    app.last_played = Some(Node::new(app.root.join("a/1.mp3"), &app.library.config));
+    app
+  }
+
+  fn paths(app: &App) -> Vec<PathBuf> {
+    let list = app.library.file_list();
+    list.iter().map(|(node, _)| node.path.clone()).collect()
+  }
+
+  #[test]
+  fn folders_keep_the_walk_inside() {
+    let mut app = app();
+    let root = app.root.clone();
+    let list = paths(&app);
+    let b = list
+      .iter()
+      .position(|path| *path == root.join("b"))
+      .unwrap();
+    let a2 = list
+      .iter()
+      .position(|path| *path == root.join("a/2.mp3"))
+      .unwrap();
+
+    app.repeat.mode = Mode::Folder;
+    assert!(!out_of_bounds(&mut app, a2));
+    assert!(out_of_bounds(&mut app, b));
+    assert!(out_of_bounds(&mut app, list.len()));
+    // back to the folder's first row
+    let start = wrap(&mut app).unwrap();
+    assert_eq!(list[start], root.join("a/1.mp3"));
+
+    app.repeat.mode = Mode::All;
+    assert!(!out_of_bounds(&mut app, b));
+    assert!(out_of_bounds(&mut app, list.len()));
+    assert_eq!(wrap(&mut app), Some(0));
+
+    app.repeat.mode = Mode::Off;
+    assert!(!out_of_bounds(&mut app, b));
+    assert_eq!(wrap(&mut app), None);
+  }
+
+  #[test]
+  fn loop_statuses_map_to_modes() {
+    for mode in [Mode::Off, Mode::One, Mode::Folder, Mode::All] {
+      assert_eq!(from_loop_status(loop_status(mode), mode), Some(mode));
+    }
+    assert_eq!(loop_status(Mode::Folder), "Playlist");
+    assert_eq!(from_loop_status("Playlist", Mode::Off), Some(Mode::All));
+    assert_eq!(from_loop_status("Track", Mode::Folder), Some(Mode::One));
+    assert_eq!(from_loop_status("Sometimes", Mode::Off), None);
+  }
+}
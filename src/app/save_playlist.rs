@@ -0,0 +1,63 @@
+use super::*;
+
+// Opens the save prompt for the highlighted folder (or the one holding the
+// highlighted file), named after it.
+pub fn open(state: &mut App) {
+  let node = match state.highlighted() {
+    Some(node) if node.is_dir() => node,
+    Some(node) => match node.path.parent() {
+      Some(parent) => state.library.folder(parent),
+      None => return,
+    },
+    None => state.library.root.clone(),
+  };
+  open_folder(state, node);
+}
+
+fn open_folder(state: &mut App, folder: Arc<Node>) {
+  state.saving = match state.all_files(&folder, open_folder) {
+    Some(files) => files,
+    None => return,
+  };
+  prompt(state, &format!("{}.m3u8", folder.title()));
+}
+
+// Opens the save prompt for what's playing from the queue and what's left in it.
+pub fn open_queue(state: &mut App) {
+  state.saving = state
+    .queue
+    .current
+    .iter()
+    .chain(state.queue.entries.iter())
+    .cloned()
+    .collect();
+  prompt(state, "queue.m3u8");
+}
+
+fn prompt(state: &mut App, name: &str) {
+  state.input = name.to_owned();
+  state.focus = Focusable::SavePlaylist;
+}
+
+// Saves to the path given, relative to the library root unless absolute.
+// The extension picks the format: m3u8 (the default), m3u, pls or xspf.
+pub fn process(state: &mut App, input: &str) {
+  let nodes = std::mem::take(&mut state.saving);
+  let input = input.trim();
+  if input.is_empty() || nodes.is_empty() {
+    return;
+  }
+
+  let mut path = state.library.root.path.join(input);
+  if path.extension().is_none() {
+    path.set_extension("m3u8");
+  }
+  if let Some(dir) = path.parent() {
+    let _ = fs::create_dir_all(dir);
+  }
+  if playlist::write(&path, &nodes).is_ok() {
+    // show the new playlist in the tree
+    let root = state.library.root.path.clone();
+    state.set_root(&root);
+  }
+}
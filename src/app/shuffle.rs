@@ -0,0 +1,268 @@
+use super::*;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Default)]
+pub enum Mode {
+  #[default]
+  Off,
+  Tracks,
+  Rated,   // tracks, higher rated ones earlier on average
+  Albums,  // albums in random order, each played through
+  Folders, // the same, for folders `shuffle_depth` below the one shuffled
+}
+
+// A shuffled pass over a folder: the one highlighted as it starts, or else
+// the one holding what's playing. Played tracks are kept so Prev goes back
+// to what actually played before.
+#[derive(Default)]
+pub struct Shuffle {
+  pub mode: Mode,
+  upcoming: Option<VecDeque<Arc<Node>>>,
+  history: Vec<Arc<Node>>,
+  root: PathBuf, // the folder being shuffled
+}
+
+pub fn cycle(state: &mut App) {
+  let mode = match state.shuffle.mode {
+    Mode::Off => Mode::Tracks,
+    Mode::Tracks => Mode::Rated,
+    Mode::Rated => Mode::Albums,
+    Mode::Albums => Mode::Folders,
+    Mode::Folders => Mode::Off,
+  };
+  set(state, mode);
+}
+
+// Media controls only turn shuffle on or off, on being tracks.
+pub fn toggle(state: &mut App, on: bool) {
+  match (on, state.shuffle.mode) {
+    (true, Mode::Off) => set(state, Mode::Tracks),
+    (false, _) => set(state, Mode::Off),
+    // already on, in whatever mode
+    (true, _) => {}
+  }
+}
+
+fn set(state: &mut App, mode: Mode) {
+  let shuffle = &mut state.shuffle;
+  shuffle.mode = mode;
+  shuffle.upcoming = None;
+  if mode == Mode::Off {
+    shuffle.history.clear();
+  }
+  let _ = state
+    .status_tx
+    .send(PlaybackStatus::Shuffle(mode != Mode::Off));
+
+  let mut meta = Meta::load().unwrap_or_default();
+  meta.shuffle = mode;
+  let _ = meta.save();
+}
+
+// Plays the next shuffled track, returning false when shuffle is off.
+pub fn next(state: &mut App) -> bool {
+  let shuffle = &mut state.shuffle;
+  if shuffle.mode == Mode::Off {
+    return false;
+  }
+
+  if !shuffle.root.starts_with(&state.library.root.path) {
+    shuffle.upcoming = None;
+  }
+  if shuffle.upcoming.is_none() {
+    let folder = folder(state);
+    start(state, folder);
+    return true;
+  }
+  play_upcoming(state);
+  true
+}
+
+// What a new pass goes over: the highlighted folder, or the one holding
+// what's playing, or failing both the whole library.
+fn folder(state: &mut App) -> Arc<Node> {
+  if let Some(node) = state.highlighted().filter(|node| node.is_dir()) {
+    return node;
+  }
+  let root = &state.library.root;
+  let playing = state
+    .last_played
+    .as_ref()
+    .and_then(|node| node.path.parent());
+  match playing {
+    Some(path) if path.starts_with(&root.path) => state.library.folder(path),
+    _ => root.clone(),
+  }
+}
+
+// Starts a pass over a folder, once its files have been read.
+fn start(state: &mut App, folder: Arc<Node>) {
+  if state.shuffle.mode == Mode::Off {
+    return;
+  }
+  let files = match state.all_files(&folder, start) {
+    Some(files) => files,
+    None => return,
+  };
+  let depth = state.library.config.shuffle_depth;
+  let shuffle = &mut state.shuffle;
+  if shuffle.root != folder.path {
+    shuffle.root = folder.path.clone();
+    shuffle.history.clear();
+  }
+  shuffle.upcoming = Some(order(&folder.path, files, shuffle.mode, depth));
+  play_upcoming(state);
+}
+
+fn play_upcoming(state: &mut App) {
+  let shuffle = &mut state.shuffle;
+  let upcoming = match shuffle.upcoming.as_mut() {
+    Some(upcoming) => upcoming,
+    None => return,
+  };
+
+  match upcoming.pop_front() {
+    Some(node) => {
+      shuffle.history.push(node.clone());
+      state.play_node(&node);
+    }
+    // the pass is over, the next one starts fresh
+    None => {
+      shuffle.upcoming = None;
+      match state.repeat.mode == repeat::Mode::All && !shuffle.history.is_empty() {
+        // over the same folder again
+        true => {
+          shuffle.history.clear();
+          let folder = state.library.folder(&shuffle.root);
+          start(state, folder);
+        }
+        false => state.pause(),
+      }
+    }
+  }
+}
+
+pub fn prev(state: &mut App) -> bool {
+  let shuffle = &mut state.shuffle;
+  if shuffle.mode == Mode::Off {
+    return false;
+  }
+
+  if shuffle.history.len() > 1 {
+    if let (Some(current), Some(upcoming)) = (shuffle.history.pop(), &mut shuffle.upcoming) {
+      upcoming.push_front(current);
+    }
+  }
+  if let Some(node) = shuffle.history.last().cloned() {
+    state.play_node(&node);
+  }
+  true
+}
+
+pub fn describe(state: &App) -> Option<String> {
+  match state.shuffle.mode {
+    Mode::Off => None,
+    Mode::Tracks => Some("Shuffle tracks".to_owned()),
+    Mode::Rated => Some("Shuffle by rating".to_owned()),
+    Mode::Albums => Some("Shuffle albums".to_owned()),
+    Mode::Folders => Some(format!(
+      "Shuffle folders (depth {})",
+      state.library.config.shuffle_depth
+    )),
+  }
+}
+
+fn order(root: &Path, files: Vec<Arc<Node>>, mode: Mode, depth: usize) -> VecDeque<Arc<Node>> {
+  let mut groups = match mode {
+    Mode::Off => vec![],
+    Mode::Tracks => files.into_iter().map(|file| vec![file]).collect(),
+    Mode::Rated => return weighted(files),
+    Mode::Albums => group(files, |file| file.path.parent().unwrap_or(root).to_owned()),
+    Mode::Folders => group(files, |file| folder_at(root, &file.path, depth)),
+  };
+  fastrand::shuffle(&mut groups);
+  groups.into_iter().flatten().collect()
+}
+
+// A random order where each track's chance of coming next is proportional
+// to its weight (Efraimidis-Spirakis: sort by u^(1/w)).
+fn weighted(files: Vec<Arc<Node>>) -> VecDeque<Arc<Node>> {
+  let mut keyed: Vec<(f64, Arc<Node>)> = files
+    .into_iter()
+    .map(|file| (fastrand::f64().powf(1. / ratings::weight(&file)), file))
+    .collect();
+  keyed.sort_by(|a, b| b.0.total_cmp(&a.0));
+  keyed.into_iter().map(|(_, file)| file).collect()
+}
+
+// Groups items by key, keeping the order they came in.
+fn group<T>(items: Vec<T>, key: impl Fn(&T) -> PathBuf) -> Vec<Vec<T>> {
+  let mut index = HashMap::new();
+  let mut groups: Vec<Vec<T>> = vec![];
+  for item in items {
+    let i = *index.entry(key(&item)).or_insert(groups.len());
+    match groups.get_mut(i) {
+      Some(group) => group.push(item),
+      None => groups.push(vec![item]),
+    }
+  }
+  groups
+}
+
+// The folder `depth` levels below root that holds a file, or the file's own
+// folder when it sits higher up than that.
+fn folder_at(root: &Path, path: &Path, depth: usize) -> PathBuf {
+  let parent = path.parent().unwrap_or(root);
+  let relative = parent.strip_prefix(root).unwrap_or(Path::new(""));
+  root.join(relative.components().take(depth).collect::<PathBuf>())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn passes_stay_in_the_highlighted_folder() {
+    let mut app = TestApp::new(&["a/1.mp3", "a/2.mp3", "b/3.mp3"]);
+    let root = app.root.clone();
+    app.selected = Some(app.row(&root.join("a")));
+    app.shuffle.mode = Mode::Tracks;
+
+    let mut played = vec![];
+    for _ in 0..2 {
+      assert!(next(&mut app));
+      played.push(app.last_played.as_ref().unwrap().path.clone());
+    }
+    // and that's the pass over
+    next(&mut app);
+    assert_eq!(app.last_played.as_ref().unwrap().path, played[1]);
+    played.sort();
+    assert_eq!(played, [root.join("a/1.mp3"), root.join("a/2.mp3")]);
+  }
+
+  #[test]
+  fn folders_group_at_depth() {
+    let root = Path::new("/music");
+    let paths: Vec<PathBuf> = [
+      "/music/a/one/1.mp3",
+      "/music/a/two/2.mp3",
+      "/music/b/3.mp3",
+      "/music/a/one/4.mp3",
+      "/music/5.mp3",
+    ]
+    .iter()
+    .map(PathBuf::from)
+    .collect();
+
+    let groups = group(paths.clone(), |p| folder_at(root, p, 1));
+    assert_eq!(groups.len(), 3);
+    assert_eq!(
+      groups[0],
+      vec![paths[0].clone(), paths[1].clone(), paths[3].clone()]
+    );
+    assert_eq!(groups[2], vec![paths[4].clone()]);
+
+    assert_eq!(group(paths, |p| folder_at(root, p, 2)).len(), 4);
+  }
+}
@@ -0,0 +1,438 @@
+use super::*;
+use crate::history::{self, Play};
+use crate::playlist::smart;
+use chrono::{Local, TimeZone, Timelike};
+use crossterm::event::KeyCode;
+use std::thread;
+use tui::{
+  layout::Rect,
+  style::{Color, Modifier, Style},
+  terminal::Frame,
+  text::Span,
+  widgets::{BarChart, Block, Borders, Paragraph, Row, Table},
+};
+
+// How many entries each top list shows.
+const TOP: usize = 10;
+
+#[derive(Clone, Copy, PartialEq, Default)]
+enum Period {
+  Week,
+  Month,
+  Year,
+  #[default]
+  All,
+}
+
+impl Period {
+  fn next(self) -> Self {
+    match self {
+      Period::Week => Period::Month,
+      Period::Month => Period::Year,
+      Period::Year => Period::All,
+      Period::All => Period::Week,
+    }
+  }
+
+  fn name(self) -> &'static str {
+    match self {
+      Period::Week => "last 7 days",
+      Period::Month => "last 30 days",
+      Period::Year => "last year",
+      Period::All => "all time",
+    }
+  }
+
+  fn seconds(self) -> Option<i64> {
+    match self {
+      Period::Week => Some(7 * 86400),
+      Period::Month => Some(30 * 86400),
+      Period::Year => Some(365 * 86400),
+      Period::All => None,
+    }
+  }
+}
+
+// Listening statistics from the history, and totals for the library, which
+// are counted in the background since every file has to be probed.
+#[derive(Default)]
+pub struct Stats {
+  period: Period,
+  plays: Vec<Play>,
+  tags: HashMap<PathBuf, Tags>,
+  summary: Summary,
+  totals: Arc<Mutex<Option<Totals>>>,
+  counted: PathBuf, // the folder totals are for
+}
+
+struct Tags {
+  title: String,
+  album: String,
+  artist: String,
+  folder: String,
+}
+
+#[derive(Default)]
+struct Summary {
+  listened: u64,
+  plays: usize,
+  skips: usize,
+  tracks: Vec<Ranked>,
+  albums: Vec<Ranked>,
+  artists: Vec<Ranked>,
+  folders: Vec<Ranked>,
+  hours: [u64; 24], // seconds listened by hour of the day
+}
+
+#[derive(Debug, PartialEq)]
+struct Ranked {
+  name: String,
+  plays: u32,
+  listened: u64,
+}
+
+#[derive(Default)]
+struct Totals {
+  files: usize,
+  duration: u64,
+  formats: Vec<(String, usize, u64)>, // (extension, files, bytes)
+}
+
+pub fn open_view(state: &mut App) {
+  let stats = &mut state.stats;
+  let config = &state.library.config;
+  stats.plays = history::all();
+  for play in &stats.plays {
+    if !stats.tags.contains_key(&play.path) {
+      stats
+        .tags
+        .insert(play.path.clone(), tags(&play.path, config));
+    }
+  }
+  summarise(stats);
+
+  // a count still going holds on to its totals, and is left to finish
+  let root = &state.library.root.path;
+  if Arc::strong_count(&stats.totals) == 1 || stats.counted != *root {
+    count_library(stats, root.clone(), config.clone());
+  }
+
+  state.focus = Focusable::Stats;
+}
+
+fn count_library(stats: &mut Stats, root: PathBuf, config: Arc<Config>) {
+  let totals = Arc::new(Mutex::new(None));
+  stats.totals = totals.clone();
+  stats.counted = root.clone();
+  thread::spawn(move || {
+    let mut counted = Totals::default();
+    let mut formats = HashMap::new();
+    count(
+      &Node::new(&root, &config),
+      &config,
+      &mut counted,
+      &mut formats,
+    );
+    counted.formats = formats
+      .into_iter()
+      .map(|(ext, (files, bytes))| (ext, files, bytes))
+      .collect();
+    counted
+      .formats
+      .sort_by_key(|format| std::cmp::Reverse(format.2));
+    *totals.lock() = Some(counted);
+  });
+}
+
+// Titles and groupings for a played file. Without tags, albums and artists
+// are taken to be the folder and the one above it.
+fn tags(path: &Path, config: &Config) -> Tags {
+  let node = Node::new(path, config);
+  let folder_name = |levels: usize| {
+    path
+      .ancestors()
+      .nth(levels)
+      .and_then(Path::file_name)
+      .map(|name| name.to_string_lossy().to_string())
+      .unwrap_or_default()
+  };
+  #[allow(unused_mut)]
+  let mut tags = Tags {
+    title: node.title().to_owned(),
+    album: folder_name(1),
+    artist: folder_name(2),
+    folder: path
+      .parent()
+      .map(|p| p.to_string_lossy().to_string())
+      .unwrap_or_default(),
+  };
+  #[cfg(feature = "metadata")]
+  if let Some(metadata) = &node.metadata {
+    if let Some(album) = &metadata.album {
+      tags.album = album.clone();
+    }
+    if let Some(artist) = &metadata.artist {
+      tags.artist = artist.clone();
+    }
+  }
+  tags
+}
+
+fn summarise(stats: &mut Stats) {
+  let since = stats
+    .period
+    .seconds()
+    .map_or(i64::MIN, |seconds| history::now() - seconds);
+  let plays: Vec<&Play> = stats.plays.iter().filter(|p| p.start >= since).collect();
+
+  let mut summary = Summary {
+    listened: plays.iter().map(|p| p.listened).sum(),
+    plays: plays.iter().filter(|p| !p.skipped).count(),
+    skips: plays.iter().filter(|p| p.skipped).count(),
+    ..Default::default()
+  };
+  for play in &plays {
+    if let Some(time) = Local.timestamp_opt(play.start, 0).single() {
+      summary.hours[time.hour() as usize] += play.listened;
+    }
+  }
+
+  let tags = &stats.tags;
+  let tag = |play: &Play, pick: fn(&Tags) -> &String| {
+    tags.get(&play.path).map(pick).cloned().unwrap_or_default()
+  };
+  let (album, artist, folder) = (
+    |p: &Play| tag(p, |t| &t.album),
+    |p: &Play| tag(p, |t| &t.artist),
+    |p: &Play| tag(p, |t| &t.folder),
+  );
+  // tracks by file, since titles like "Intro" turn up on many albums
+  summary.tracks = top(&plays, |p| p.path.clone(), |p| tag(p, |t| &t.title));
+  summary.albums = top(&plays, album, album);
+  summary.artists = top(&plays, artist, artist);
+  summary.folders = top(&plays, folder, folder);
+  stats.summary = summary;
+}
+
+// The most played, counted together by `key` and shown by name, by plays and
+// then time listened.
+fn top<K: std::hash::Hash + Eq>(
+  plays: &[&Play],
+  key: impl Fn(&Play) -> K,
+  name: impl Fn(&Play) -> String,
+) -> Vec<Ranked> {
+  let mut ranked: HashMap<K, Ranked> = HashMap::new();
+  for play in plays {
+    let name = name(play);
+    if name.is_empty() {
+      continue;
+    }
+    let entry = ranked.entry(key(play)).or_insert(Ranked {
+      name,
+      plays: 0,
+      listened: 0,
+    });
+    entry.plays += !play.skipped as u32;
+    entry.listened += play.listened;
+  }
+
+  let mut ranked: Vec<Ranked> = ranked.into_values().collect();
+  ranked.sort_by(|a, b| (b.plays, b.listened, &a.name).cmp(&(a.plays, a.listened, &b.name)));
+  ranked.truncate(TOP);
+  ranked
+}
+
+// Every file under a folder, leaving out playlists and smart playlists so
+// nothing's counted twice.
+fn count(
+  node: &Node,
+  config: &Config,
+  totals: &mut Totals,
+  formats: &mut HashMap<String, (usize, u64)>,
+) {
+  for folder in node.folders.iter().flatten() {
+    if !smart::is_smart(&folder.path) && !playlist::is_playlist(&folder.path) {
+      count(&Node::new(&folder.path, config), config, totals, formats);
+    }
+  }
+  for file in node.files.iter().flatten().filter(|f| f.missing == 0) {
+    let bytes = fs::metadata(&file.path).map_or(0, |m| m.len());
+    let ext = extension(&file.path).unwrap_or("?").to_lowercase();
+    let format = formats.entry(ext).or_default();
+    format.0 += 1;
+    format.1 += bytes;
+    totals.files += 1;
+    totals.duration += library::index::duration(&file.path).unwrap_or(0);
+  }
+}
+
+fn duration(seconds: u64) -> String {
+  match seconds / 3600 {
+    0 => format!("{}m {:0>2}s", seconds / 60, seconds % 60),
+    hours => format!("{}h {:0>2}m", hours, seconds / 60 % 60),
+  }
+}
+
+fn size(bytes: u64) -> String {
+  let mut size = bytes as f64;
+  for unit in ["B", "KiB", "MiB", "GiB"] {
+    if size < 1024. {
+      return format!("{:.1} {}", size, unit);
+    }
+    size /= 1024.;
+  }
+  format!("{:.1} TiB", size)
+}
+
+fn block(title: &str) -> Block<'_> {
+  Block::default()
+    .borders(Borders::ALL)
+    .border_style(Style::default().fg(Color::Blue))
+    .title(title)
+}
+
+fn ranked_table<'a>(title: &'a str, ranked: &'a [Ranked], widths: &'a [Constraint]) -> Table<'a> {
+  let rows = ranked.iter().map(|r| {
+    Row::new(vec![
+      r.name.clone(),
+      r.plays.to_string(),
+      duration(r.listened),
+    ])
+  });
+  Table::new(rows)
+    .header(Row::new(vec!["", "plays", "time"]).style(Style::default().fg(Color::DarkGray)))
+    .block(block(title))
+    .widths(widths)
+}
+
+pub fn render<B: Backend>(state: &mut App, area: Rect, frame: &mut Frame<B>) {
+  let stats = &state.stats;
+  let summary = &stats.summary;
+
+  let rows = Layout::default()
+    .direction(Direction::Vertical)
+    .constraints([
+      Constraint::Length(2),
+      Constraint::Min(6),
+      Constraint::Length(12),
+    ])
+    .split(area);
+
+  let title = format!("Stats: {} (p: period, Esc: back)", stats.period.name());
+  let header = Paragraph::new(format!(
+    "Listened {} · {} plays · {} skipped",
+    duration(summary.listened),
+    summary.plays,
+    summary.skips
+  ))
+  .block(
+    Block::default().title(Span::styled(
+      format!("{:width$}", title, width = area.width as usize),
+      Style::default()
+        .bg(Color::Blue)
+        .add_modifier(Modifier::BOLD),
+    )),
+  );
+  frame.render_widget(header, rows[0]);
+
+  let columns = Layout::default()
+    .direction(Direction::Horizontal)
+    .constraints([Constraint::Ratio(1, 4); 4])
+    .split(rows[1]);
+  let widths = [
+    Constraint::Percentage(60),
+    Constraint::Length(5),
+    Constraint::Length(8),
+  ];
+  let tops = [
+    ("Tracks", &summary.tracks),
+    ("Albums", &summary.albums),
+    ("Artists", &summary.artists),
+    ("Folders", &summary.folders),
+  ];
+  for ((title, ranked), column) in tops.into_iter().zip(columns) {
+    frame.render_widget(ranked_table(title, ranked, &widths), column);
+  }
+
+  let bottom = Layout::default()
+    .direction(Direction::Horizontal)
+    .constraints([Constraint::Percentage(60), Constraint::Percentage(40)])
+    .split(rows[2]);
+
+  let labels: Vec<String> = (0..24).map(|hour| format!("{:0>2}", hour)).collect();
+  let minutes: Vec<(&str, u64)> = labels
+    .iter()
+    .zip(summary.hours)
+    .map(|(label, seconds)| (label.as_str(), seconds / 60))
+    .collect();
+  let bar_width = (bottom[0].width.saturating_sub(2) / 24)
+    .saturating_sub(1)
+    .max(1);
+  let chart = BarChart::default()
+    .block(block("Minutes by hour"))
+    .data(&minutes)
+    .bar_width(bar_width)
+    .bar_gap(1)
+    .bar_style(Style::default().fg(Color::Blue))
+    .value_style(Style::default().bg(Color::Blue).fg(Color::Black));
+  frame.render_widget(chart, bottom[0]);
+
+  let totals = stats.totals.lock();
+  let rows: Vec<Row> = match &*totals {
+    None => vec![Row::new(vec!["Counting…"])],
+    Some(totals) => [
+      Row::new(vec!["Files".to_owned(), totals.files.to_string()]),
+      Row::new(vec!["Duration".to_owned(), duration(totals.duration)]),
+    ]
+    .into_iter()
+    .chain(totals.formats.iter().map(|(ext, files, bytes)| {
+      Row::new(vec![ext.clone(), format!("{} · {}", files, size(*bytes))])
+    }))
+    .collect(),
+  };
+  let widths = [Constraint::Length(10), Constraint::Min(10)];
+  let table = Table::new(rows).block(block("Library")).widths(&widths);
+  frame.render_widget(table, bottom[1]);
+}
+
+pub fn handle_input(state: &mut App, key: &KeyEvent) {
+  match key.code {
+    KeyCode::Char('p') => {
+      state.stats.period = state.stats.period.next();
+      summarise(&mut state.stats);
+    }
+    KeyCode::Char('S') | KeyCode::Esc => state.focus = Focusable::FileList,
+    _ => {}
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn top_ranks_by_plays_then_time() {
+    let play = |name: &str, listened, skipped| Play {
+      start: 0,
+      path: PathBuf::from(name),
+      listened,
+      skipped,
+    };
+    let plays = [
+      play("a", 100, false),
+      play("b", 50, false),
+      play("b", 60, false),
+      play("c", 300, false),
+      play("c", 10, true),
+    ];
+    let plays: Vec<&Play> = plays.iter().collect();
+    let name = |p: &Play| p.path.to_string_lossy().to_string();
+    let ranked = top(&plays, name, name);
+
+    let names: Vec<&str> = ranked.iter().map(|r| r.name.as_str()).collect();
+    assert_eq!(names, ["b", "c", "a"]);
+    // skips add to the time but aren't plays
+    assert_eq!(ranked[1].plays, 1);
+    assert_eq!(ranked[1].listened, 310);
+  }
+}
@@ -0,0 +1,488 @@
+use super::*;
+use crate::metadata::{write_metadata, Metadata};
+use crossterm::event::KeyCode;
+use tui::{
+  layout::Rect,
+  style::{Color, Modifier, Style},
+  terminal::Frame,
+  text::{Span, Spans},
+  widgets::{Block, Borders, List, ListItem, ListState},
+};
+
+// The tags the editor offers, in the form's order.
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Field {
+  Title,
+  Artist,
+  Album,
+  AlbumArtist,
+  Track,
+  TrackTotal,
+  Disc,
+  DiscTotal,
+  Date,
+  Genre,
+  Composer,
+  Comment,
+}
+
+const FIELDS: [Field; 12] = [
+  Field::Title,
+  Field::Artist,
+  Field::Album,
+  Field::AlbumArtist,
+  Field::Track,
+  Field::TrackTotal,
+  Field::Disc,
+  Field::DiscTotal,
+  Field::Date,
+  Field::Genre,
+  Field::Composer,
+  Field::Comment,
+];
+
+impl Field {
+  fn label(self) -> &'static str {
+    match self {
+      Field::Title => "Title",
+      Field::Artist => "Artist",
+      Field::Album => "Album",
+      Field::AlbumArtist => "Album artist",
+      Field::Track => "Track",
+      Field::TrackTotal => "Tracks",
+      Field::Disc => "Disc",
+      Field::DiscTotal => "Discs",
+      Field::Date => "Date",
+      Field::Genre => "Genre",
+      Field::Composer => "Composer",
+      Field::Comment => "Comment",
+    }
+  }
+
+  // `%t` and so on in filename patterns
+  fn placeholder(c: char) -> Option<Self> {
+    Some(match c {
+      't' => Field::Title,
+      'a' => Field::Artist,
+      'b' => Field::Album,
+      'A' => Field::AlbumArtist,
+      'n' => Field::Track,
+      'N' => Field::TrackTotal,
+      'd' => Field::Disc,
+      'y' => Field::Date,
+      'g' => Field::Genre,
+      'c' => Field::Composer,
+      _ => return None,
+    })
+  }
+
+  fn get(self, metadata: &Metadata) -> Option<String> {
+    let number = |n: Option<u16>| n.map(|n| n.to_string());
+    match self {
+      Field::Title => metadata.title.clone(),
+      Field::Artist => metadata.artist.clone(),
+      Field::Album => metadata.album.clone(),
+      Field::AlbumArtist => metadata.album_artist.clone(),
+      Field::Track => number(metadata.track_number),
+      Field::TrackTotal => number(metadata.track_total),
+      Field::Disc => number(metadata.disc_number),
+      Field::DiscTotal => number(metadata.disc_total),
+      Field::Date => metadata.date.clone(),
+      Field::Genre => metadata.genre.clone(),
+      Field::Composer => metadata.composer.clone(),
+      Field::Comment => metadata.comment.clone(),
+    }
+  }
+
+  // Empty removes the tag, as do numbers that don't parse.
+  fn set(self, metadata: &mut Metadata, value: &str) {
+    let value = value.trim();
+    let text = Some(value.to_owned()).filter(|v| !v.is_empty());
+    let number = value.parse().ok();
+    match self {
+      Field::Title => metadata.title = text,
+      Field::Artist => metadata.artist = text,
+      Field::Album => metadata.album = text,
+      Field::AlbumArtist => metadata.album_artist = text,
+      Field::Track => metadata.track_number = number,
+      Field::TrackTotal => metadata.track_total = number,
+      Field::Disc => metadata.disc_number = number,
+      Field::DiscTotal => metadata.disc_total = number,
+      Field::Date => metadata.date = text,
+      Field::Genre => metadata.genre = text,
+      Field::Composer => metadata.composer = text,
+      Field::Comment => metadata.comment = text,
+    }
+  }
+}
+
+struct Entry {
+  field: Field,
+  value: String,
+  mixed: bool,   // the files have different values, which are kept unless edited
+  changed: bool, // edited, so written to every file
+}
+
+// Edits the tags of one file, or every file in a folder at once. Written
+// tags can be put back, a batch at a time.
+#[derive(Default)]
+pub struct TagEditor {
+  files: Vec<(PathBuf, Metadata)>, // in folder order, with their tags as read
+  entries: Vec<Entry>,
+  pattern: String, // fills in tags from each file's name, like "%n - %t"
+  numbering: bool, // numbers tracks in folder order
+  selected: usize, // an entry, or the pattern after them
+  title: String,
+  error: Option<String>,
+  undo: Vec<(PathBuf, Metadata)>, // how the last batch was before writing
+}
+
+// Opens the editor for the highlighted file, or every file in the
+// highlighted folder.
+pub fn open(state: &mut App) {
+  if let Some(node) = state.highlighted() {
+    open_node(state, node);
+  }
+}
+
+fn open_node(state: &mut App, node: Arc<Node>) {
+  let (title, nodes) = match node.is_dir() {
+    true => {
+      let files = match state.all_files(&node, open_node) {
+        Some(files) => files,
+        None => return,
+      };
+      (format!("{} files in {}", files.len(), node.title()), files)
+    }
+    false => (node.title().to_owned(), vec![node]),
+  };
+  if nodes.is_empty() {
+    return;
+  }
+
+  let editor = &mut state.tag_editor;
+  editor.files = nodes
+    .iter()
+    .map(|node| {
+      // only files the tagger hasn't got to yet are read here
+      let tags = match node.tagged {
+        true => node.metadata.clone(),
+        false => library::index::metadata(&node.path),
+      };
+      (node.path.clone(), tags.unwrap_or_default())
+    })
+    .collect();
+  editor.entries = FIELDS
+    .iter()
+    .map(|&field| {
+      let mut values = editor.files.iter().map(|(_, m)| field.get(m));
+      let first = values.next().flatten();
+      let mixed = values.any(|value| value != first);
+      Entry {
+        field,
+        value: match mixed {
+          true => String::new(),
+          false => first.unwrap_or_default(),
+        },
+        mixed,
+        changed: false,
+      }
+    })
+    .collect();
+  editor.pattern = String::new();
+  editor.numbering = false;
+  editor.selected = 0;
+  editor.title = title;
+  editor.error = None;
+  state.focus = Focusable::TagEditor;
+}
+
+impl TagEditor {
+  // A file's tags as the form would write them: fields typed in, then
+  // what the pattern finds in its name, then the numbering.
+  fn edited(&self, index: usize) -> Metadata {
+    let (path, before) = &self.files[index];
+    let mut metadata = before.clone();
+    for entry in self.entries.iter().filter(|e| e.changed) {
+      entry.field.set(&mut metadata, &entry.value);
+    }
+
+    let name = path.file_stem().unwrap_or_default().to_string_lossy();
+    if let Some(fields) = from_filename(&self.pattern, &name) {
+      for (field, value) in fields {
+        field.set(&mut metadata, &value);
+      }
+    }
+
+    if self.numbering {
+      metadata.track_number = Some(index as u16 + 1);
+      metadata.track_total = Some(self.files.len() as u16);
+    }
+    metadata
+  }
+}
+
+// Splits a file name by a pattern like "%n - %t", giving each placeholder's
+// tag the text between the ones around it. `%%` is a literal %.
+fn from_filename(pattern: &str, name: &str) -> Option<Vec<(Field, String)>> {
+  enum Token {
+    Field(Field),
+    Text(String),
+  }
+  let mut tokens = vec![];
+  let mut chars = pattern.chars().peekable();
+  while let Some(c) = chars.next() {
+    let field = match c {
+      '%' => chars.peek().and_then(|&c| Field::placeholder(c)),
+      _ => None,
+    };
+    match field {
+      Some(field) => {
+        chars.next();
+        tokens.push(Token::Field(field));
+      }
+      None => {
+        if c == '%' && chars.peek() == Some(&'%') {
+          chars.next();
+        }
+        match tokens.last_mut() {
+          Some(Token::Text(text)) => text.push(c),
+          _ => tokens.push(Token::Text(c.to_string())),
+        }
+      }
+    }
+  }
+  if tokens.is_empty() {
+    return None;
+  }
+
+  let mut fields = vec![];
+  let mut rest = name;
+  let mut tokens = tokens.iter().peekable();
+  while let Some(token) = tokens.next() {
+    match token {
+      Token::Text(text) => rest = rest.strip_prefix(text.as_str())?,
+      Token::Field(field) => {
+        let end = match tokens.peek() {
+          Some(Token::Text(next)) => rest.find(next.as_str())?,
+          // two placeholders in a row can't be told apart
+          Some(Token::Field(_)) => return None,
+          None => rest.len(),
+        };
+        fields.push((*field, rest[..end].trim().to_owned()));
+        rest = &rest[end..];
+      }
+    }
+  }
+  match rest.is_empty() {
+    true => Some(fields),
+    false => None,
+  }
+}
+
+fn write(state: &mut App) {
+  let editor = &mut state.tag_editor;
+  let mut written = vec![];
+  let mut failed = vec![];
+  for index in 0..editor.files.len() {
+    let metadata = editor.edited(index);
+    let (path, before) = &editor.files[index];
+    if metadata == *before {
+      continue;
+    }
+    match write_metadata(path, &metadata) {
+      Ok(()) => written.push((path.clone(), before.clone())),
+      Err(error) => failed.push(error),
+    }
+  }
+
+  if !written.is_empty() {
+    reread(state, &written);
+    state.tag_editor.undo = written;
+  }
+  match failed.first() {
+    Some(error) => {
+      state.tag_editor.error = Some(format!("{} not written: {}", failed.len(), error));
+    }
+    None => state.focus = Focusable::FileList,
+  }
+}
+
+// Puts back the tags from before the last batch was written.
+pub fn undo(state: &mut App) {
+  let undo = std::mem::take(&mut state.tag_editor.undo);
+  for (path, metadata) in &undo {
+    let _ = write_metadata(path, metadata);
+  }
+  reread(state, &undo);
+  state.focus = Focusable::FileList;
+}
+
+// Has the library read the written files' tags again.
+fn reread(state: &mut App, files: &[(PathBuf, Metadata)]) {
+  let folders: HashSet<&Path> = files.iter().filter_map(|(path, _)| path.parent()).collect();
+  for folder in folders {
+    state.library.invalidate(folder);
+  }
+  state.library.rebuild();
+}
+
+pub fn select_delta(state: &mut App, delta: i64) {
+  let editor = &mut state.tag_editor;
+  let last = editor.entries.len() as i64; // the pattern
+  editor.selected = (editor.selected as i64 + delta).clamp(0, last) as usize;
+}
+
+pub fn render<B: Backend>(state: &mut App, area: Rect, frame: &mut Frame<B>) {
+  let editor = &state.tag_editor;
+  let label =
+    |text: &str| Span::styled(format!("{:>14}  ", text), Style::default().fg(Color::Cyan));
+  let dim = Style::default().fg(Color::DarkGray);
+
+  let mut items: Vec<ListItem> = editor
+    .entries
+    .iter()
+    .map(|entry| {
+      let value = match entry {
+        Entry {
+          field: Field::Track,
+          ..
+        } if editor.numbering => Span::styled(
+          format!("1 to {} in folder order", editor.files.len()),
+          Style::default().fg(Color::Green),
+        ),
+        Entry {
+          mixed: true,
+          changed: false,
+          ..
+        } => Span::styled("(various)", dim),
+        entry => Span::from(entry.value.clone()),
+      };
+      ListItem::new(Spans::from(vec![label(entry.field.label()), value]))
+    })
+    .collect();
+  items.push(ListItem::new(Spans::from(vec![
+    label("From filename"),
+    match editor.pattern.is_empty() {
+      true => Span::styled("%n - %t, %a - %b - %t, ...", dim),
+      false => Span::from(editor.pattern.clone()),
+    },
+  ])));
+  items.push(ListItem::new(""));
+  if let Some(error) = &editor.error {
+    items.push(ListItem::new(Span::styled(
+      error.clone(),
+      Style::default().fg(Color::Red),
+    )));
+  }
+  items.push(ListItem::new(Span::styled(
+    "Enter write · Esc close · ^T number tracks · ^Z undo last write",
+    dim,
+  )));
+
+  let title = format!("Tags: {}", editor.title);
+  let list = List::new(items)
+    .block(
+      Block::default().borders(Borders::RIGHT).title(Span::styled(
+        format!("{:width$}", title, width = area.width as usize),
+        Style::default()
+          .bg(Color::Blue)
+          .add_modifier(Modifier::BOLD),
+      )),
+    )
+    .highlight_style(
+      Style::default()
+        .bg(Color::LightGreen)
+        .fg(Color::Black)
+        .add_modifier(Modifier::BOLD),
+    );
+
+  let mut list_state = ListState::default();
+  list_state.select(Some(editor.selected));
+  frame.render_stateful_widget(list, area, &mut list_state);
+}
+
+pub fn handle_input(state: &mut App, key: &KeyEvent) {
+  let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+  let editor = &mut state.tag_editor;
+
+  match key.code {
+    KeyCode::Char('t') if ctrl => editor.numbering = !editor.numbering,
+    KeyCode::Char('z') if ctrl => undo(state),
+    KeyCode::Char(c) if !ctrl => edit(editor, |value| value.push(c)),
+    KeyCode::Backspace => edit(editor, |value| {
+      value.pop();
+    }),
+    KeyCode::Tab => select_delta(state, 1),
+    KeyCode::BackTab => select_delta(state, -1),
+    KeyCode::Enter => write(state),
+    KeyCode::Esc => state.focus = Focusable::FileList,
+    _ => {}
+  }
+}
+
+// What's typed goes into the selected entry, or the pattern after them.
+fn edit(editor: &mut TagEditor, edit: impl FnOnce(&mut String)) {
+  match editor.entries.get_mut(editor.selected) {
+    Some(entry) => {
+      edit(&mut entry.value);
+      entry.changed = true;
+    }
+    None => edit(&mut editor.pattern),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn filename_patterns_split_names() {
+    let fields = from_filename("%n - %t", "03 - Some Song").unwrap();
+    assert_eq!(
+      fields,
+      [
+        (Field::Track, "03".to_owned()),
+        (Field::Title, "Some Song".to_owned())
+      ]
+    );
+    let fields = from_filename("%a - %b - %t", "A - B - C - D").unwrap();
+    assert_eq!(fields[2], (Field::Title, "C - D".to_owned()));
+    assert!(from_filename("%n - %t", "no separator").is_none());
+    assert!(from_filename("%n%t", "03 Song").is_none());
+  }
+
+  #[test]
+  fn edits_apply_in_order() {
+    let file = |name: &str, title: &str| {
+      let metadata = Metadata {
+        title: Some(title.to_owned()),
+        album: Some("Old".to_owned()),
+        ..Default::default()
+      };
+      (PathBuf::from(name), metadata)
+    };
+    let entry = |field, value: &str| Entry {
+      field,
+      value: value.to_owned(),
+      mixed: false,
+      changed: true,
+    };
+    let editor = TagEditor {
+      files: vec![file("b/01 - One.mp3", "x"), file("b/02 - Two.mp3", "y")],
+      entries: vec![entry(Field::Album, "New"), entry(Field::Title, "typed")],
+      pattern: "%n - %t".to_owned(),
+      numbering: true,
+      ..Default::default()
+    };
+
+    let second = editor.edited(1);
+    assert_eq!(second.album.as_deref(), Some("New"));
+    // the name wins over what's typed, and numbering over the name
+    assert_eq!(second.title.as_deref(), Some("Two"));
+    assert_eq!(
+      (second.track_number, second.track_total),
+      (Some(2), Some(2))
+    );
+  }
+}
@@ -0,0 +1,345 @@
+use super::*;
+use chrono::{Local, NaiveTime, TimeZone};
+use serde_derive::{Deserialize, Serialize};
+
+// alarms and sleep deadlines that were missed by more than this (e.g. the app was closed) are dropped
+const STALE_SECS: i64 = 60;
+
+#[derive(Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct Timers {
+  pub sleep: Option<Sleep>,
+  pub alarm: Option<Alarm>,
+  pub fade_secs: u64,
+
+  #[serde(skip)]
+  fading_in: Option<Instant>,
+}
+
+impl Default for Timers {
+  fn default() -> Self {
+    Self {
+      sleep: None,
+      alarm: None,
+      fade_secs: 30,
+      fading_in: None,
+    }
+  }
+}
+
+#[derive(Deserialize, Serialize, Clone, PartialEq, Debug)]
+pub enum Sleep {
+  At(i64), // unix timestamp
+  EndOfTrack,
+  EndOfFolder,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+pub struct Alarm {
+  pub at: i64, // unix timestamp
+  pub path: PathBuf,
+}
+
+fn now() -> i64 {
+  Local::now().timestamp()
+}
+
+fn clock(timestamp: i64) -> String {
+  match Local.timestamp_opt(timestamp, 0).single() {
+    Some(time) => time.format("%H:%M").to_string(),
+    None => String::new(),
+  }
+}
+
+fn save(state: &App) {
+  let mut meta = Meta::load().unwrap_or_default();
+  meta.timers = state.timers.clone();
+  let _ = meta.save();
+}
+
+pub fn update(state: &mut App) {
+  let now = now();
+  let fade = state.timers.fade_secs.max(1) as f32;
+
+  if let Some(alarm) = state.timers.alarm.clone() {
+    if now >= alarm.at {
+      state.timers.alarm = None;
+      if now - alarm.at < STALE_SECS {
+        ring(state, &alarm.path);
+      }
+      save(state);
+    }
+  }
+
+  if let Some(started) = state.timers.fading_in {
+    let volume = started.elapsed().as_secs_f32() / fade;
+    if volume >= 1. {
+      state.timers.fading_in = None;
+    }
+    state.backend.set_volume(volume.min(1.));
+  }
+
+  let remaining = match state.timers.sleep {
+    Some(Sleep::At(at)) if now >= at => {
+      if now - at < STALE_SECS {
+        state.pause();
+      }
+      finish_sleep(state);
+      return;
+    }
+    Some(Sleep::At(at)) => Some((at - now) as f32),
+    Some(Sleep::EndOfTrack) => Some(track_remaining(state)),
+    Some(Sleep::EndOfFolder) if last_in_folder(state) => Some(track_remaining(state)),
+    _ => None,
+  };
+
+  if let Some(remaining) = remaining {
+    if remaining < fade && !state.backend.is_paused() {
+      state.backend.set_volume(remaining / fade);
+    }
+  }
+}
+
+// Starts the alarm quietly to fade in, as long as it did start: the path may
+// have gone, or be outside the root by now.
+fn ring(state: &mut App, path: &Path) {
+  state.backend.set_volume(0.);
+  state.play_path(path);
+  let playing = state
+    .last_played
+    .as_ref()
+    .map(|node| node.path.starts_with(path));
+  match playing == Some(true) && !state.backend.is_paused() {
+    true => state.timers.fading_in = Some(Instant::now()),
+    false => state.backend.set_volume(1.),
+  }
+}
+
+fn track_remaining(state: &App) -> f32 {
+  let (_, pos, dur) = state.progress;
+  match dur {
+    0 => f32::MAX,
+    _ => dur.saturating_sub(pos) as f32,
+  }
+}
+
+fn last_in_folder(state: &App) -> bool {
+  let list = state.library.file_list();
+  let current = match list.get(state.play_index) {
+    Some((node, _)) => node,
+    None => return true,
+  };
+  match list.get(state.play_index + 1) {
+    Some((next, _)) => next.path.parent() != current.path.parent(),
+    None => true,
+  }
+}
+
+fn finish_sleep(state: &mut App) {
+  state.timers.sleep = None;
+  state.backend.set_volume(1.);
+  save(state);
+}
+
+// Called when a track finishes. Returns true if playback should stop instead of continuing.
+pub fn sleep_on_finish(state: &mut App) -> bool {
+  let sleep = match state.timers.sleep {
+    Some(Sleep::EndOfTrack) => true,
+    Some(Sleep::EndOfFolder) => last_in_folder(state),
+    _ => false,
+  };
+
+  if sleep {
+    state.backend.stop();
+    let _ = state.status_tx.send(PlaybackStatus::Paused);
+    finish_sleep(state);
+  }
+  sleep
+}
+
+#[derive(PartialEq, Debug)]
+enum SleepCmd {
+  Set(Option<Sleep>), // None turns it off
+  Fade(u64),
+}
+
+// Parses the sleep prompt: `<minutes>`, `track`, `folder`, `fade <seconds>` or `off`.
+fn parse_sleep(input: &str, now: i64) -> Option<SleepCmd> {
+  let mut args = input.split_whitespace();
+  Some(match (args.next(), args.next()) {
+    (Some("fade"), Some(secs)) => SleepCmd::Fade(secs.parse().ok()?),
+    (None, _) | (Some("off"), _) => SleepCmd::Set(None),
+    (Some("track"), _) => SleepCmd::Set(Some(Sleep::EndOfTrack)),
+    (Some("folder"), _) => SleepCmd::Set(Some(Sleep::EndOfFolder)),
+    (Some(minutes), _) => {
+      let minutes = minutes.parse::<u64>().ok()?;
+      SleepCmd::Set(Some(Sleep::At(now + minutes as i64 * 60)))
+    }
+  })
+}
+
+pub fn process_sleep_cmd(state: &mut App, input: &str) {
+  match parse_sleep(input, now()) {
+    Some(SleepCmd::Fade(secs)) => state.timers.fade_secs = secs,
+    Some(SleepCmd::Set(sleep)) => {
+      if sleep.is_none() {
+        state.backend.set_volume(1.);
+      }
+      state.timers.sleep = sleep;
+    }
+    None => return,
+  }
+  save(state);
+}
+
+// Parses the alarm prompt: `<HH:MM> [path]` or `off`.
+// Without a path the alarm plays whatever is highlighted.
+pub fn process_alarm_cmd(state: &mut App, input: &str) {
+  let input = input.trim();
+  let (time, path) = match input.split_once(' ') {
+    Some((time, path)) => (time, Some(PathBuf::from(path.trim()))),
+    None => (input, None),
+  };
+
+  if time.is_empty() || time == "off" {
+    state.timers.alarm = None;
+    save(state);
+    return;
+  }
+
+  let path = match path.or_else(|| state.highlighted().map(|n| n.path.clone())) {
+    Some(path) => path,
+    None => return,
+  };
+  let at = match next_occurrence(time) {
+    Some(at) => at,
+    None => return,
+  };
+
+  state.timers.alarm = Some(Alarm { at, path });
+  save(state);
+}
+
+fn next_occurrence(time: &str) -> Option<i64> {
+  let time = NaiveTime::parse_from_str(time, "%H:%M").ok()?;
+  let now = Local::now();
+  let mut at = now.date_naive().and_time(time);
+  if at <= now.naive_local() {
+    at += chrono::Duration::days(1);
+  }
+  Some(Local.from_local_datetime(&at).earliest()?.timestamp())
+}
+
+// Pending timers, shown in the status area.
+pub fn describe(state: &App) -> Option<String> {
+  let mut parts = vec![];
+
+  match state.timers.sleep {
+    Some(Sleep::At(at)) => {
+      let remaining = (at - now()).max(0);
+      parts.push(format!(
+        "Sleep in {}:{:0>2}",
+        remaining / 60,
+        remaining % 60
+      ));
+    }
+    Some(Sleep::EndOfTrack) => parts.push("Sleep after track".to_owned()),
+    Some(Sleep::EndOfFolder) => parts.push("Sleep after folder".to_owned()),
+    None => {}
+  }
+
+  if let Some(alarm) = &state.timers.alarm {
+    let name = alarm.path.file_name().unwrap_or_default().to_string_lossy();
+    parts.push(format!("Alarm {} ({})", clock(alarm.at), name));
+  }
+
+  match parts.is_empty() {
+    true => None,
+    false => Some(format!(
+      "{} · fade {}s",
+      parts.join(" · "),
+      state.timers.fade_secs
+    )),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::backends::Silent;
+
+  // An app on a folder holding one file, and the volume it's set to.
+  fn app() -> (TestApp, Arc<Mutex<f32>>) {
+    let volume = Arc::new(Mutex::new(1.));
+    let backend = Silent {
+      volume: volume.clone(),
+      ..Default::default()
+    };
+    (TestApp::with_backend(&["1.mp3"], backend), volume)
+  }
+
+  #[test]
+  fn sleep_prompts_parse() {
+    assert_eq!(
+      parse_sleep("30", 100),
+      Some(SleepCmd::Set(Some(Sleep::At(1900))))
+    );
+    assert_eq!(
+      parse_sleep(" track ", 0),
+      Some(SleepCmd::Set(Some(Sleep::EndOfTrack)))
+    );
+    assert_eq!(
+      parse_sleep("folder", 0),
+      Some(SleepCmd::Set(Some(Sleep::EndOfFolder)))
+    );
+    assert_eq!(parse_sleep("fade 20", 0), Some(SleepCmd::Fade(20)));
+    assert_eq!(parse_sleep("off", 0), Some(SleepCmd::Set(None)));
+    assert_eq!(parse_sleep("", 0), Some(SleepCmd::Set(None)));
+    assert_eq!(parse_sleep("fade", 0), None);
+    assert_eq!(parse_sleep("-5", 0), None);
+  }
+
+  #[test]
+  fn alarms_go_off_within_a_day() {
+    let at = next_occurrence("07:30").unwrap();
+    assert_eq!(clock(at), "07:30");
+    assert!(at > now() && at <= now() + 86400);
+    assert_eq!(next_occurrence("25:00"), None);
+    assert_eq!(next_occurrence("7.30"), None);
+  }
+
+  #[test]
+  fn alarms_fade_in_once_playing() {
+    let (mut app, volume) = app();
+    let root = app.root.clone();
+
+    // nothing to play outside the root, so nothing to fade
+    ring(&mut app, Path::new("/elsewhere/1.mp3"));
+    assert!(app.timers.fading_in.is_none());
+    assert_eq!(*volume.lock(), 1.);
+
+    ring(&mut app, &root.join("1.mp3"));
+    assert!(app.timers.fading_in.is_some());
+    assert_eq!(*volume.lock(), 0.);
+
+    // half way through the fade, half way up
+    app.timers.fade_secs = 30;
+    app.timers.fading_in = Some(Instant::now() - Duration::from_secs(15));
+    update(&mut app);
+    assert!((*volume.lock() - 0.5).abs() < 0.05);
+
+    app.timers.fading_in = Some(Instant::now() - Duration::from_secs(30));
+    update(&mut app);
+    assert!(app.timers.fading_in.is_none());
+    assert_eq!(*volume.lock(), 1.);
+  }
+
+  #[test]
+  fn sleep_fades_out_towards_the_end() {
+    let (mut app, volume) = app();
+    app.timers.fade_secs = 30;
+    app.timers.sleep = Some(Sleep::At(now() + 15));
+    update(&mut app);
+    assert!((*volume.lock() - 0.5).abs() < 0.05);
+  }
+}
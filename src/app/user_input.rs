@@ -15,7 +15,11 @@ pub fn render<'a, B: Backend>(state: &'a mut App, area: Rect, frame: &mut Frame<
       .border_style(Style::default().fg(Color::Blue))
       .title(match state.focus {
         Focusable::Dir => "Change Directory",
-        Focusable::Search => "Search",
+        Focusable::Search => "Search (*N for N stars up, *fav for favourites)",
+        Focusable::Sleep => "Sleep Timer (minutes, track, folder, fade <secs>, off)",
+        Focusable::Alarm => "Alarm (HH:MM [path], off)",
+        Focusable::SavePlaylist => "Save Playlist (path from the root folder)",
+        Focusable::PlaylistName => "Playlist Name",
         _ => "",
       }),
   );
@@ -23,7 +27,13 @@ pub fn render<'a, B: Backend>(state: &'a mut App, area: Rect, frame: &mut Frame<
 }
 
 pub fn handle_input<'a>(state: &'a mut App, key: &KeyEvent) {
+  let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+
   match key.code {
+    // add a search result to the target playlist
+    KeyCode::Char('l') if ctrl && state.focus == Focusable::Search => {
+      playlists::add_highlighted(state);
+    }
     KeyCode::Backspace => {
       state.input.pop();
     }
@@ -33,7 +43,7 @@ pub fn handle_input<'a>(state: &'a mut App, key: &KeyEvent) {
     KeyCode::Char(c) => {
       state.input.push(c);
     }
-    KeyCode::Esc => state.focus = Focusable::FileList,
+    KeyCode::Esc => back(state),
     _ => {}
   }
 
@@ -46,10 +56,7 @@ pub fn handle_input<'a>(state: &'a mut App, key: &KeyEvent) {
 pub fn process_cmd<'a>(state: &'a mut App) {
   match state.focus {
     Focusable::Dir => {
-      let mut input = state.input.clone();
-      if let Some(home_dir) = dirs::home_dir() {
-        input = input.replace('~', &home_dir.display().to_string());
-      }
+      let input = expand_home(&state.input);
       let input_str = input.as_str().trim();
 
       let path = match (input_str, &state.library.root) {
@@ -77,9 +84,40 @@ pub fn process_cmd<'a>(state: &'a mut App) {
         state.play_path(&node.path);
       }
     }
+    Focusable::Sleep => {
+      let input = state.input.clone();
+      timer::process_sleep_cmd(state, &input);
+    }
+    Focusable::Alarm => {
+      let input = expand_home(&state.input);
+      timer::process_alarm_cmd(state, &input);
+    }
+    Focusable::SavePlaylist => {
+      let input = expand_home(&state.input);
+      save_playlist::process(state, &input);
+    }
+    Focusable::PlaylistName => {
+      let input = state.input.clone();
+      playlists::process_name(state, &input);
+    }
     _ => {}
   }
 
   state.input = String::new();
-  state.focus = Focusable::FileList;
+  back(state);
+}
+
+// Prompts return to where they were opened from.
+fn back(state: &mut App) {
+  state.focus = match state.focus {
+    Focusable::PlaylistName => Focusable::Playlists,
+    _ => Focusable::FileList,
+  };
+}
+
+fn expand_home(input: &str) -> String {
+  match dirs::home_dir() {
+    Some(home_dir) => input.replace('~', &home_dir.display().to_string()),
+    None => input.to_owned(),
+  }
 }
@@ -0,0 +1,288 @@
+use crate::*;
+use flate2::read::DeflateDecoder;
+use std::{
+  io::{self, Read, Seek, SeekFrom},
+  sync::OnceLock,
+  time::SystemTime,
+};
+use zip::CompressionMethod;
+
+// Archives are shown as folders, and the files inside them get paths as if
+// the archive was one: `/music/pack.zip/drums/kick.wav`.
+const ARCHIVES: &[&str] = &["zip", "tar"];
+
+#[derive(Clone, Copy)]
+enum Method {
+  Stored,
+  Deflated,
+}
+
+struct Member {
+  path: PathBuf, // within the archive
+  offset: u64,
+  size: u64, // as stored
+  method: Method,
+}
+
+type Listing = (Option<SystemTime>, Arc<Vec<Member>>);
+
+// The tree is rebuilt often, so listings are kept until the archive changes.
+static LISTINGS: OnceLock<Mutex<HashMap<PathBuf, Listing>>> = OnceLock::new();
+
+pub fn is_archive(path: &Path) -> bool {
+  let archive = extension(path)
+    .map(|e| ARCHIVES.iter().any(|a| a.eq_ignore_ascii_case(e)))
+    .unwrap_or(false);
+  archive && path.is_file()
+}
+
+// Splits a path inside an archive into the archive and the path within it.
+pub fn split(path: &Path) -> Option<(&Path, &Path)> {
+  let archive = path.ancestors().skip(1).find(|a| is_archive(a))?;
+  Some((archive, path.strip_prefix(archive).ok()?))
+}
+
+// Real folders, archives, and folders inside archives.
+pub fn is_dir(path: &Path) -> bool {
+  path.is_dir() || list(path).is_some()
+}
+
+fn members(archive: &Path) -> Result<Arc<Vec<Member>>> {
+  let modified = fs::metadata(archive)?.modified().ok();
+  let listings = LISTINGS.get_or_init(|| Mutex::new(HashMap::new()));
+  if let Some((cached, members)) = listings.lock().get(archive) {
+    if *cached == modified {
+      return Ok(members.clone());
+    }
+  }
+
+  let members = Arc::new(
+    match extension(archive).map(|e| e.to_lowercase()).as_deref() {
+      Some("zip") => read_zip(archive)?,
+      _ => read_tar(archive)?,
+    },
+  );
+  listings
+    .lock()
+    .insert(archive.to_owned(), (modified, members.clone()));
+  Ok(members)
+}
+
+fn read_zip(path: &Path) -> Result<Vec<Member>> {
+  let mut zip = zip::ZipArchive::new(File::open(path)?)?;
+  let mut members = vec![];
+  for i in 0..zip.len() {
+    let file = zip.by_index_raw(i)?;
+    let method = match file.compression() {
+      CompressionMethod::Stored => Method::Stored,
+      CompressionMethod::Deflated => Method::Deflated,
+      _ => continue,
+    };
+    // names that would escape the archive are skipped
+    let path = match file.enclosed_name() {
+      Some(path) if !file.is_dir() => path.to_owned(),
+      _ => continue,
+    };
+    members.push(Member {
+      path,
+      offset: file.data_start(),
+      size: file.compressed_size(),
+      method,
+    });
+  }
+  Ok(members)
+}
+
+fn read_tar(path: &Path) -> Result<Vec<Member>> {
+  let mut tar = tar::Archive::new(File::open(path)?);
+  let mut members = vec![];
+  for entry in tar.entries_with_seek()? {
+    let entry = entry?;
+    if !entry.header().entry_type().is_file() {
+      continue;
+    }
+    members.push(Member {
+      path: entry.path()?.into_owned(),
+      offset: entry.raw_file_position(),
+      size: entry.size(),
+      method: Method::Stored,
+    });
+  }
+  Ok(members)
+}
+
+// The files and folders directly inside an archive, or a folder within one.
+pub fn list(dir: &Path) -> Option<(Vec<PathBuf>, Vec<PathBuf>)> {
+  let (archive, prefix) = match is_archive(dir) {
+    true => (dir, Path::new("")),
+    false => split(dir)?,
+  };
+  let members = members(archive).ok()?;
+
+  let (mut files, mut folders) = (vec![], HashSet::new());
+  for member in members.iter() {
+    let mut rest = match member.path.strip_prefix(prefix) {
+      Ok(rest) => rest.components(),
+      Err(_) => continue,
+    };
+    let name = match rest.next() {
+      Some(name) => dir.join(name),
+      None => continue,
+    };
+    if rest.next().is_some() {
+      folders.insert(name);
+    } else {
+      files.push(name);
+    }
+  }
+
+  if prefix != Path::new("") && files.is_empty() && folders.is_empty() {
+    return None;
+  }
+  Some((files, folders.into_iter().collect()))
+}
+
+// A file inside an archive, read straight from it without extracting.
+// Stored files can seek, compressed ones only play from the start.
+pub enum MemberReader {
+  Stored {
+    file: File,
+    start: u64,
+    size: u64,
+    position: u64,
+  },
+  Deflated(DeflateDecoder<io::Take<File>>),
+}
+
+pub fn open(path: &Path) -> Result<MemberReader> {
+  let (archive, inner_path) = match split(path) {
+    Some(split) => split,
+    None => bail!("{} is not inside an archive", path.display()),
+  };
+  let members = members(archive)?;
+  let member = match members.iter().find(|m| m.path == inner_path) {
+    Some(member) => member,
+    None => bail!(
+      "{} not found in {}",
+      inner_path.display(),
+      archive.display()
+    ),
+  };
+
+  let mut file = File::open(archive)?;
+  file.seek(SeekFrom::Start(member.offset))?;
+  Ok(match member.method {
+    Method::Stored => MemberReader::Stored {
+      file,
+      start: member.offset,
+      size: member.size,
+      position: 0,
+    },
+    Method::Deflated => MemberReader::Deflated(DeflateDecoder::new(file.take(member.size))),
+  })
+}
+
+impl MemberReader {
+  pub fn is_seekable(&self) -> bool {
+    matches!(self, Self::Stored { .. })
+  }
+
+  // Only known for stored files, which is all seeking needs.
+  pub fn byte_len(&self) -> Option<u64> {
+    match self {
+      Self::Stored { size, .. } => Some(*size),
+      Self::Deflated(_) => None,
+    }
+  }
+}
+
+impl Read for MemberReader {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    match self {
+      Self::Stored {
+        file,
+        size,
+        position,
+        ..
+      } => {
+        let left = (size.saturating_sub(*position) as usize).min(buf.len());
+        let read = file.read(&mut buf[..left])?;
+        *position += read as u64;
+        Ok(read)
+      }
+      Self::Deflated(decoder) => decoder.read(buf),
+    }
+  }
+}
+
+impl Seek for MemberReader {
+  fn seek(&mut self, to: SeekFrom) -> io::Result<u64> {
+    let (file, start, size, position) = match self {
+      Self::Stored {
+        file,
+        start,
+        size,
+        position,
+      } => (file, *start, *size, position),
+      Self::Deflated(_) => {
+        return Err(io::Error::new(
+          io::ErrorKind::Unsupported,
+          "compressed archive members can't seek",
+        ))
+      }
+    };
+
+    let target = match to {
+      SeekFrom::Start(offset) => offset as i64,
+      SeekFrom::Current(delta) => *position as i64 + delta,
+      SeekFrom::End(delta) => size as i64 + delta,
+    };
+    if target < 0 {
+      return Err(io::Error::new(
+        io::ErrorKind::InvalidInput,
+        "seek before the start of the file",
+      ));
+    }
+    *position = (target as u64).min(size);
+    file.seek(SeekFrom::Start(start + *position))?;
+    Ok(*position)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn tar_members_are_listed_and_seekable() {
+    let dir = std::env::temp_dir().join(format!("aquinas-archive-{}", std::process::id()));
+    let _ = fs::create_dir_all(&dir);
+    let path = dir.join("pack.TAR");
+
+    let mut builder = tar::Builder::new(File::create(&path).unwrap());
+    for (name, data) in [
+      ("drums/kick.wav", &b"kick data"[..]),
+      ("intro.mp3", b"intro"),
+    ] {
+      let mut header = tar::Header::new_gnu();
+      header.set_size(data.len() as u64);
+      header.set_cksum();
+      builder.append_data(&mut header, name, data).unwrap();
+    }
+    builder.finish().unwrap();
+    drop(builder);
+
+    let (files, folders) = list(&path).unwrap();
+    assert_eq!(files, vec![path.join("intro.mp3")]);
+    assert_eq!(folders, vec![path.join("drums")]);
+    assert!(is_dir(&path.join("drums")));
+
+    let mut reader = open(&path.join("drums/kick.wav")).unwrap();
+    reader.seek(SeekFrom::Start(5)).unwrap();
+    let mut rest = String::new();
+    reader.read_to_string(&mut rest).unwrap();
+    assert_eq!(rest, "data");
+
+    let _ = fs::remove_dir_all(&dir);
+  }
+}
@@ -3,6 +3,8 @@ mod gstreamer_backend;
 #[cfg(feature = "symphonia_backend")]
 mod symphonia_backend;
 
+#[cfg(test)]
+use crate::prelude::{Arc, Mutex};
 use std::boxed::Box;
 use std::path::{Path, PathBuf};
 
@@ -15,6 +17,22 @@ pub fn load() -> Box<dyn Backend> {
   return Box::new(symphonia_backend::Symphonia::new());
 }
 
+// Whether the backend can play a file, judging by its contents rather than its name.
+pub fn probe(path: &Path) -> bool {
+  #[cfg(feature = "gstreamer_backend")]
+  return gstreamer_backend::probe(path);
+  #[cfg(feature = "symphonia_backend")]
+  return symphonia_backend::probe(path);
+}
+
+// Length of a file in seconds, when its container says.
+pub fn duration(path: &Path) -> Option<u64> {
+  #[cfg(feature = "gstreamer_backend")]
+  return gstreamer_backend::duration(path);
+  #[cfg(feature = "symphonia_backend")]
+  return symphonia_backend::duration(path);
+}
+
 pub trait Backend {
   fn new() -> Self
   where
@@ -22,10 +40,65 @@ pub trait Backend {
   fn track_finished(&self) -> bool;
   fn play(&mut self, path: Option<&Path>) -> anyhow::Result<()>;
   fn pause(&mut self);
+  fn stop(&mut self);
   fn is_paused(&self) -> bool;
   fn last_played(&self) -> Option<&PathBuf>;
+  fn title(&self) -> Option<&str>; // as tagged in the playing file
   fn play_pause(&mut self);
   fn seek(&mut self, time: u64);
   fn seek_delta(&mut self, delta_time: i64);
   fn progress(&self) -> (f64, u64, u64); // (pct, pos, dur)
+  fn set_volume(&mut self, volume: f32); // 0.0 - 1.0
+}
+
+// Plays nothing and finishes nothing, for testing what's around playback.
+#[cfg(test)]
+#[derive(Default)]
+pub struct Silent {
+  pub playing: Option<PathBuf>,
+  pub paused: bool,
+  pub volume: Arc<Mutex<f32>>, // shared, to see what it was set to
+}
+
+#[cfg(test)]
+impl Backend for Silent {
+  fn new() -> Self {
+    Self::default()
+  }
+  fn track_finished(&self) -> bool {
+    false
+  }
+  fn play(&mut self, path: Option<&Path>) -> anyhow::Result<()> {
+    if let Some(path) = path {
+      self.playing = Some(path.to_owned());
+    }
+    self.paused = false;
+    Ok(())
+  }
+  fn pause(&mut self) {
+    self.paused = true;
+  }
+  fn stop(&mut self) {
+    self.playing = None;
+  }
+  fn is_paused(&self) -> bool {
+    self.paused
+  }
+  fn last_played(&self) -> Option<&PathBuf> {
+    self.playing.as_ref()
+  }
+  fn title(&self) -> Option<&str> {
+    None
+  }
+  fn play_pause(&mut self) {
+    self.paused = !self.paused;
+  }
+  fn seek(&mut self, _time: u64) {}
+  fn seek_delta(&mut self, _delta_time: i64) {}
+  fn progress(&self) -> (f64, u64, u64) {
+    (0., 0, 0)
+  }
+  fn set_volume(&mut self, volume: f32) {
+    *self.volume.lock() = volume;
+  }
 }
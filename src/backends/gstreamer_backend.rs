@@ -12,6 +12,29 @@ pub struct GStreamer {
   pub last_played: Option<PathBuf>,
 }
 
+pub fn probe(path: &Path) -> bool {
+  if gst::init().is_err() {
+    return false;
+  }
+  let discoverer = match gst_pbutils::Discoverer::new(ClockTime::from_seconds(5)) {
+    Ok(discoverer) => discoverer,
+    Err(_) => return false,
+  };
+  discoverer
+    .discover_uri(&format!("file:///{}", path.display()))
+    .map(|info| !info.audio_streams().is_empty())
+    .unwrap_or(false)
+}
+
+pub fn duration(path: &Path) -> Option<u64> {
+  gst::init().ok()?;
+  let discoverer = gst_pbutils::Discoverer::new(ClockTime::from_seconds(5)).ok()?;
+  let info = discoverer
+    .discover_uri(&format!("file:///{}", path.display()))
+    .ok()?;
+  info.duration().map(ClockTime::seconds)
+}
+
 impl super::Backend for GStreamer {
   fn new() -> Self {
     gst::init().expect("Could not initialize GStreamer.");
@@ -31,6 +54,10 @@ impl super::Backend for GStreamer {
     self.last_played.as_ref()
   }
 
+  fn title(&self) -> Option<&str> {
+    None
+  }
+
   fn track_finished(&self) -> bool {
     let progress = self.progress();
     !self.paused && progress.2 == 0 || progress.1 >= progress.2
@@ -51,6 +78,10 @@ impl super::Backend for GStreamer {
     self.player.pause();
     self.paused = true;
   }
+  fn stop(&mut self) {
+    self.player.stop();
+    self.paused = true;
+  }
   fn is_paused(&self) -> bool {
     self.paused
   }
@@ -89,4 +120,8 @@ impl super::Backend for GStreamer {
     let percent = time_pos as f64 / (duration as f64);
     (percent, time_pos, duration)
   }
+
+  fn set_volume(&mut self, volume: f32) {
+    self.player.set_volume(volume.clamp(0., 1.) as f64);
+  }
 }
@@ -1,12 +1,21 @@
+mod midi;
+mod render;
+mod soundfont;
+mod tracker;
+
+use crate::archive;
 use anyhow::{bail, Result};
 use cpal::{
   traits::{DeviceTrait, HostTrait, StreamTrait},
   BufferSize, SampleRate, StreamConfig,
 };
+use midi::Midi;
 use parking_lot::{Condvar, Mutex};
 use rb::*;
+use render::RenderedReader;
 use std::{
   fs::File,
+  io::Read,
   path::{Path, PathBuf},
   sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering},
   sync::Arc,
@@ -19,11 +28,12 @@ use symphonia::core::{
   conv::ConvertibleSample,
   errors::Error,
   formats::{FormatOptions, FormatReader, Track},
-  io::MediaSourceStream,
-  meta::MetadataOptions,
+  io::{MediaSource, MediaSourceStream},
+  meta::{MetadataOptions, StandardTagKey},
   probe::Hint,
   units::{Duration, Time},
 };
+use tracker::Tracker;
 
 trait AudioOutputSample:
   cpal::Sample + ConvertibleSample + RawSample + std::marker::Send + 'static
@@ -36,7 +46,10 @@ impl AudioOutputSample for u16 {}
 pub struct Symphonia {
   duration: u64,
   last_played: Option<PathBuf>,
+  title: Option<String>,
   controls: Arc<Controls>,
+  // f32 bits, shared with the output stream so changes apply immediately
+  volume: Arc<AtomicU32>,
 }
 
 #[derive(Default)]
@@ -64,20 +77,122 @@ fn reset_audio_output(
   output: &mut Option<Box<dyn AudioOutput>>,
   spec: &Option<SignalSpec>,
   duration: u64,
+  volume: &Arc<AtomicU32>,
 ) {
   if let Some(spec) = spec {
-    output.replace(try_open(*spec, duration).unwrap());
+    output.replace(try_open(*spec, duration, volume.clone()).unwrap());
+  }
+}
+
+fn title(reader: &mut dyn FormatReader) -> Option<String> {
+  let metadata = reader.metadata();
+  let tags = metadata.current()?.tags();
+  tags
+    .iter()
+    .find(|tag| tag.std_key == Some(StandardTagKey::TrackTitle))
+    .map(|tag| tag.value.to_string())
+}
+
+// Formats symphonia can't decode, which are rendered to pcm instead.
+enum Rendered {
+  Midi,
+  Tracker,
+}
+
+impl MediaSource for archive::MemberReader {
+  fn is_seekable(&self) -> bool {
+    archive::MemberReader::is_seekable(self)
+  }
+
+  fn byte_len(&self) -> Option<u64> {
+    archive::MemberReader::byte_len(self)
+  }
+}
+
+// Files inside archives are read straight out of them.
+fn source(path: &Path) -> Result<Box<dyn MediaSource>> {
+  Ok(match archive::split(path) {
+    Some(_) => Box::new(archive::open(path)?),
+    None => Box::new(File::open(path)?),
+  })
+}
+
+// Tells rendered formats apart by their headers.
+fn rendered(path: &Path) -> Result<Option<Rendered>> {
+  let mut header = vec![];
+  source(path)?
+    .take(tracker::HEADER_LEN)
+    .read_to_end(&mut header)?;
+
+  if header.starts_with(b"MThd") {
+    return Ok(Some(Rendered::Midi));
+  }
+  Ok(tracker::kind(&header).map(|_| Rendered::Tracker))
+}
+
+// Whether a file is playable, going by its contents rather than its name.
+pub fn probe(path: &Path) -> bool {
+  if let Ok(Some(_)) = rendered(path) {
+    return true;
+  }
+  let source = match source(path) {
+    Ok(source) => source,
+    Err(_) => return false,
+  };
+
+  let mss = MediaSourceStream::new(source, Default::default());
+  match symphonia::default::get_probe().format(
+    &Hint::new(),
+    mss,
+    &Default::default(),
+    &Default::default(),
+  ) {
+    Ok(probed) => probed
+      .format
+      .tracks()
+      .iter()
+      .any(|t| t.codec_params.codec != CODEC_TYPE_NULL),
+    Err(_) => false,
   }
 }
 
+pub fn duration(path: &Path) -> Option<u64> {
+  let reader = Symphonia::get_reader(path).ok()?;
+  let track = reader
+    .tracks()
+    .iter()
+    .find(|t| t.codec_params.codec != CODEC_TYPE_NULL)?;
+  let params = &track.codec_params;
+  let time = params
+    .time_base?
+    .calc_time(params.start_ts + params.n_frames?);
+  Some(time.seconds)
+}
+
 impl Symphonia {
   fn get_reader(path: &Path) -> Result<Box<dyn FormatReader>> {
-    let src = File::open(path)?;
+    let rendered = rendered(path)?;
 
-    let mss = MediaSourceStream::new(Box::new(src), Default::default());
-    let hint = Hint::new();
+    let mss = MediaSourceStream::new(source(path)?, Default::default());
+    let mut hint = Hint::new();
+    if let Some(extension) = crate::extension(path) {
+      hint.with_extension(&extension.to_lowercase());
+    }
     let meta_opts: MetadataOptions = Default::default();
     let fmt_opts: FormatOptions = Default::default();
+
+    match rendered {
+      Some(Rendered::Midi) => {
+        return Ok(Box::new(RenderedReader::<Midi>::try_new(mss, &fmt_opts)?));
+      }
+      Some(Rendered::Tracker) => {
+        return Ok(Box::new(RenderedReader::<Tracker>::try_new(
+          mss, &fmt_opts,
+        )?));
+      }
+      None => {}
+    }
+
     let probed = symphonia::default::get_probe().format(&hint, mss, &fmt_opts, &meta_opts)?;
 
     Ok(probed.format)
@@ -90,6 +205,8 @@ impl super::Backend for Symphonia {
       duration: 0,
       controls: Arc::new(Controls::default()),
       last_played: None,
+      title: None,
+      volume: Arc::new(AtomicU32::new(1f32.to_bits())),
     }
   }
 
@@ -97,13 +214,19 @@ impl super::Backend for Symphonia {
     self.last_played.as_ref()
   }
 
+  fn title(&self) -> Option<&str> {
+    self.title.as_deref()
+  }
+
   fn play(&mut self, path: Option<&Path>) -> Result<()> {
     self.controls = Arc::new(Controls::default());
+    self.title = None;
 
     if let Some(path) = path {
       self.last_played = Some(path.to_owned());
 
       let mut reader = Self::get_reader(path)?;
+      self.title = title(reader.as_mut());
       let track = reader.default_track();
 
       let tb = track.codec_params.time_base.unwrap();
@@ -118,6 +241,7 @@ impl super::Backend for Symphonia {
 
       thread::spawn({
         let controls = self.controls.clone();
+        let volume = self.volume.clone();
         let track_id = track.id;
         let mut decoder =
           symphonia::default::get_codecs().make(&track.codec_params, &decoder_options)?;
@@ -136,7 +260,7 @@ impl super::Backend for Symphonia {
             if *is_paused {
               audio_output.as_ref().map(|ao| ao.pause());
               cvar.wait(&mut is_paused);
-              reset_audio_output(&mut audio_output, &spec, duration);
+              reset_audio_output(&mut audio_output, &spec, duration, &volume);
               audio_output.as_ref().map(|ao| ao.play());
             }
 
@@ -173,7 +297,7 @@ impl super::Backend for Symphonia {
                 if audio_output.is_none() {
                   spec = Some(*decoded.spec());
                   duration = decoded.capacity() as Duration;
-                  reset_audio_output(&mut audio_output, &spec, duration);
+                  reset_audio_output(&mut audio_output, &spec, duration, &volume);
                   // audio_output.replace(try_open(spec.unwrap(), duration).unwrap());
                 }
 
@@ -215,6 +339,15 @@ impl super::Backend for Symphonia {
     cvar.notify_one();
   }
 
+  // Drops the current track's controls, leaving the backend paused with nothing loaded.
+  fn stop(&mut self) {
+    let controls = Controls::default();
+    *controls.is_paused.0.lock() = true;
+    self.controls = Arc::new(controls);
+    self.duration = 0;
+    self.title = None;
+  }
+
   fn play_pause(&mut self) {
     let &(ref lock, ref cvar) = &self.controls.is_paused;
     let mut is_paused = lock.lock();
@@ -237,6 +370,11 @@ impl super::Backend for Symphonia {
     let position = self.controls.position.load(Ordering::Relaxed) as i64;
     self.seek(position.saturating_add(delta_time) as u64);
   }
+
+  fn set_volume(&mut self, volume: f32) {
+    let volume = volume.clamp(0., 1.);
+    self.volume.store(volume.to_bits(), Ordering::Relaxed);
+  }
 }
 
 struct CpalAudioOutputImpl<T: AudioOutputSample>
@@ -255,7 +393,11 @@ pub trait AudioOutput {
   fn play(&self);
 }
 
-fn try_open(spec: SignalSpec, duration: Duration) -> Result<Box<dyn AudioOutput>> {
+fn try_open(
+  spec: SignalSpec,
+  duration: Duration,
+  volume: Arc<AtomicU32>,
+) -> Result<Box<dyn AudioOutput>> {
   let host = cpal::default_host();
   let device = match host.default_output_device() {
     Some(device) => device,
@@ -271,9 +413,15 @@ fn try_open(spec: SignalSpec, duration: Duration) -> Result<Box<dyn AudioOutput>
   };
 
   match config.sample_format() {
-    cpal::SampleFormat::F32 => CpalAudioOutputImpl::<f32>::try_open(spec, duration, &device),
-    cpal::SampleFormat::I16 => CpalAudioOutputImpl::<i16>::try_open(spec, duration, &device),
-    cpal::SampleFormat::U16 => CpalAudioOutputImpl::<u16>::try_open(spec, duration, &device),
+    cpal::SampleFormat::F32 => {
+      CpalAudioOutputImpl::<f32>::try_open(spec, duration, volume, &device)
+    }
+    cpal::SampleFormat::I16 => {
+      CpalAudioOutputImpl::<i16>::try_open(spec, duration, volume, &device)
+    }
+    cpal::SampleFormat::U16 => {
+      CpalAudioOutputImpl::<u16>::try_open(spec, duration, volume, &device)
+    }
     _ => unreachable!(), // We shouldn't reach here... right?
   }
 }
@@ -282,6 +430,7 @@ impl<T: AudioOutputSample + cpal::SizedSample> CpalAudioOutputImpl<T> {
   pub fn try_open(
     spec: SignalSpec,
     duration: Duration,
+    volume: Arc<AtomicU32>,
     device: &cpal::Device,
   ) -> Result<Box<dyn AudioOutput>> {
     let channels = spec.channels.count() as usize;
@@ -301,6 +450,15 @@ impl<T: AudioOutputSample + cpal::SizedSample> CpalAudioOutputImpl<T> {
       &config,
       move |data: &mut [T], _| {
         let written = ring_buf_rx.read(data).unwrap_or(0);
+
+        let volume = f32::from_bits(volume.load(Ordering::Relaxed));
+        if volume < 1. {
+          let amp = <T::Float as cpal::FromSample<f32>>::from_sample_(volume);
+          data[..written]
+            .iter_mut()
+            .for_each(|s| *s = cpal::Sample::mul_amp(*s, amp));
+        }
+
         data[written..].iter_mut().for_each(|s| *s = T::MID);
       },
       move |_| {},
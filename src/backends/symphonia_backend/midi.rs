@@ -0,0 +1,217 @@
+use super::render::Renderer;
+use super::soundfont::{SoundFont, Synth};
+use crate::{Config, Mutex};
+use midly::{MetaMessage, MidiMessage, Smf, Timing, TrackEventKind};
+use std::{path::PathBuf, sync::Arc};
+use symphonia::core::{
+  errors::{decode_error, unsupported_error, Result},
+  meta::{MetadataBuilder, MetadataLog, StandardTagKey, Tag, Value},
+};
+
+const SAMPLE_RATE: u32 = 44100;
+// let the last notes ring out
+const TAIL_SECS: u64 = 2;
+
+// Parsing a soundfont can take a while, so keep the last one around between tracks.
+static SOUNDFONT: Mutex<Option<(PathBuf, Arc<SoundFont>)>> = Mutex::new(None);
+
+fn soundfont() -> Result<Arc<SoundFont>> {
+  let path = match Config::load().unwrap_or_default().soundfont {
+    Some(path) => path,
+    None => return unsupported_error("midi: no soundfont configured"),
+  };
+
+  let mut cache = SOUNDFONT.lock();
+  if let Some((cached, font)) = &*cache {
+    if *cached == path {
+      return Ok(font.clone());
+    }
+  }
+
+  let font = Arc::new(SoundFont::parse(&std::fs::read(&path)?)?);
+  *cache = Some((path, font.clone()));
+  Ok(font)
+}
+
+struct Event {
+  frame: u64,
+  channel: u8,
+  message: MidiMessage,
+}
+
+pub struct Midi {
+  synth: Synth,
+  events: Vec<Event>,
+  next: usize,
+  position: u64,
+  frames: u64,
+  title: Option<String>,
+}
+
+// Flattens all tracks into one list of channel events, timed in output frames.
+fn events(smf: &Smf) -> Vec<Event> {
+  let mut timeline = vec![];
+  for track in &smf.tracks {
+    let mut tick = 0;
+    for event in track {
+      tick += event.delta.as_int() as u64;
+      timeline.push((tick, event.kind));
+    }
+  }
+  timeline.sort_by_key(|(tick, _)| *tick);
+
+  let rate = SAMPLE_RATE as f64;
+  let frames_per_tick = |tempo: f64| match smf.header.timing {
+    Timing::Metrical(tpb) => tempo / 1e6 / tpb.as_int().max(1) as f64 * rate,
+    Timing::Timecode(fps, sub) => rate / (fps.as_f32() as f64 * sub.max(1) as f64),
+  };
+
+  let mut events = vec![];
+  // microseconds per beat, 120bpm until told otherwise
+  let mut tempo = 500_000.;
+  let (mut last_tick, mut frame) = (0, 0.);
+
+  for (tick, kind) in timeline {
+    frame += (tick - last_tick) as f64 * frames_per_tick(tempo);
+    last_tick = tick;
+
+    match kind {
+      TrackEventKind::Meta(MetaMessage::Tempo(t)) => tempo = t.as_int() as f64,
+      TrackEventKind::Midi { channel, message } => events.push(Event {
+        frame: frame as u64,
+        channel: channel.as_int(),
+        message,
+      }),
+      _ => {}
+    }
+  }
+  events
+}
+
+fn title(smf: &Smf) -> Option<String> {
+  smf.tracks.first()?.iter().find_map(|e| match e.kind {
+    TrackEventKind::Meta(MetaMessage::TrackName(name)) => {
+      Some(String::from_utf8_lossy(name).trim().to_owned())
+    }
+    _ => None,
+  })
+}
+
+// Notes are skipped while seeking, only the channel state is replayed.
+fn apply(synth: &mut Synth, event: &Event, notes: bool) {
+  let channel = event.channel;
+  match event.message {
+    MidiMessage::NoteOn { key, vel } if notes => synth.note_on(channel, key.as_int(), vel.as_int()),
+    MidiMessage::NoteOff { key, .. } if notes => synth.note_off(channel, key.as_int()),
+    MidiMessage::Controller { controller, value } => {
+      synth.controller(channel, controller.as_int(), value.as_int())
+    }
+    MidiMessage::ProgramChange { program } => synth.program_change(channel, program.as_int()),
+    MidiMessage::PitchBend { bend } => synth.pitch_bend(channel, bend.as_f32()),
+    _ => {}
+  }
+}
+
+impl Renderer for Midi {
+  fn open(data: Vec<u8>) -> Result<Self> {
+    let smf = match Smf::parse(&data) {
+      Ok(smf) => smf,
+      Err(_) => return decode_error("midi: invalid midi file"),
+    };
+    let events = events(&smf);
+    let frames = events.last().map(|e| e.frame).unwrap_or(0) + TAIL_SECS * SAMPLE_RATE as u64;
+
+    Ok(Self {
+      synth: Synth::new(soundfont()?, SAMPLE_RATE),
+      title: title(&smf).filter(|t| !t.is_empty()),
+      events,
+      next: 0,
+      position: 0,
+      frames,
+    })
+  }
+
+  fn sample_rate(&self) -> u32 {
+    SAMPLE_RATE
+  }
+
+  fn frames(&self) -> u64 {
+    self.frames
+  }
+
+  fn render(&mut self, out: &mut [f32]) {
+    let frames = out.len() / 2;
+    let mut done = 0;
+
+    while done < frames {
+      let now = self.position + done as u64;
+      while let Some(event) = self.events.get(self.next) {
+        if event.frame > now {
+          break;
+        }
+        apply(&mut self.synth, event, true);
+        self.next += 1;
+      }
+
+      let until = match self.events.get(self.next) {
+        Some(event) => ((event.frame - self.position) as usize).min(frames),
+        None => frames,
+      };
+      self.synth.render(&mut out[done * 2..until * 2]);
+      done = until;
+    }
+
+    self.position += frames as u64;
+  }
+
+  fn seek(&mut self, frame: u64) {
+    self.synth.reset();
+    self.next = 0;
+    while let Some(event) = self.events.get(self.next) {
+      if event.frame >= frame {
+        break;
+      }
+      apply(&mut self.synth, event, false);
+      self.next += 1;
+    }
+    self.position = frame;
+  }
+
+  fn metadata(&self) -> MetadataLog {
+    let mut log = MetadataLog::default();
+    if let Some(title) = &self.title {
+      let mut builder = MetadataBuilder::new();
+      builder.add_tag(Tag::new(
+        Some(StandardTagKey::TrackTitle),
+        "TITLE",
+        Value::from(title.as_str()),
+      ));
+      log.push(builder.metadata());
+    }
+    log
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // format 0, 96 ticks per beat, tempo doubled after the first beat
+  const SMF: &[u8] = &[
+    b'M', b'T', b'h', b'd', 0, 0, 0, 6, 0, 0, 0, 1, 0, 96, //
+    b'M', b'T', b'r', b'k', 0, 0, 0, 23, //
+    0x00, 0x90, 60, 100, // note on at 0
+    0x60, 0xFF, 0x51, 3, 0x03, 0xD0, 0x90, // tempo 250000us at beat 1
+    0x00, 0x80, 60, 0, // note off at beat 1
+    0x60, 0x90, 62, 100, // note on at beat 2
+    0x00, 0xFF, 0x2F, 0, // end of track
+  ];
+
+  #[test]
+  fn tempo_changes_are_timed_in_frames() {
+    let smf = Smf::parse(SMF).unwrap();
+    let frames: Vec<u64> = events(&smf).iter().map(|e| e.frame).collect();
+    // 0.5s for the first beat at 120bpm, 0.25s for the second at 240bpm
+    assert_eq!(frames, vec![0, 22050, 33075]);
+  }
+}
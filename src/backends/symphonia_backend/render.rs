@@ -0,0 +1,119 @@
+use std::io::Read;
+use symphonia::core::{
+  audio::Channels,
+  codecs::{CodecParameters, CODEC_TYPE_PCM_F32LE},
+  errors::{Error, Result},
+  formats::{Cue, FormatOptions, FormatReader, Packet, SeekMode, SeekTo, SeekedTo, Track},
+  io::MediaSourceStream,
+  meta::{Metadata, MetadataLog},
+  units::TimeBase,
+};
+
+const PACKET_FRAMES: u64 = 1024;
+
+// Something that synthesizes stereo audio from a file instead of decoding it, e.g. MIDI.
+pub trait Renderer: Sized + Send + Sync {
+  fn open(data: Vec<u8>) -> Result<Self>;
+  fn sample_rate(&self) -> u32;
+  // Total length in frames.
+  fn frames(&self) -> u64;
+  // Fill `out` with interleaved stereo samples.
+  fn render(&mut self, out: &mut [f32]);
+  fn seek(&mut self, frame: u64);
+  fn metadata(&self) -> MetadataLog {
+    MetadataLog::default()
+  }
+}
+
+// Exposes a `Renderer` as a Symphonia format whose packets are raw f32 PCM,
+// so rendered files go through the same decoder and output path as everything else.
+pub struct RenderedReader<R: Renderer> {
+  source: MediaSourceStream,
+  renderer: R,
+  tracks: Vec<Track>,
+  metadata: MetadataLog,
+  position: u64,
+  buffer: Vec<f32>,
+}
+
+impl<R: Renderer> FormatReader for RenderedReader<R> {
+  fn try_new(mut source: MediaSourceStream, _options: &FormatOptions) -> Result<Self> {
+    let mut data = vec![];
+    source.read_to_end(&mut data)?;
+    let renderer = R::open(data)?;
+
+    let mut params = CodecParameters::new();
+    params
+      .for_codec(CODEC_TYPE_PCM_F32LE)
+      .with_sample_rate(renderer.sample_rate())
+      .with_time_base(TimeBase::new(1, renderer.sample_rate()))
+      .with_n_frames(renderer.frames())
+      .with_max_frames_per_packet(PACKET_FRAMES)
+      .with_channels(Channels::FRONT_LEFT | Channels::FRONT_RIGHT);
+
+    Ok(Self {
+      source,
+      metadata: renderer.metadata(),
+      renderer,
+      tracks: vec![Track::new(0, params)],
+      position: 0,
+      buffer: vec![],
+    })
+  }
+
+  fn cues(&self) -> &[Cue] {
+    &[]
+  }
+
+  fn metadata(&mut self) -> Metadata<'_> {
+    self.metadata.metadata()
+  }
+
+  fn seek(&mut self, _mode: SeekMode, to: SeekTo) -> Result<SeekedTo> {
+    let frame = match to {
+      SeekTo::Time { time, .. } => {
+        ((time.seconds as f64 + time.frac) * self.renderer.sample_rate() as f64) as u64
+      }
+      SeekTo::TimeStamp { ts, .. } => ts,
+    }
+    .min(self.renderer.frames());
+
+    self.renderer.seek(frame);
+    self.position = frame;
+
+    Ok(SeekedTo {
+      track_id: 0,
+      required_ts: frame,
+      actual_ts: frame,
+    })
+  }
+
+  fn tracks(&self) -> &[Track] {
+    &self.tracks
+  }
+
+  fn next_packet(&mut self) -> Result<Packet> {
+    let frames = self
+      .renderer
+      .frames()
+      .saturating_sub(self.position)
+      .min(PACKET_FRAMES);
+    if frames == 0 {
+      return Err(Error::IoError(std::io::ErrorKind::UnexpectedEof.into()));
+    }
+
+    self.buffer.clear();
+    self.buffer.resize(frames as usize * 2, 0.);
+    self.renderer.render(&mut self.buffer);
+
+    let data: Vec<u8> = self.buffer.iter().flat_map(|s| s.to_le_bytes()).collect();
+    let packet = Packet::new_from_boxed_slice(0, self.position, frames, data.into_boxed_slice());
+    self.position += frames;
+
+    Ok(packet)
+  }
+
+  fn into_inner(self: Box<Self>) -> MediaSourceStream {
+    self.source
+  }
+}
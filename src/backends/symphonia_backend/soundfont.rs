@@ -0,0 +1,722 @@
+use std::{collections::HashMap, f32::consts::FRAC_PI_4, sync::Arc};
+use symphonia::core::errors::{decode_error, Result};
+
+// SF2 generator operators used by the synth.
+const START_OFFSET: usize = 0;
+const END_OFFSET: usize = 1;
+const LOOP_START_OFFSET: usize = 2;
+const LOOP_END_OFFSET: usize = 3;
+const START_COARSE_OFFSET: usize = 4;
+const END_COARSE_OFFSET: usize = 12;
+const PAN: usize = 17;
+const DELAY_VOL_ENV: usize = 33;
+const ATTACK_VOL_ENV: usize = 34;
+const HOLD_VOL_ENV: usize = 35;
+const DECAY_VOL_ENV: usize = 36;
+const SUSTAIN_VOL_ENV: usize = 37;
+const RELEASE_VOL_ENV: usize = 38;
+const INSTRUMENT: usize = 41;
+const KEY_RANGE: usize = 43;
+const VEL_RANGE: usize = 44;
+const LOOP_START_COARSE_OFFSET: usize = 45;
+const KEYNUM: usize = 46;
+const VELOCITY: usize = 47;
+const INITIAL_ATTENUATION: usize = 48;
+const LOOP_END_COARSE_OFFSET: usize = 50;
+const COARSE_TUNE: usize = 51;
+const FINE_TUNE: usize = 52;
+const SAMPLE_ID: usize = 53;
+const SAMPLE_MODES: usize = 54;
+const SCALE_TUNING: usize = 56;
+const OVERRIDING_ROOT_KEY: usize = 58;
+const GENERATORS: usize = 61;
+
+// Generators that are not summed between the preset and instrument level.
+const NON_ADDITIVE: &[usize] = &[
+  START_OFFSET,
+  END_OFFSET,
+  LOOP_START_OFFSET,
+  LOOP_END_OFFSET,
+  START_COARSE_OFFSET,
+  END_COARSE_OFFSET,
+  INSTRUMENT,
+  KEY_RANGE,
+  VEL_RANGE,
+  LOOP_START_COARSE_OFFSET,
+  KEYNUM,
+  VELOCITY,
+  LOOP_END_COARSE_OFFSET,
+  SAMPLE_ID,
+  SAMPLE_MODES,
+  OVERRIDING_ROOT_KEY,
+];
+
+const MAX_VOICES: usize = 128;
+
+type Generators = [i32; GENERATORS];
+
+fn default_generators() -> Generators {
+  let mut gens = [0; GENERATORS];
+  for op in [21, 23, 25, 26, 27, 28, 30, 33, 34, 35, 36, 38] {
+    gens[op] = -12000;
+  }
+  gens[8] = 13500;
+  gens[KEY_RANGE] = 127 << 8;
+  gens[VEL_RANGE] = 127 << 8;
+  gens[KEYNUM] = -1;
+  gens[VELOCITY] = -1;
+  gens[SCALE_TUNING] = 100;
+  gens[OVERRIDING_ROOT_KEY] = -1;
+  gens
+}
+
+#[derive(Clone)]
+struct Zone {
+  gens: Generators,
+  // generators explicitly set in this zone (or its global zone)
+  set: [bool; GENERATORS],
+}
+
+impl Zone {
+  fn in_range(&self, op: usize, value: u8) -> bool {
+    let range = self.gens[op];
+    let (lo, hi) = ((range & 0xff) as u8, ((range >> 8) & 0xff) as u8);
+    value >= lo && value <= hi
+  }
+}
+
+struct SampleHeader {
+  start: u32,
+  end: u32,
+  loop_start: u32,
+  loop_end: u32,
+  sample_rate: u32,
+  original_pitch: u8,
+  pitch_correction: i8,
+  sample_type: u16,
+}
+
+pub struct SoundFont {
+  samples: Vec<i16>,
+  headers: Vec<SampleHeader>,
+  instruments: Vec<Vec<Zone>>,
+  presets: HashMap<(u16, u16), Vec<Zone>>,
+}
+
+struct Chunk<'a> {
+  id: &'a [u8],
+  data: &'a [u8],
+}
+
+fn chunks(mut data: &[u8]) -> Vec<Chunk<'_>> {
+  let mut chunks = vec![];
+  while data.len() >= 8 {
+    let len = u32::from_le_bytes([data[4], data[5], data[6], data[7]]) as usize;
+    let end = (8 + len).min(data.len());
+    chunks.push(Chunk {
+      id: &data[..4],
+      data: &data[8..end],
+    });
+    // chunks are padded to an even length
+    data = &data[(end + (len & 1)).min(data.len())..];
+  }
+  chunks
+}
+
+fn u16_at(data: &[u8], at: usize) -> u16 {
+  u16::from_le_bytes([data[at], data[at + 1]])
+}
+
+fn u32_at(data: &[u8], at: usize) -> u32 {
+  u32::from_le_bytes([data[at], data[at + 1], data[at + 2], data[at + 3]])
+}
+
+// (bag index) per record, for phdr / inst
+fn bag_indices(data: &[u8], record: usize, offset: usize) -> Vec<usize> {
+  data
+    .chunks_exact(record)
+    .map(|r| u16_at(r, offset) as usize)
+    .collect()
+}
+
+// Resolves the zones of every preset or instrument. A leading zone without the
+// terminal generator (instrument / sample id) is a global zone, merged into the rest.
+fn zones(
+  bag_ndx: &[usize],
+  bags: &[u8],
+  gens: &[u8],
+  terminal: usize,
+  defaults: &Generators,
+) -> Vec<Vec<Zone>> {
+  let gen_ndx: Vec<usize> = bags
+    .chunks_exact(4)
+    .map(|b| u16_at(b, 0) as usize)
+    .collect();
+  let generators: Vec<(usize, i32)> = gens
+    .chunks_exact(4)
+    .map(|g| {
+      let op = u16_at(g, 0) as usize;
+      let amount = match op {
+        KEY_RANGE | VEL_RANGE => u16_at(g, 2) as i32,
+        _ => u16_at(g, 2) as i16 as i32,
+      };
+      (op, amount)
+    })
+    .collect();
+
+  bag_ndx
+    .windows(2)
+    .map(|w| {
+      let mut global: Option<Zone> = None;
+      let mut zones = vec![];
+
+      for bag in w[0]..w[1] {
+        let (from, to) = match (gen_ndx.get(bag), gen_ndx.get(bag + 1)) {
+          (Some(from), Some(to)) => (*from, *to),
+          _ => break,
+        };
+
+        let mut zone = global.clone().unwrap_or(Zone {
+          gens: *defaults,
+          set: [false; GENERATORS],
+        });
+        for (op, amount) in generators.get(from..to).unwrap_or_default() {
+          if *op < GENERATORS {
+            zone.gens[*op] = *amount;
+            zone.set[*op] = true;
+          }
+        }
+
+        let is_global = generators
+          .get(from..to)
+          .unwrap_or_default()
+          .iter()
+          .all(|(op, _)| *op != terminal);
+        match is_global {
+          true if bag == w[0] => global = Some(zone),
+          true => {}
+          false => zones.push(zone),
+        }
+      }
+      zones
+    })
+    .collect()
+}
+
+impl SoundFont {
+  pub fn parse(data: &[u8]) -> Result<Self> {
+    let riff = match chunks(data).into_iter().next() {
+      Some(c) if c.id == b"RIFF" && c.data.starts_with(b"sfbk") => c,
+      _ => return decode_error("sf2: not a soundfont"),
+    };
+
+    let mut samples = vec![];
+    let mut pdta: HashMap<&[u8], &[u8]> = HashMap::new();
+
+    for list in chunks(&riff.data[4..]) {
+      if list.id != b"LIST" || list.data.len() < 4 {
+        continue;
+      }
+      for chunk in chunks(&list.data[4..]) {
+        match (&list.data[..4], chunk.id) {
+          (b"sdta", b"smpl") => {
+            samples = chunk
+              .data
+              .chunks_exact(2)
+              .map(|s| i16::from_le_bytes([s[0], s[1]]))
+              .collect();
+          }
+          (b"pdta", id) => {
+            pdta.insert(id, chunk.data);
+          }
+          _ => {}
+        }
+      }
+    }
+
+    // zones point into the sample data, so there has to be some
+    if samples.is_empty() {
+      return decode_error("sf2: no sample data");
+    }
+
+    let get = |id: &[u8]| pdta.get(id).copied().unwrap_or_default();
+    let (phdr, inst, shdr) = (get(b"phdr"), get(b"inst"), get(b"shdr"));
+    if phdr.is_empty() || inst.is_empty() || shdr.is_empty() {
+      return decode_error("sf2: missing preset data");
+    }
+
+    let headers = shdr
+      .chunks_exact(46)
+      .map(|s| SampleHeader {
+        start: u32_at(s, 20),
+        end: u32_at(s, 24),
+        loop_start: u32_at(s, 28),
+        loop_end: u32_at(s, 32),
+        sample_rate: u32_at(s, 36),
+        original_pitch: s[40],
+        pitch_correction: s[41] as i8,
+        sample_type: u16_at(s, 44),
+      })
+      .collect();
+
+    let instruments = zones(
+      &bag_indices(inst, 22, 20),
+      get(b"ibag"),
+      get(b"igen"),
+      SAMPLE_ID,
+      &default_generators(),
+    );
+
+    // preset level generators are offsets, only the ranges need a default
+    let mut preset_defaults = [0; GENERATORS];
+    preset_defaults[KEY_RANGE] = 127 << 8;
+    preset_defaults[VEL_RANGE] = 127 << 8;
+    let preset_zones = zones(
+      &bag_indices(phdr, 38, 24),
+      get(b"pbag"),
+      get(b"pgen"),
+      INSTRUMENT,
+      &preset_defaults,
+    );
+    let mut presets = HashMap::new();
+    for (header, zones) in phdr.chunks_exact(38).zip(preset_zones) {
+      let (program, bank) = (u16_at(header, 20), u16_at(header, 22));
+      presets.entry((bank, program)).or_insert(zones);
+    }
+
+    Ok(Self {
+      samples,
+      headers,
+      instruments,
+      presets,
+    })
+  }
+
+  // Combined preset + instrument generators for every sample that should sound for a note.
+  fn regions(&self, bank: u16, program: u16, key: u8, vel: u8) -> Vec<Generators> {
+    // fall back to the general midi bank (or the standard drum kit)
+    let fallback = match bank {
+      128 => 128,
+      _ => 0,
+    };
+    let preset = self
+      .presets
+      .get(&(bank, program))
+      .or_else(|| self.presets.get(&(fallback, program)))
+      .or_else(|| self.presets.get(&(fallback, 0)));
+    let preset = match preset {
+      Some(preset) => preset,
+      None => return vec![],
+    };
+
+    let mut regions = vec![];
+    for pzone in preset {
+      if !pzone.in_range(KEY_RANGE, key) || !pzone.in_range(VEL_RANGE, vel) {
+        continue;
+      }
+      let instrument = match self.instruments.get(pzone.gens[INSTRUMENT] as usize) {
+        Some(instrument) => instrument,
+        None => continue,
+      };
+
+      for izone in instrument {
+        if !izone.in_range(KEY_RANGE, key) || !izone.in_range(VEL_RANGE, vel) {
+          continue;
+        }
+        let mut gens = izone.gens;
+        for (op, gen) in gens.iter_mut().enumerate() {
+          if pzone.set[op] && !NON_ADDITIVE.contains(&op) {
+            *gen += pzone.gens[op];
+          }
+        }
+        regions.push(gens);
+      }
+    }
+    regions
+  }
+}
+
+fn timecents(tc: i32) -> f32 {
+  2f32.powf(tc as f32 / 1200.)
+}
+
+fn centibels(cb: i32) -> f32 {
+  10f32.powf(-(cb.max(0) as f32) / 200.)
+}
+
+#[derive(PartialEq, Clone, Copy)]
+enum Stage {
+  Delay,
+  Attack,
+  Hold,
+  Decay,
+  Sustain,
+  Release,
+  Done,
+}
+
+struct Envelope {
+  stage: Stage,
+  level: f32,
+  elapsed: u32,
+  delay: u32,
+  attack: u32,
+  hold: u32,
+  // per-frame multipliers for exponential decay / release
+  decay: f32,
+  sustain: f32,
+  release: f32,
+}
+
+// Multiplier that takes a level down by 100dB over `secs`.
+fn falloff(secs: f32, rate: f32) -> f32 {
+  (1e-5f32.ln() / (secs * rate).max(1.)).exp()
+}
+
+impl Envelope {
+  fn new(gens: &Generators, rate: f32) -> Self {
+    let frames = |op: usize| (timecents(gens[op]) * rate) as u32;
+    Self {
+      stage: Stage::Delay,
+      level: 0.,
+      elapsed: 0,
+      delay: frames(DELAY_VOL_ENV),
+      attack: frames(ATTACK_VOL_ENV),
+      hold: frames(HOLD_VOL_ENV),
+      decay: falloff(timecents(gens[DECAY_VOL_ENV]), rate),
+      sustain: centibels(gens[SUSTAIN_VOL_ENV]),
+      release: falloff(timecents(gens[RELEASE_VOL_ENV]), rate),
+    }
+  }
+
+  fn next(&mut self) -> f32 {
+    self.elapsed += 1;
+    match self.stage {
+      Stage::Delay if self.elapsed >= self.delay => self.advance(Stage::Attack),
+      Stage::Attack => {
+        self.level = (self.elapsed as f32 / self.attack.max(1) as f32).min(1.);
+        if self.elapsed >= self.attack {
+          self.advance(Stage::Hold);
+        }
+      }
+      Stage::Hold if self.elapsed >= self.hold => self.advance(Stage::Decay),
+      Stage::Decay => {
+        self.level *= self.decay;
+        if self.level <= self.sustain {
+          self.level = self.sustain;
+          self.advance(Stage::Sustain);
+        }
+      }
+      Stage::Release => {
+        self.level *= self.release;
+        if self.level < 1e-4 {
+          self.advance(Stage::Done);
+        }
+      }
+      _ => {}
+    }
+    self.level
+  }
+
+  fn advance(&mut self, stage: Stage) {
+    self.stage = stage;
+    self.elapsed = 0;
+  }
+
+  fn release(&mut self) {
+    if self.stage != Stage::Done {
+      self.advance(Stage::Release);
+    }
+  }
+}
+
+struct Voice {
+  channel: u8,
+  key: u8,
+  position: f64,
+  step: f64,
+  end: f64,
+  loop_start: f64,
+  loop_end: f64,
+  // 0: no loop, 1: loop, 3: loop until released
+  loop_mode: i32,
+  released: bool,
+  held: bool,
+  gain: f32,
+  pan: f32,
+  envelope: Envelope,
+}
+
+impl Voice {
+  fn looping(&self) -> bool {
+    self.loop_mode == 1 || (self.loop_mode == 3 && !self.released)
+  }
+
+  fn release(&mut self) {
+    self.released = true;
+    self.envelope.release();
+  }
+}
+
+#[derive(Clone)]
+struct Channel {
+  program: u16,
+  bank: u16,
+  volume: f32,
+  expression: f32,
+  pan: f32,
+  bend: f32, // semitones
+  bend_range: f32,
+  sustain: bool,
+  rpn: (u8, u8),
+}
+
+impl Default for Channel {
+  fn default() -> Self {
+    Self {
+      program: 0,
+      bank: 0,
+      volume: 100. / 127.,
+      expression: 1.,
+      pan: 0.,
+      bend: 0.,
+      bend_range: 2.,
+      sustain: false,
+      rpn: (127, 127),
+    }
+  }
+}
+
+pub struct Synth {
+  font: Arc<SoundFont>,
+  rate: f32,
+  voices: Vec<Voice>,
+  channels: [Channel; 16],
+}
+
+impl Synth {
+  pub fn new(font: Arc<SoundFont>, rate: u32) -> Self {
+    Self {
+      font,
+      rate: rate as f32,
+      voices: vec![],
+      channels: Default::default(),
+    }
+  }
+
+  pub fn reset(&mut self) {
+    self.voices.clear();
+    self.channels = Default::default();
+  }
+
+  pub fn note_on(&mut self, channel: u8, key: u8, vel: u8) {
+    if vel == 0 {
+      return self.note_off(channel, key);
+    }
+
+    let ch = &self.channels[channel as usize];
+    // channel 10 is reserved for percussion
+    let bank = match channel {
+      9 => 128,
+      _ => ch.bank,
+    };
+
+    for gens in self.font.regions(bank, ch.program, key, vel) {
+      let header = match self.font.headers.get(gens[SAMPLE_ID] as usize) {
+        Some(header) if header.sample_type & 0x8000 == 0 => header,
+        _ => continue,
+      };
+
+      let key = match gens[KEYNUM] {
+        k if k >= 0 => k as u8,
+        _ => key,
+      };
+      let vel = match gens[VELOCITY] {
+        v if v >= 0 => v as u8,
+        _ => vel,
+      };
+      let root = match gens[OVERRIDING_ROOT_KEY] {
+        r if r >= 0 => r,
+        _ => header.original_pitch as i32,
+      };
+
+      let cents = (key as i32 - root) * gens[SCALE_TUNING]
+        + gens[COARSE_TUNE] * 100
+        + gens[FINE_TUNE]
+        + header.pitch_correction as i32;
+      let step = 2f64.powf(cents as f64 / 1200.) * header.sample_rate as f64 / self.rate as f64;
+
+      let len = self.font.samples.len() as i64;
+      let addr = |base: u32, fine: usize, coarse: usize| {
+        (base as i64 + gens[fine] as i64 + gens[coarse] as i64 * 32768).clamp(0, len - 1) as f64
+      };
+      let start = addr(header.start, START_OFFSET, START_COARSE_OFFSET);
+      let end = addr(header.end, END_OFFSET, END_COARSE_OFFSET);
+      let loop_start = addr(
+        header.loop_start,
+        LOOP_START_OFFSET,
+        LOOP_START_COARSE_OFFSET,
+      );
+      let loop_end = addr(header.loop_end, LOOP_END_OFFSET, LOOP_END_COARSE_OFFSET);
+
+      // velocity follows the usual squared curve; attenuation is scaled the way most synths do
+      let gain =
+        (vel as f32 / 127.).powi(2) * centibels((gens[INITIAL_ATTENUATION] as f32 * 0.4) as i32);
+
+      if self.voices.len() >= MAX_VOICES {
+        self.voices.remove(0);
+      }
+      self.voices.push(Voice {
+        channel,
+        key,
+        position: start,
+        step,
+        end,
+        loop_start,
+        loop_end,
+        loop_mode: match loop_end > loop_start {
+          true => gens[SAMPLE_MODES],
+          false => 0,
+        },
+        released: false,
+        held: false,
+        gain,
+        pan: gens[PAN].clamp(-500, 500) as f32 / 500.,
+        envelope: Envelope::new(&gens, self.rate),
+      });
+    }
+  }
+
+  pub fn note_off(&mut self, channel: u8, key: u8) {
+    let sustain = self.channels[channel as usize].sustain;
+    for voice in &mut self.voices {
+      if voice.channel == channel && voice.key == key && !voice.released {
+        match sustain {
+          true => voice.held = true,
+          false => voice.release(),
+        }
+      }
+    }
+  }
+
+  pub fn controller(&mut self, channel: u8, controller: u8, value: u8) {
+    let ch = &mut self.channels[channel as usize];
+    let value_f = value as f32 / 127.;
+    match controller {
+      0 => ch.bank = value as u16,
+      6 if ch.rpn == (0, 0) => ch.bend_range = value as f32,
+      7 => ch.volume = value_f,
+      10 => ch.pan = value_f * 2. - 1.,
+      11 => ch.expression = value_f,
+      64 => {
+        ch.sustain = value >= 64;
+        if !ch.sustain {
+          for voice in &mut self.voices {
+            if voice.channel == channel && voice.held {
+              voice.held = false;
+              voice.release();
+            }
+          }
+        }
+      }
+      100 => ch.rpn.1 = value,
+      101 => ch.rpn.0 = value,
+      120 => self.voices.retain(|v| v.channel != channel),
+      121 => {
+        let program = ch.program;
+        *ch = Channel {
+          program,
+          ..Default::default()
+        };
+      }
+      123 => {
+        for voice in &mut self.voices {
+          if voice.channel == channel {
+            voice.release();
+          }
+        }
+      }
+      _ => {}
+    }
+  }
+
+  pub fn program_change(&mut self, channel: u8, program: u8) {
+    self.channels[channel as usize].program = program as u16;
+  }
+
+  // `bend` ranges from -1.0 to 1.0
+  pub fn pitch_bend(&mut self, channel: u8, bend: f32) {
+    let ch = &mut self.channels[channel as usize];
+    ch.bend = bend * ch.bend_range;
+  }
+
+  pub fn render(&mut self, out: &mut [f32]) {
+    out.iter_mut().for_each(|s| *s = 0.);
+    let samples = &self.font.samples;
+
+    for voice in &mut self.voices {
+      let ch = &self.channels[voice.channel as usize];
+      let step = voice.step * 2f64.powf(ch.bend as f64 / 12.);
+      let gain = voice.gain * ch.volume.powi(2) * ch.expression.powi(2);
+      let angle = ((voice.pan + ch.pan).clamp(-1., 1.) + 1.) * FRAC_PI_4;
+      let (left, right) = (angle.cos() * gain, angle.sin() * gain);
+
+      for frame in out.chunks_exact_mut(2) {
+        let level = voice.envelope.next();
+        if voice.envelope.stage == Stage::Done || voice.position >= voice.end {
+          voice.envelope.stage = Stage::Done;
+          break;
+        }
+
+        let index = voice.position as usize;
+        let frac = (voice.position - index as f64) as f32;
+        let s0 = samples[index] as f32;
+        let s1 = samples.get(index + 1).copied().unwrap_or(0) as f32;
+        let sample = (s0 + (s1 - s0) * frac) / 32768. * level;
+
+        frame[0] += sample * left;
+        frame[1] += sample * right;
+
+        voice.position += step;
+        if voice.looping() && voice.position >= voice.loop_end {
+          voice.position -= voice.loop_end - voice.loop_start;
+        }
+      }
+    }
+
+    self.voices.retain(|v| v.envelope.stage != Stage::Done);
+    out.iter_mut().for_each(|s| *s = s.clamp(-1., 1.));
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn chunk(id: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut chunk = id.to_vec();
+    chunk.extend((data.len() as u32).to_le_bytes());
+    chunk.extend(data);
+    chunk
+  }
+
+  #[test]
+  fn fonts_without_samples_are_rejected() {
+    let sdta = [b"sdta".to_vec(), chunk(b"smpl", &[])].concat();
+    let pdta = [
+      b"pdta".to_vec(),
+      chunk(b"phdr", &[0; 38]),
+      chunk(b"inst", &[0; 22]),
+      chunk(b"shdr", &[0; 46]),
+    ]
+    .concat();
+    let body = [
+      b"sfbk".to_vec(),
+      chunk(b"LIST", &sdta),
+      chunk(b"LIST", &pdta),
+    ]
+    .concat();
+    let error = SoundFont::parse(&chunk(b"RIFF", &body)).err().unwrap();
+    assert!(error.to_string().contains("no sample data"));
+  }
+}
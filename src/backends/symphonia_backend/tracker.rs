@@ -0,0 +1,1162 @@
+mod it;
+mod protracker;
+mod s3m;
+mod xm;
+
+use super::render::Renderer;
+use crate::Config;
+use std::{collections::HashSet, f64::consts::PI, sync::Arc};
+use symphonia::core::{
+  errors::{decode_error, Result},
+  meta::{MetadataBuilder, MetadataLog, StandardTagKey, Tag, Value},
+};
+
+const SAMPLE_RATE: u32 = 44100;
+// amiga clock in the period units shared by all formats (quarter amiga periods)
+const AMIGA_CLOCK: f64 = 14317456.;
+// songs that never end on their own are cut off here
+const MAX_SECS: u64 = 60 * 60;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Kind {
+  Mod,
+  S3m,
+  Xm,
+  It,
+}
+
+pub struct Module {
+  pub kind: Kind,
+  pub title: String,
+  // sample / instrument names, commonly used as a message board
+  pub texts: Vec<String>,
+  pub channels: usize,
+  pub orders: Vec<Order>,
+  pub restart: usize,
+  pub patterns: Vec<Pattern>,
+  pub instruments: Vec<Instrument>,
+  pub samples: Vec<Sample>,
+  pub speed: u8,
+  pub tempo: u8,
+  pub global_volume: f32,
+  pub pan: Vec<f32>, // -1.0 (left) to 1.0 (right)
+  pub channel_volume: Vec<f32>,
+  pub linear: bool,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Order {
+  Pattern(usize),
+  Skip,
+  End,
+}
+
+pub struct Pattern {
+  pub rows: usize,
+  pub cells: Vec<Cell>, // rows * channels
+}
+
+#[derive(Clone, Copy, Default)]
+pub struct Cell {
+  pub note: Note,
+  pub instrument: u8, // 1 based, 0 for none
+  pub volume: Volume,
+  pub effect: Effect,
+}
+
+// Notes count semitones from C-0, C-4 plays a sample at its c5 speed.
+#[derive(Clone, Copy, Default, PartialEq)]
+pub enum Note {
+  #[default]
+  None,
+  On(u8),
+  Off,
+  Cut,
+  Fade,
+}
+
+#[derive(Clone, Copy, Default)]
+pub enum Volume {
+  #[default]
+  None,
+  Set(u8),
+  Slide(i8),
+  FineSlide(i8),
+  Pan(u8), // 0 - 64
+  PortaDown(u8),
+  PortaUp(u8),
+  TonePorta(u8),
+  Vibrato(u8),
+  VibratoSpeed(u8),
+}
+
+#[derive(Clone, Copy, Default)]
+pub enum Effect {
+  #[default]
+  None,
+  Arpeggio(u8),
+  // s3m and it pack fine slides into these, see `Player::porta`
+  PortaUp(u8),
+  PortaDown(u8),
+  FinePortaUp(u8),
+  FinePortaDown(u8),
+  ExtraFinePortaUp(u8),
+  ExtraFinePortaDown(u8),
+  TonePorta(u8),
+  Vibrato(u8),
+  FineVibrato(u8),
+  Tremolo(u8),
+  TonePortaVolumeSlide(u8),
+  VibratoVolumeSlide(u8),
+  // xy: x up / y down, s3m and it also encode fine slides here
+  VolumeSlide(u8),
+  FineVolumeSlideUp(u8),
+  FineVolumeSlideDown(u8),
+  SetVolume(u8),
+  SetPan(u8), // 0 - 255
+  SampleOffset(u8),
+  PositionJump(u8),
+  PatternBreak(u8),
+  SetSpeed(u8),
+  SetTempo(u8),
+  SetGlobalVolume(u8), // 0 - 64
+  GlobalVolumeSlide(u8),
+  ChannelVolume(u8), // 0 - 64
+  ChannelVolumeSlide(u8),
+  Retrigger(u8),
+  NoteCut(u8),
+  NoteDelay(u8),
+  PatternLoop(u8),
+  PatternDelay(u8),
+  KeyOff(u8),
+}
+
+#[derive(Clone, Copy)]
+pub struct Loop {
+  pub start: usize,
+  pub end: usize,
+  pub ping_pong: bool,
+}
+
+pub struct Sample {
+  pub data: Vec<f32>,
+  pub c5_speed: f64,
+  pub volume: u8, // 0 - 64
+  pub global_volume: f32,
+  pub pan: Option<f32>,
+  pub looped: Option<Loop>,
+  pub sustain: Option<Loop>,
+}
+
+pub struct Envelope {
+  pub points: Vec<(u16, u8)>, // (tick, 0 - 64)
+  pub sustain: Option<(usize, usize)>,
+  pub looped: Option<(usize, usize)>,
+}
+
+impl Envelope {
+  fn value(&self, tick: u16) -> f32 {
+    let next = self.points.iter().position(|p| p.0 > tick);
+    let value = match next {
+      Some(0) => self.points[0].1 as f32,
+      Some(i) => {
+        let (a, b) = (self.points[i - 1], self.points[i]);
+        // broken modules can have points out of order
+        let t = tick.saturating_sub(a.0) as f32 / b.0.saturating_sub(a.0).max(1) as f32;
+        a.1 as f32 + (b.1 as f32 - a.1 as f32) * t
+      }
+      None => self.points.last().map(|p| p.1 as f32).unwrap_or(64.),
+    };
+    value / 64.
+  }
+
+  fn advance(&self, tick: u16, released: bool) -> u16 {
+    let tick = tick.saturating_add(1);
+    let wrap = match (self.sustain, self.looped) {
+      (Some(range), _) if !released => Some(range),
+      (_, looped) => looped,
+    };
+    if let Some((start, end)) = wrap {
+      if let (Some(start), Some(end)) = (self.points.get(start), self.points.get(end)) {
+        if tick > end.0 {
+          return start.0;
+        }
+      }
+    }
+    tick.min(self.points.last().map(|p| p.0).unwrap_or(0))
+  }
+}
+
+pub struct Instrument {
+  pub keymap: Vec<(u8, u8)>, // note -> (note, 1 based sample)
+  pub volume_envelope: Option<Envelope>,
+  pub fadeout: f32, // per tick, of 1.0
+}
+
+impl Instrument {
+  // Mod and s3m files have one instrument per sample.
+  fn for_sample(sample: usize) -> Self {
+    Self {
+      keymap: (0..120).map(|n| (n, sample as u8 + 1)).collect(),
+      volume_envelope: None,
+      fadeout: 0.,
+    }
+  }
+}
+
+#[derive(Default, Clone)]
+struct Channel {
+  sample: Option<usize>,
+  instrument: Option<usize>,
+  note: u8,
+  position: f64,
+  backwards: bool,
+  playing: bool,
+  period: f64,
+  target_period: f64,
+  volume: i32, // 0 - 64
+  channel_volume: f32,
+  pan: f32,
+  released: bool,
+  fade: f32,
+  envelope_tick: u16,
+
+  // per tick modulation
+  period_offset: f64,
+  volume_offset: i32,
+  arpeggio: u8,
+
+  // effect memory
+  porta_up: u8,
+  porta_down: u8,
+  tone_porta: u8,
+  vibrato: u8,
+  vibrato_phase: u8,
+  tremolo: u8,
+  tremolo_phase: u8,
+  volume_slide: u8,
+  sample_offset: u8,
+  retrigger: u8,
+  channel_volume_slide: u8,
+  loop_row: usize,
+  loop_count: u8,
+
+  delayed: Option<Cell>,
+}
+
+fn sine(phase: u8) -> f64 {
+  (phase as f64 / 64. * 2. * PI).sin()
+}
+
+pub struct Player {
+  module: Arc<Module>,
+  rate: f64,
+  channels: Vec<Channel>,
+  order: usize,
+  row: usize,
+  tick: u8,
+  speed: u8,
+  tempo: u8,
+  global_volume: f32,
+  global_volume_slide: u8,
+  // extra times the current row is played (pattern delay)
+  repeats: u8,
+  repeating: bool,
+  jump: Option<(usize, usize)>,
+  visited: HashSet<(usize, usize)>,
+  loops_left: u32,
+  ended: bool,
+}
+
+impl Player {
+  pub fn new(module: Arc<Module>, loops: u32) -> Self {
+    let channels = (0..module.channels)
+      .map(|c| Channel {
+        pan: module.pan.get(c).copied().unwrap_or(0.),
+        channel_volume: module.channel_volume.get(c).copied().unwrap_or(1.),
+        ..Default::default()
+      })
+      .collect();
+
+    let mut player = Self {
+      rate: SAMPLE_RATE as f64,
+      channels,
+      order: 0,
+      row: 0,
+      tick: 0,
+      speed: module.speed.max(1),
+      tempo: module.tempo.max(32),
+      global_volume: module.global_volume,
+      global_volume_slide: 0,
+      repeats: 0,
+      repeating: false,
+      jump: None,
+      visited: HashSet::new(),
+      loops_left: loops,
+      ended: false,
+      module,
+    };
+    match player.next_order(0) {
+      Some(order) => player.order = order,
+      None => player.ended = true,
+    }
+    player
+  }
+
+  fn next_order(&self, mut order: usize) -> Option<usize> {
+    loop {
+      match self.module.orders.get(order) {
+        Some(Order::Pattern(p)) if *p < self.module.patterns.len() => return Some(order),
+        Some(Order::End) | None => return None,
+        _ => order += 1,
+      }
+    }
+  }
+
+  fn pattern(&self) -> Option<&Pattern> {
+    match self.module.orders.get(self.order) {
+      Some(Order::Pattern(p)) => self.module.patterns.get(*p),
+      _ => None,
+    }
+  }
+
+  // Runs one tick of the sequencer, returning how many frames it lasts.
+  fn tick(&mut self) -> usize {
+    if self.ended {
+      return 0;
+    }
+
+    if self.tick == 0 && !self.repeating {
+      self.row();
+    } else {
+      for c in 0..self.channels.len() {
+        self.effects(c, self.tick);
+      }
+    }
+
+    for c in 0..self.channels.len() {
+      self.envelopes(c);
+    }
+
+    // tempo is in bpm, with 24 ticks to the beat
+    let frames = (self.rate * 2.5 / self.tempo as f64) as usize;
+
+    self.tick += 1;
+    if self.tick >= self.speed {
+      self.tick = 0;
+      self.repeating = self.repeats > 0;
+      match self.repeating {
+        true => self.repeats -= 1,
+        false => self.advance(),
+      }
+    }
+
+    frames
+  }
+
+  fn advance(&mut self) {
+    let looping = self.channels.iter().any(|c| c.loop_count > 0);
+    let (order, row) = match self.jump.take() {
+      Some(jump) => jump,
+      None => (self.order, self.row + 1),
+    };
+
+    let rows = match self.module.orders.get(order) {
+      Some(Order::Pattern(p)) => self.module.patterns.get(*p).map(|p| p.rows).unwrap_or(0),
+      _ => 0,
+    };
+    let (order, row) = match row >= rows {
+      true => (order + 1, 0),
+      false => (order, row),
+    };
+
+    let next = self
+      .next_order(order)
+      .map(|order| (order, row))
+      .filter(|position| looping || !self.visited.contains(position));
+
+    match next {
+      Some((order, row)) => {
+        if !looping {
+          self.visited.insert((self.order, self.row));
+        }
+        self.order = order;
+        self.row = row;
+      }
+      None if self.loops_left > 0 => {
+        self.loops_left -= 1;
+        self.visited.clear();
+        let (order, row) = match self.next_order(order) {
+          Some(order) => (order, row),
+          None => (self.next_order(self.module.restart).unwrap_or(0), 0),
+        };
+        self.order = order;
+        self.row = row;
+      }
+      None => self.ended = true,
+    }
+  }
+
+  fn row(&mut self) {
+    let pattern = match self.pattern() {
+      Some(pattern) => pattern,
+      None => return,
+    };
+    let channels = self.module.channels;
+    let cells: Vec<Cell> = pattern.cells[self.row * channels..(self.row + 1) * channels].to_vec();
+
+    for (c, cell) in cells.into_iter().enumerate() {
+      let delayed = match cell.effect {
+        Effect::NoteDelay(d) if d > 0 => d < self.speed,
+        _ => false,
+      };
+      match delayed {
+        true => self.channels[c].delayed = Some(cell),
+        false => self.trigger(c, &cell),
+      }
+      self.row_effects(c, &cell);
+    }
+  }
+
+  fn trigger(&mut self, c: usize, cell: &Cell) {
+    let module = self.module.clone();
+    let tone_porta = matches!(
+      cell.effect,
+      Effect::TonePorta(_) | Effect::TonePortaVolumeSlide(_)
+    ) || matches!(cell.volume, Volume::TonePorta(_));
+
+    if cell.instrument > 0 {
+      let instrument = cell.instrument as usize - 1;
+      let ch = &mut self.channels[c];
+      if instrument < module.instruments.len() {
+        ch.instrument = Some(instrument);
+      }
+      // instruments reset the volume of the sample they'd play
+      let note = match cell.note {
+        Note::On(n) => n,
+        _ => ch.note,
+      };
+      if let Some(sample) = self.sample_for(c, note) {
+        let sample = &module.samples[sample];
+        let ch = &mut self.channels[c];
+        ch.volume = sample.volume as i32;
+        if let Some(pan) = sample.pan {
+          ch.pan = pan;
+        }
+        ch.released = false;
+        ch.fade = 1.;
+        ch.envelope_tick = 0;
+      }
+    }
+
+    match cell.note {
+      Note::On(note) => {
+        let sample_index = match self.sample_for(c, note) {
+          Some(sample) => sample,
+          None => return,
+        };
+        let mapped = self.mapped_note(c, note);
+        let sample = &module.samples[sample_index];
+        let period = self.period(mapped, sample.c5_speed);
+        let ch = &mut self.channels[c];
+
+        if tone_porta && ch.playing {
+          ch.target_period = period;
+          return;
+        }
+
+        ch.note = note;
+        ch.sample = Some(sample_index);
+        ch.period = period;
+        ch.target_period = period;
+        ch.position = 0.;
+        ch.backwards = false;
+        ch.playing = true;
+        ch.released = false;
+        ch.fade = 1.;
+        ch.envelope_tick = 0;
+        ch.vibrato_phase = 0;
+        ch.tremolo_phase = 0;
+      }
+      Note::Off => self.key_off(c),
+      Note::Cut => {
+        let ch = &mut self.channels[c];
+        ch.volume = 0;
+        ch.playing = false;
+      }
+      Note::Fade => self.channels[c].released = true,
+      Note::None => {}
+    }
+  }
+
+  fn key_off(&mut self, c: usize) {
+    let has_envelope = self
+      .instrument(c)
+      .and_then(|i| i.volume_envelope.as_ref())
+      .is_some();
+    let ch = &mut self.channels[c];
+    ch.released = true;
+    if !has_envelope && self.module.kind == Kind::Xm {
+      ch.volume = 0;
+    }
+  }
+
+  fn instrument(&self, c: usize) -> Option<&Instrument> {
+    self.module.instruments.get(self.channels[c].instrument?)
+  }
+
+  fn sample_for(&self, c: usize, note: u8) -> Option<usize> {
+    let (_, sample) = *self.instrument(c)?.keymap.get(note as usize)?;
+    let sample = (sample as usize).checked_sub(1)?;
+    match sample < self.module.samples.len() {
+      true => Some(sample),
+      false => None,
+    }
+  }
+
+  fn mapped_note(&self, c: usize, note: u8) -> u8 {
+    match self.instrument(c).and_then(|i| i.keymap.get(note as usize)) {
+      Some((mapped, _)) => *mapped,
+      None => note,
+    }
+  }
+
+  fn period(&self, note: u8, c5_speed: f64) -> f64 {
+    match self.module.linear {
+      true => (96. - note as f64) * 64.,
+      false => AMIGA_CLOCK / (c5_speed * 2f64.powf((note as f64 - 48.) / 12.)),
+    }
+  }
+
+  fn frequency(&self, c: usize) -> f64 {
+    let ch = &self.channels[c];
+    let (min, max) = self.period_range();
+    let period = (ch.period + ch.period_offset).clamp(min, max);
+    let frequency = match (self.module.linear, ch.sample) {
+      (true, Some(sample)) => {
+        self.module.samples[sample].c5_speed * 2f64.powf((3072. - period) / 768.)
+      }
+      (true, None) => 0.,
+      (false, _) => AMIGA_CLOCK / period,
+    };
+    frequency * 2f64.powf(ch.arpeggio as f64 / 12.)
+  }
+
+  // Positive units slide the pitch up.
+  fn slide(&mut self, c: usize, units: f64) {
+    let (min, max) = self.period_range();
+    let ch = &mut self.channels[c];
+    ch.period = (ch.period - units).clamp(min, max);
+  }
+
+  fn period_range(&self) -> (f64, f64) {
+    match self.module.linear {
+      true => (-1536., 7680.),
+      false => (1., 32000.),
+    }
+  }
+
+  fn volume_slide(&mut self, c: usize, param: u8, tick: u8) {
+    let ch = &mut self.channels[c];
+    if param != 0 {
+      ch.volume_slide = param;
+    }
+    let (up, down) = (
+      (ch.volume_slide >> 4) as i32,
+      (ch.volume_slide & 0xf) as i32,
+    );
+    let fine_slides = matches!(self.module.kind, Kind::S3m | Kind::It);
+
+    let delta = match (fine_slides, up, down) {
+      // DxF and DFy are fine slides, applied on the first tick only
+      (true, up, 0xf) if up != 0 => match tick {
+        0 => up,
+        _ => 0,
+      },
+      (true, 0xf, down) if down != 0 => match tick {
+        0 => -down,
+        _ => 0,
+      },
+      _ if tick == 0 => 0,
+      (_, up, _) if up != 0 => up,
+      (_, _, down) => -down,
+    };
+    ch.volume = (ch.volume + delta).clamp(0, 64);
+  }
+
+  fn porta(&mut self, c: usize, param: u8, up: bool, tick: u8) {
+    let ch = &mut self.channels[c];
+    let memory = match up {
+      true => &mut ch.porta_up,
+      false => &mut ch.porta_down,
+    };
+    if param != 0 {
+      *memory = param;
+    }
+    let param = *memory;
+    let sign = if up { 1. } else { -1. };
+
+    // s3m and it encode fine (Fx) and extra fine (Ex) slides in the same effect
+    let units = match (self.module.kind, param >> 4) {
+      (Kind::S3m | Kind::It, 0xf) if tick == 0 => (param & 0xf) as f64 * 4.,
+      (Kind::S3m | Kind::It, 0xe) if tick == 0 => (param & 0xf) as f64,
+      (Kind::S3m | Kind::It, 0xe | 0xf) => 0.,
+      (_, _) if tick == 0 => 0.,
+      (_, _) => param as f64 * 4.,
+    };
+    self.slide(c, units * sign);
+  }
+
+  fn tone_porta(&mut self, c: usize, param: u8) {
+    let ch = &mut self.channels[c];
+    if param != 0 {
+      ch.tone_porta = param;
+    }
+    let speed = ch.tone_porta as f64 * 4.;
+    ch.period = match ch.period < ch.target_period {
+      true => (ch.period + speed).min(ch.target_period),
+      false => (ch.period - speed).max(ch.target_period),
+    };
+  }
+
+  fn vibrato(&mut self, c: usize, param: u8, fine: bool) {
+    let ch = &mut self.channels[c];
+    if param >> 4 != 0 {
+      ch.vibrato = (ch.vibrato & 0x0f) | (param & 0xf0);
+    }
+    if param & 0xf != 0 {
+      ch.vibrato = (ch.vibrato & 0xf0) | (param & 0x0f);
+    }
+    let depth = (ch.vibrato & 0xf) as f64 * if fine { 1. } else { 4. };
+    ch.period_offset = sine(ch.vibrato_phase) * depth * 2.;
+    ch.vibrato_phase = ch.vibrato_phase.wrapping_add(ch.vibrato >> 4) % 64;
+  }
+
+  // Effects that happen once, on the first tick of a row.
+  fn row_effects(&mut self, c: usize, cell: &Cell) {
+    {
+      let ch = &mut self.channels[c];
+      ch.period_offset = 0.;
+      ch.volume_offset = 0;
+      ch.arpeggio = 0;
+    }
+
+    match cell.volume {
+      Volume::Set(v) => self.channels[c].volume = v.min(64) as i32,
+      Volume::FineSlide(d) => {
+        let ch = &mut self.channels[c];
+        ch.volume = (ch.volume + d as i32).clamp(0, 64);
+      }
+      Volume::Pan(p) => self.channels[c].pan = p.min(64) as f32 / 32. - 1.,
+      Volume::VibratoSpeed(s) => {
+        let ch = &mut self.channels[c];
+        ch.vibrato = (ch.vibrato & 0x0f) | (s << 4);
+      }
+      _ => {}
+    }
+
+    match cell.effect {
+      Effect::FinePortaUp(p) => self.slide(c, p as f64 * 4.),
+      Effect::FinePortaDown(p) => self.slide(c, -(p as f64) * 4.),
+      Effect::ExtraFinePortaUp(p) => self.slide(c, p as f64),
+      Effect::ExtraFinePortaDown(p) => self.slide(c, -(p as f64)),
+      Effect::PortaUp(p) => self.porta(c, p, true, 0),
+      Effect::PortaDown(p) => self.porta(c, p, false, 0),
+      Effect::VolumeSlide(p) | Effect::TonePortaVolumeSlide(p) | Effect::VibratoVolumeSlide(p) => {
+        self.volume_slide(c, p, 0)
+      }
+      Effect::FineVolumeSlideUp(p) => {
+        let ch = &mut self.channels[c];
+        ch.volume = (ch.volume + p as i32).min(64);
+      }
+      Effect::FineVolumeSlideDown(p) => {
+        let ch = &mut self.channels[c];
+        ch.volume = (ch.volume - p as i32).max(0);
+      }
+      Effect::SetVolume(v) => self.channels[c].volume = v.min(64) as i32,
+      Effect::SetPan(p) => self.channels[c].pan = p as f32 / 127.5 - 1.,
+      Effect::SampleOffset(o) => {
+        let ch = &mut self.channels[c];
+        if o != 0 {
+          ch.sample_offset = o;
+        }
+        if matches!(cell.note, Note::On(_)) {
+          ch.position = ch.sample_offset as f64 * 256.;
+        }
+      }
+      Effect::PositionJump(order) => self.jump = Some((order as usize, 0)),
+      Effect::PatternBreak(row) => {
+        let order = match self.jump {
+          Some((order, _)) => order,
+          None => self.order + 1,
+        };
+        self.jump = Some((order, row as usize));
+      }
+      Effect::SetSpeed(s) if s > 0 => self.speed = s,
+      Effect::SetTempo(t) if t >= 32 => self.tempo = t,
+      Effect::SetGlobalVolume(v) => self.global_volume = v.min(64) as f32 / 64.,
+      Effect::GlobalVolumeSlide(p) if p != 0 => self.global_volume_slide = p,
+      Effect::ChannelVolume(v) => self.channels[c].channel_volume = v.min(64) as f32 / 64.,
+      Effect::ChannelVolumeSlide(p) if p != 0 => self.channels[c].channel_volume_slide = p,
+      Effect::PatternLoop(0) => self.channels[c].loop_row = self.row,
+      Effect::PatternLoop(count) => {
+        let ch = &mut self.channels[c];
+        match ch.loop_count {
+          0 => ch.loop_count = count,
+          1 => {
+            ch.loop_count = 0;
+            return;
+          }
+          _ => ch.loop_count -= 1,
+        }
+        self.jump = Some((self.order, ch.loop_row));
+      }
+      Effect::PatternDelay(rows) => self.repeats = rows,
+      Effect::Retrigger(p) if p != 0 => self.channels[c].retrigger = p,
+      Effect::KeyOff(0) => self.key_off(c),
+      Effect::NoteCut(0) => self.channels[c].volume = 0,
+      Effect::Arpeggio(_) | Effect::Vibrato(_) | Effect::FineVibrato(_) | Effect::Tremolo(_) => {
+        self.effects(c, 0)
+      }
+      _ => {}
+    }
+  }
+
+  // Effects that update every tick after the first.
+  fn effects(&mut self, c: usize, tick: u8) {
+    let cell = match self.pattern() {
+      Some(pattern) => pattern.cells[self.row * self.module.channels + c],
+      None => return,
+    };
+
+    {
+      let ch = &mut self.channels[c];
+      ch.period_offset = 0.;
+      ch.volume_offset = 0;
+      ch.arpeggio = 0;
+    }
+
+    if tick != 0 {
+      match cell.volume {
+        Volume::Slide(d) => {
+          let ch = &mut self.channels[c];
+          ch.volume = (ch.volume + d as i32).clamp(0, 64);
+        }
+        Volume::PortaUp(p) => self.slide(c, p as f64 * 4.),
+        Volume::PortaDown(p) => self.slide(c, -(p as f64) * 4.),
+        Volume::TonePorta(p) => self.tone_porta(c, p),
+        Volume::Vibrato(d) => self.vibrato(c, d, false),
+        _ => {}
+      }
+    }
+
+    match cell.effect {
+      Effect::Arpeggio(p) if p != 0 => {
+        self.channels[c].arpeggio = match tick % 3 {
+          0 => 0,
+          1 => p >> 4,
+          _ => p & 0xf,
+        }
+      }
+      Effect::PortaUp(p) => self.porta(c, p, true, tick),
+      Effect::PortaDown(p) => self.porta(c, p, false, tick),
+      Effect::TonePorta(p) if tick != 0 => self.tone_porta(c, p),
+      Effect::Vibrato(p) => self.vibrato(c, p, false),
+      Effect::FineVibrato(p) => self.vibrato(c, p, true),
+      Effect::Tremolo(p) => {
+        let ch = &mut self.channels[c];
+        if p != 0 {
+          ch.tremolo = p;
+        }
+        ch.volume_offset = (sine(ch.tremolo_phase) * (ch.tremolo & 0xf) as f64 * 4.) as i32;
+        ch.tremolo_phase = ch.tremolo_phase.wrapping_add(ch.tremolo >> 4) % 64;
+      }
+      Effect::VolumeSlide(p) if tick != 0 => self.volume_slide(c, p, tick),
+      Effect::TonePortaVolumeSlide(p) if tick != 0 => {
+        self.tone_porta(c, 0);
+        self.volume_slide(c, p, tick);
+      }
+      Effect::VibratoVolumeSlide(p) => {
+        self.vibrato(c, 0, false);
+        if tick != 0 {
+          self.volume_slide(c, p, tick);
+        }
+      }
+      Effect::GlobalVolumeSlide(_) if tick != 0 => {
+        let p = self.global_volume_slide;
+        let delta = match p >> 4 {
+          0 => -((p & 0xf) as f32),
+          up => up as f32,
+        };
+        self.global_volume = (self.global_volume + delta / 64.).clamp(0., 1.);
+      }
+      Effect::ChannelVolumeSlide(_) if tick != 0 => {
+        let ch = &mut self.channels[c];
+        let p = ch.channel_volume_slide;
+        let delta = match p >> 4 {
+          0 => -((p & 0xf) as f32),
+          up => up as f32,
+        };
+        ch.channel_volume = (ch.channel_volume + delta / 64.).clamp(0., 1.);
+      }
+      Effect::Retrigger(_) if tick != 0 => {
+        let ch = &mut self.channels[c];
+        let interval = (ch.retrigger & 0xf).max(1);
+        if tick.is_multiple_of(interval) {
+          ch.position = 0.;
+          ch.backwards = false;
+          ch.volume = match ch.retrigger >> 4 {
+            1..=5 => ch.volume - (1 << ((ch.retrigger >> 4) - 1)),
+            6 => ch.volume * 2 / 3,
+            7 => ch.volume / 2,
+            9..=0xd => ch.volume + (1 << ((ch.retrigger >> 4) - 9)),
+            0xe => ch.volume * 3 / 2,
+            0xf => ch.volume * 2,
+            _ => ch.volume,
+          }
+          .clamp(0, 64);
+        }
+      }
+      Effect::NoteCut(t) if t == tick => self.channels[c].volume = 0,
+      Effect::KeyOff(t) if t == tick && tick != 0 => self.key_off(c),
+      Effect::NoteDelay(t) if t == tick => {
+        if let Some(cell) = self.channels[c].delayed.take() {
+          self.trigger(c, &cell);
+        }
+      }
+      _ => {}
+    }
+  }
+
+  fn envelopes(&mut self, c: usize) {
+    let module = self.module.clone();
+    let instrument = self.channels[c]
+      .instrument
+      .and_then(|i| module.instruments.get(i));
+    let ch = &mut self.channels[c];
+
+    if let Some(instrument) = instrument {
+      if let Some(envelope) = &instrument.volume_envelope {
+        ch.envelope_tick = envelope.advance(ch.envelope_tick, ch.released);
+      }
+      if ch.released {
+        ch.fade = (ch.fade - instrument.fadeout).max(0.);
+      }
+    }
+  }
+
+  fn gain(&self, c: usize) -> f32 {
+    let ch = &self.channels[c];
+    let sample = match ch.sample {
+      Some(sample) => &self.module.samples[sample],
+      None => return 0.,
+    };
+    let envelope = match self.instrument(c).and_then(|i| i.volume_envelope.as_ref()) {
+      Some(envelope) => envelope.value(ch.envelope_tick),
+      None => 1.,
+    };
+    let volume = (ch.volume + ch.volume_offset).clamp(0, 64) as f32 / 64.;
+    volume * envelope * ch.fade * ch.channel_volume * sample.global_volume * self.global_volume
+  }
+
+  // Mixes (or with `out: None`, just advances) every channel over `frames`.
+  fn mix(&mut self, mut out: Option<&mut [f32]>, frames: usize) {
+    let module = self.module.clone();
+    let amplify = 1. / (module.channels as f32).sqrt().max(1.);
+
+    for c in 0..self.channels.len() {
+      if !self.channels[c].playing {
+        continue;
+      }
+      let step = self.frequency(c) / self.rate;
+      let gain = self.gain(c) * amplify;
+      let ch = &mut self.channels[c];
+      let sample = match ch.sample {
+        Some(sample) => &module.samples[sample],
+        None => continue,
+      };
+      let looped = match (ch.released, sample.sustain) {
+        (false, Some(sustain)) => Some(sustain),
+        _ => sample.looped,
+      };
+      let (left, right) = (((1. - ch.pan) / 2.).sqrt(), ((1. + ch.pan) / 2.).sqrt());
+
+      for frame in 0..frames {
+        let index = ch.position as usize;
+        if index >= sample.data.len() {
+          ch.playing = false;
+          break;
+        }
+
+        if let Some(out) = out.as_deref_mut() {
+          let frac = (ch.position - index as f64) as f32;
+          let s0 = sample.data[index];
+          let s1 = sample.data.get(index + 1).copied().unwrap_or(s0);
+          let value = (s0 + (s1 - s0) * frac) * gain;
+          out[frame * 2] += value * left;
+          out[frame * 2 + 1] += value * right;
+        }
+
+        match ch.backwards {
+          true => ch.position -= step,
+          false => ch.position += step,
+        }
+
+        if let Some(l) = looped {
+          if ch.backwards && ch.position < l.start as f64 {
+            ch.position = 2. * l.start as f64 - ch.position;
+            ch.backwards = false;
+          } else if !ch.backwards && ch.position >= l.end as f64 {
+            match l.ping_pong {
+              true => {
+                ch.position = 2. * l.end as f64 - ch.position - 1.;
+                ch.backwards = true;
+              }
+              false => ch.position -= (l.end - l.start).max(1) as f64,
+            }
+          }
+        }
+      }
+    }
+  }
+}
+
+pub struct Tracker {
+  module: Arc<Module>,
+  player: Player,
+  loops: u32,
+  frames: u64,
+  position: u64,
+  // frames left in the current tick
+  remaining: usize,
+}
+
+// How much of a file `kind` needs to look at.
+pub const HEADER_LEN: u64 = 1084;
+
+pub fn kind(header: &[u8]) -> Option<Kind> {
+  if header.get(0x2c..0x30) == Some(b"SCRM") {
+    return Some(Kind::S3m);
+  }
+  if header.starts_with(b"Extended Module: ") {
+    return Some(Kind::Xm);
+  }
+  if header.starts_with(b"IMPM") {
+    return Some(Kind::It);
+  }
+  protracker::channels(header).map(|_| Kind::Mod)
+}
+
+fn load(data: &[u8]) -> Option<Module> {
+  match kind(data)? {
+    Kind::Mod => protracker::load(data),
+    Kind::S3m => s3m::load(data),
+    Kind::Xm => xm::load(data),
+    Kind::It => it::load(data),
+  }
+}
+
+impl Tracker {
+  fn new(module: Arc<Module>, loops: u32) -> Self {
+    Self {
+      frames: Self::length(&module, loops),
+      player: Player::new(module.clone(), loops),
+      module,
+      loops,
+      position: 0,
+      remaining: 0,
+    }
+  }
+
+  fn length(module: &Arc<Module>, loops: u32) -> u64 {
+    let mut player = Player::new(module.clone(), loops);
+    let mut frames = 0;
+    while !player.ended && frames < MAX_SECS * SAMPLE_RATE as u64 {
+      frames += player.tick() as u64;
+    }
+    frames
+  }
+}
+
+impl Renderer for Tracker {
+  fn open(data: Vec<u8>) -> Result<Self> {
+    let module = match load(&data) {
+      Some(module) => Arc::new(module),
+      None => return decode_error("tracker: unsupported module"),
+    };
+    Ok(Self::new(
+      module,
+      Config::load().unwrap_or_default().module_loops,
+    ))
+  }
+
+  fn sample_rate(&self) -> u32 {
+    SAMPLE_RATE
+  }
+
+  fn frames(&self) -> u64 {
+    self.frames
+  }
+
+  fn render(&mut self, out: &mut [f32]) {
+    out.iter_mut().for_each(|s| *s = 0.);
+    let frames = out.len() / 2;
+    let mut done = 0;
+
+    while done < frames {
+      if self.remaining == 0 {
+        self.remaining = self.player.tick();
+        if self.remaining == 0 {
+          break;
+        }
+      }
+      let n = self.remaining.min(frames - done);
+      self.player.mix(Some(&mut out[done * 2..(done + n) * 2]), n);
+      self.remaining -= n;
+      done += n;
+    }
+
+    out.iter_mut().for_each(|s| *s = s.clamp(-1., 1.));
+    self.position += frames as u64;
+  }
+
+  fn seek(&mut self, frame: u64) {
+    self.player = Player::new(self.module.clone(), self.loops);
+    self.position = 0;
+    self.remaining = 0;
+
+    while self.position < frame {
+      if self.remaining == 0 {
+        self.remaining = self.player.tick();
+        if self.remaining == 0 {
+          break;
+        }
+      }
+      let n = self.remaining.min((frame - self.position) as usize);
+      self.player.mix(None, n);
+      self.remaining -= n;
+      self.position += n as u64;
+    }
+    self.position = frame;
+  }
+
+  fn metadata(&self) -> MetadataLog {
+    let mut builder = MetadataBuilder::new();
+    if !self.module.title.is_empty() {
+      builder.add_tag(Tag::new(
+        Some(StandardTagKey::TrackTitle),
+        "TITLE",
+        Value::from(self.module.title.as_str()),
+      ));
+    }
+    // sample and instrument names are where modules keep their messages,
+    // a line each
+    let texts: Vec<&str> = self
+      .module
+      .texts
+      .iter()
+      .map(String::as_str)
+      .filter(|t| !t.is_empty())
+      .collect();
+    if !texts.is_empty() {
+      builder.add_tag(Tag::new(
+        Some(StandardTagKey::Comment),
+        "COMMENT",
+        Value::from(texts.join("\n")),
+      ));
+    }
+
+    let mut log = MetadataLog::default();
+    log.push(builder.metadata());
+    log
+  }
+}
+
+// Fixed length, nul padded strings as found in module headers.
+pub fn text(bytes: &[u8]) -> String {
+  let end = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+  String::from_utf8_lossy(&bytes[..end])
+    .chars()
+    .map(|c| if c.is_control() { ' ' } else { c })
+    .collect::<String>()
+    .trim_end()
+    .to_owned()
+}
+
+pub fn u16_le(data: &[u8], at: usize) -> u16 {
+  data
+    .get(at..at + 2)
+    .map(|b| u16::from_le_bytes([b[0], b[1]]))
+    .unwrap_or(0)
+}
+
+pub fn u32_le(data: &[u8], at: usize) -> u32 {
+  data
+    .get(at..at + 4)
+    .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // one pattern playing a looped square wave on the first channel
+  fn module() -> Vec<u8> {
+    let mut data = vec![0; 1084 + 1024];
+    data[..4].copy_from_slice(b"test");
+    let sample = &mut data[20..50];
+    sample[22..24].copy_from_slice(&16u16.to_be_bytes());
+    sample[25] = 64;
+    sample[28..30].copy_from_slice(&16u16.to_be_bytes());
+    data[950] = 1;
+    data[1080..1084].copy_from_slice(b"M.K.");
+    data[1084..1088].copy_from_slice(&[0x01, 0xac, 0x10, 0x00]);
+    data.extend((0..32).map(|i| if i < 16 { 0x40 } else { 0xc0 }));
+    data
+  }
+
+  #[test]
+  fn mod_plays_for_its_pattern_length() {
+    let mut tracker = Tracker::new(Arc::new(load(&module()).unwrap()), 0);
+    // 64 rows of 6 ticks, 125bpm ticks last 882 frames
+    assert_eq!(tracker.frames(), 64 * 6 * 882);
+    assert_eq!(tracker.module.title, "test");
+
+    let mut out = vec![0.; 2048];
+    tracker.render(&mut out);
+    assert!(out.iter().any(|s| s.abs() > 0.1));
+  }
+
+  #[test]
+  fn sample_names_make_up_the_comment() {
+    let mut data = module();
+    data[20..25].copy_from_slice(b"hello");
+    data[50..55].copy_from_slice(b"world");
+    let tracker = Tracker::new(Arc::new(load(&data).unwrap()), 0);
+
+    let mut log = tracker.metadata();
+    let revision = log.metadata().skip_to_latest().cloned().unwrap();
+    let comments: Vec<String> = revision
+      .tags()
+      .iter()
+      .filter(|tag| tag.std_key == Some(StandardTagKey::Comment))
+      .map(|tag| tag.value.to_string())
+      .collect();
+    assert_eq!(comments, ["hello\nworld"]);
+  }
+
+  #[test]
+  fn envelopes_out_of_order_dont_panic() {
+    let envelope = Envelope {
+      points: vec![(10, 64), (5, 0), (20, 32)],
+      sustain: None,
+      looped: None,
+    };
+    for tick in 0..25 {
+      let value = envelope.value(tick);
+      assert!((0. ..=1.).contains(&value));
+      envelope.advance(tick, false);
+    }
+  }
+}
@@ -0,0 +1,420 @@
+use super::*;
+
+// impulse tracker's notes start an octave lower, its C-5 plays at the c5 speed
+const OCTAVE: u8 = 12;
+
+struct Bits<'a> {
+  data: &'a [u8],
+  position: usize, // in bits
+}
+
+impl Bits<'_> {
+  fn read(&mut self, width: u8) -> u32 {
+    let mut value = 0;
+    for i in 0..width as u32 {
+      let (byte, bit) = (self.position / 8, self.position % 8);
+      let set = self.data.get(byte).map(|b| b >> bit & 1).unwrap_or(0);
+      value |= (set as u32) << i;
+      self.position += 1;
+    }
+    value
+  }
+}
+
+// IT214 / IT215 sample compression: blocks of variable width deltas, with
+// IT215 integrating them twice. `bits` is 8 or 16.
+fn decompress(data: &[u8], length: usize, bits: u8, it215: bool) -> Vec<f32> {
+  let block_samples = if bits == 8 { 0x8000 } else { 0x4000 };
+  let scale = (1u32 << (bits - 1)) as f32;
+  let mut out = Vec::with_capacity(length);
+  let mut offset = 0;
+
+  while out.len() < length {
+    let size = u16_le(data, offset) as usize;
+    let block = match data.get(offset + 2..offset + 2 + size) {
+      Some(block) => block,
+      None => break,
+    };
+    offset += 2 + size;
+
+    let mut reader = Bits {
+      data: block,
+      position: 0,
+    };
+    let mut width = bits + 1;
+    let (mut d1, mut d2) = (0i32, 0i32);
+    let count = block_samples.min(length - out.len());
+    let mut done = 0;
+
+    while done < count && reader.position < block.len() * 8 {
+      let value = reader.read(width);
+
+      // values in a band at the top of the range change the width instead
+      if width < 7 {
+        if value == 1 << (width - 1) {
+          let new = reader.read(if bits == 8 { 3 } else { 4 }) as u8 + 1;
+          width = if new < width { new } else { new + 1 };
+          continue;
+        }
+      } else if width < bits + 1 {
+        let border = (((1u32 << bits) - 1) >> (bits + 1 - width)) - bits as u32 / 2;
+        if value > border && value <= border + bits as u32 {
+          let new = (value - border) as u8;
+          width = if new < width { new } else { new + 1 };
+          continue;
+        }
+      } else if width == bits + 1 {
+        if value & (1 << bits) != 0 {
+          width = (value + 1) as u8;
+          continue;
+        }
+      } else {
+        break;
+      }
+
+      // sign extend from the current width
+      let shift = 32 - width.min(bits) as u32;
+      let delta = ((value << shift) as i32) >> shift;
+      d1 = d1.wrapping_add(delta);
+      d2 = d2.wrapping_add(d1);
+      let sample = if it215 { d2 } else { d1 };
+      let sample = match bits {
+        8 => sample as i8 as f32,
+        _ => sample as i16 as f32,
+      };
+      out.push(sample / scale);
+      done += 1;
+    }
+
+    if size == 0 {
+      break;
+    }
+  }
+
+  out.resize(length, 0.);
+  out
+}
+
+fn sample(data: &[u8], at: usize, texts: &mut Vec<String>) -> Option<Sample> {
+  let header = data.get(at..at + 0x50)?;
+  if &header[..4] != b"IMPS" {
+    return None;
+  }
+  texts.push(text(&header[0x14..0x2e]));
+
+  let flags = header[0x12];
+  let convert = header[0x2e];
+  let length = u32_le(header, 0x30) as usize;
+  let wide = flags & 2 != 0;
+  let start = (u32_le(header, 0x48) as usize).min(data.len());
+
+  let samples = match (flags & 1 != 0, flags & 8 != 0, wide) {
+    (false, _, _) => vec![],
+    (true, true, wide) => decompress(
+      &data[start..],
+      length,
+      if wide { 16 } else { 8 },
+      convert & 4 != 0,
+    ),
+    // only the left channel of stereo samples is played
+    (true, false, true) => data[start..]
+      .chunks_exact(2)
+      .take(length)
+      .map(|b| {
+        let v = u16::from_le_bytes([b[0], b[1]]);
+        match convert & 1 {
+          0 => (v as f32 - 32768.) / 32768.,
+          _ => v as i16 as f32 / 32768.,
+        }
+      })
+      .collect(),
+    (true, false, false) => data[start..]
+      .iter()
+      .take(length)
+      .map(|b| match convert & 1 {
+        0 => (*b as f32 - 128.) / 128.,
+        _ => *b as i8 as f32 / 128.,
+      })
+      .collect(),
+  };
+
+  let range = |at: usize, ping_pong: bool| {
+    let (start, end) = (u32_le(header, at) as usize, u32_le(header, at + 4) as usize);
+    let end = end.min(samples.len());
+    match start < end {
+      true => Some(Loop {
+        start,
+        end,
+        ping_pong,
+      }),
+      false => None,
+    }
+  };
+  let looped = match flags & 0x10 {
+    0 => None,
+    _ => range(0x34, flags & 0x40 != 0),
+  };
+  let sustain = match flags & 0x20 {
+    0 => None,
+    _ => range(0x40, flags & 0x80 != 0),
+  };
+
+  Some(Sample {
+    data: samples,
+    c5_speed: u32_le(header, 0x3c).max(1) as f64,
+    volume: header[0x13].min(64),
+    global_volume: header[0x11].min(64) as f32 / 64.,
+    pan: match header[0x2f] {
+      p if p & 0x80 != 0 => Some((p & 0x7f).min(64) as f32 / 32. - 1.),
+      _ => None,
+    },
+    looped,
+    sustain,
+  })
+}
+
+fn instrument(data: &[u8], at: usize, old: bool, texts: &mut Vec<String>) -> Option<Instrument> {
+  let header = data.get(at..at + 0x1c0)?;
+  if &header[..4] != b"IMPI" {
+    return None;
+  }
+  texts.push(text(&header[0x20..0x3a]));
+
+  let mut keymap: Vec<(u8, u8)> = (0..120).map(|n| (n, 0)).collect();
+  for (n, entry) in keymap.iter_mut().enumerate() {
+    let at = 0x40 + (n + OCTAVE as usize) * 2;
+    if let Some(&[note, sample]) = header.get(at..at + 2) {
+      *entry = (note.saturating_sub(OCTAVE), sample);
+    }
+  }
+
+  // instruments from before 2.0 only keep their keymap and fadeout
+  if old {
+    return Some(Instrument {
+      keymap,
+      volume_envelope: None,
+      fadeout: u16_le(header, 0x18) as f32 / 512.,
+    });
+  }
+
+  let envelope = &header[0x130..];
+  let flags = envelope[0];
+  let count = (envelope[1] as usize).min(25);
+  let volume_envelope = match flags & 1 != 0 && count > 0 {
+    true => Some(Envelope {
+      points: (0..count)
+        .map(|i| (u16_le(envelope, 7 + i * 3), envelope[6 + i * 3].min(64)))
+        .collect(),
+      looped: (flags & 2 != 0).then_some((envelope[2] as usize, envelope[3] as usize)),
+      sustain: (flags & 4 != 0).then_some((envelope[4] as usize, envelope[5] as usize)),
+    }),
+    false => None,
+  };
+
+  Some(Instrument {
+    keymap,
+    volume_envelope,
+    fadeout: u16_le(header, 0x14) as f32 / 1024.,
+  })
+}
+
+fn volume(v: u8) -> Volume {
+  const TONE_PORTA: [u8; 10] = [0, 1, 4, 8, 16, 32, 64, 96, 128, 255];
+  match v {
+    0..=64 => Volume::Set(v),
+    65..=74 => Volume::FineSlide((v - 65) as i8),
+    75..=84 => Volume::FineSlide(-((v - 75) as i8)),
+    85..=94 => Volume::Slide((v - 85) as i8),
+    95..=104 => Volume::Slide(-((v - 95) as i8)),
+    105..=114 => Volume::PortaDown((v - 105) * 4),
+    115..=124 => Volume::PortaUp((v - 115) * 4),
+    128..=192 => Volume::Pan(v - 128),
+    193..=202 => Volume::TonePorta(TONE_PORTA[(v - 193) as usize]),
+    203..=212 => Volume::Vibrato(v - 203),
+    _ => Volume::None,
+  }
+}
+
+// (row, channel, cell), patterns are unpacked before the channel count is known
+type Cells = Vec<(usize, usize, Cell)>;
+
+fn pattern(data: &[u8], at: usize) -> Option<(usize, Cells)> {
+  let size = u16_le(data, at) as usize;
+  let rows = (u16_le(data, at + 2) as usize).max(1);
+  let packed = data.get(at + 8..at + 8 + size)?;
+
+  let mut cells = vec![];
+  let mut masks = [0u8; 64];
+  let mut last = [Cell::default(); 64];
+  let mut bytes = packed.iter().copied();
+  let mut row = 0;
+
+  while row < rows {
+    let variable = match bytes.next() {
+      Some(0) => {
+        row += 1;
+        continue;
+      }
+      Some(variable) => variable,
+      None => break,
+    };
+    let channel = (variable as usize - 1) & 63;
+    if variable & 0x80 != 0 {
+      masks[channel] = bytes.next().unwrap_or(0);
+    }
+    let mask = masks[channel];
+    let mut cell = Cell::default();
+    let previous = &mut last[channel];
+
+    if mask & 1 != 0 {
+      previous.note = match bytes.next().unwrap_or(0) {
+        255 => Note::Off,
+        254 => Note::Cut,
+        note @ 0..=119 => Note::On(note.saturating_sub(OCTAVE)),
+        _ => Note::Fade,
+      };
+      cell.note = previous.note;
+    }
+    if mask & 2 != 0 {
+      previous.instrument = bytes.next().unwrap_or(0);
+      cell.instrument = previous.instrument;
+    }
+    if mask & 4 != 0 {
+      previous.volume = volume(bytes.next().unwrap_or(255));
+      cell.volume = previous.volume;
+    }
+    if mask & 8 != 0 {
+      let (command, param) = (bytes.next().unwrap_or(0), bytes.next().unwrap_or(0));
+      previous.effect = match command {
+        1..=26 => s3m::effect(command, param, true),
+        _ => Effect::None,
+      };
+      cell.effect = previous.effect;
+    }
+    if mask & 16 != 0 {
+      cell.note = previous.note;
+    }
+    if mask & 32 != 0 {
+      cell.instrument = previous.instrument;
+    }
+    if mask & 64 != 0 {
+      cell.volume = previous.volume;
+    }
+    if mask & 128 != 0 {
+      cell.effect = previous.effect;
+    }
+
+    cells.push((row, channel, cell));
+  }
+
+  Some((rows, cells))
+}
+
+fn message(data: &[u8]) -> Vec<String> {
+  if u16_le(data, 0x2e) & 1 == 0 {
+    return vec![];
+  }
+  let (length, at) = (u16_le(data, 0x36) as usize, u32_le(data, 0x38) as usize);
+  match data.get(at..at + length) {
+    Some(bytes) => String::from_utf8_lossy(bytes)
+      .split(['\r', '\n', '\0'])
+      .map(|line| line.trim_end().to_owned())
+      .filter(|line| !line.is_empty())
+      .collect(),
+    None => vec![],
+  }
+}
+
+pub fn load(data: &[u8]) -> Option<Module> {
+  let header = data.get(..0xc0)?;
+  let orders = u16_le(header, 0x20) as usize;
+  let instrument_count = u16_le(header, 0x22) as usize;
+  let sample_count = u16_le(header, 0x24) as usize;
+  let pattern_count = u16_le(header, 0x26) as usize;
+  let compatible = u16_le(header, 0x2a);
+  let flags = u16_le(header, 0x2c);
+
+  let order_list = data.get(0xc0..0xc0 + orders)?;
+  let pointer = |table: usize, i: usize| u32_le(data, table + i * 4) as usize;
+  let instrument_table = 0xc0 + orders;
+  let sample_table = instrument_table + instrument_count * 4;
+  let pattern_table = sample_table + sample_count * 4;
+
+  let mut texts = vec![];
+  let instruments: Vec<Instrument> = match flags & 4 != 0 {
+    true => (0..instrument_count)
+      .map(|i| {
+        instrument(
+          data,
+          pointer(instrument_table, i),
+          compatible < 0x200,
+          &mut texts,
+        )
+      })
+      .collect::<Option<_>>()?,
+    false => (0..sample_count).map(Instrument::for_sample).collect(),
+  };
+  let samples: Vec<Sample> = (0..sample_count)
+    .map(|i| sample(data, pointer(sample_table, i), &mut texts))
+    .collect::<Option<_>>()?;
+  texts.extend(message(data));
+
+  // channels past 64 don't exist, and disabled ones (pan >= 128) are muted
+  let mut unpacked = vec![];
+  let mut channels = 1;
+  for i in 0..pattern_count {
+    let pattern = match pointer(pattern_table, i) {
+      0 => (64, vec![]),
+      at => pattern(data, at)?,
+    };
+    let used = pattern.1.iter().filter(|(_, c, _)| header[0x40 + c] < 128);
+    channels = used.map(|(_, c, _)| c + 1).fold(channels, usize::max);
+    unpacked.push(pattern);
+  }
+
+  let patterns = unpacked
+    .into_iter()
+    .map(|(rows, list)| {
+      let mut cells = vec![Cell::default(); rows * channels];
+      for (row, c, cell) in list {
+        if c < channels && header[0x40 + c] < 128 {
+          cells[row * channels + c] = cell;
+        }
+      }
+      Pattern { rows, cells }
+    })
+    .collect();
+
+  Some(Module {
+    kind: Kind::It,
+    title: text(&header[4..30]),
+    texts,
+    channels,
+    orders: order_list
+      .iter()
+      .map(|o| match o {
+        255 => Order::End,
+        254 => Order::Skip,
+        p => Order::Pattern(*p as usize),
+      })
+      .collect(),
+    restart: 0,
+    patterns,
+    instruments,
+    samples,
+    speed: header[0x32].max(1),
+    tempo: header[0x33].max(32),
+    global_volume: header[0x30].min(128) as f32 / 128.,
+    pan: (0..channels)
+      .map(|c| match header[0x40 + c] & 0x7f {
+        // surround is played in the middle
+        100 => 0.,
+        p => p.min(64) as f32 / 32. - 1.,
+      })
+      .collect(),
+    channel_volume: (0..channels)
+      .map(|c| header[0x80 + c].min(64) as f32 / 64.)
+      .collect(),
+    linear: flags & 8 != 0,
+  })
+}
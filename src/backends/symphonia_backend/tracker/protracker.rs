@@ -0,0 +1,156 @@
+use super::*;
+
+// amiga period of C-4 (protracker's C-2) at finetune 0
+const PERIOD_C4: f64 = 428.;
+
+fn u16_be(data: &[u8], at: usize) -> usize {
+  u16::from_be_bytes([data[at], data[at + 1]]) as usize
+}
+
+// Protracker effects, which xm shares for 0 - F.
+pub fn effect(effect: u8, param: u8) -> Effect {
+  let (x, y) = (param >> 4, param & 0xf);
+  match effect {
+    0x0 if param != 0 => Effect::Arpeggio(param),
+    0x1 => Effect::PortaUp(param),
+    0x2 => Effect::PortaDown(param),
+    0x3 => Effect::TonePorta(param),
+    0x4 => Effect::Vibrato(param),
+    0x5 => Effect::TonePortaVolumeSlide(param),
+    0x6 => Effect::VibratoVolumeSlide(param),
+    0x7 => Effect::Tremolo(param),
+    0x8 => Effect::SetPan(param),
+    0x9 => Effect::SampleOffset(param),
+    0xa => Effect::VolumeSlide(param),
+    0xb => Effect::PositionJump(param),
+    0xc => Effect::SetVolume(param),
+    0xd => Effect::PatternBreak(x * 10 + y),
+    0xe => match x {
+      0x1 => Effect::FinePortaUp(y),
+      0x2 => Effect::FinePortaDown(y),
+      0x6 => Effect::PatternLoop(y),
+      0x8 => Effect::SetPan(y * 17),
+      0x9 => Effect::Retrigger(y),
+      0xa => Effect::FineVolumeSlideUp(y),
+      0xb => Effect::FineVolumeSlideDown(y),
+      0xc => Effect::NoteCut(y),
+      0xd => Effect::NoteDelay(y),
+      0xe => Effect::PatternDelay(y),
+      _ => Effect::None,
+    },
+    0xf if param == 0 => Effect::None,
+    0xf if param < 32 => Effect::SetSpeed(param),
+    0xf => Effect::SetTempo(param),
+    _ => Effect::None,
+  }
+}
+
+// Mods are recognised by a tag that also gives their channel count.
+pub fn channels(data: &[u8]) -> Option<usize> {
+  let channels = match data.get(1080..1084)? {
+    b"M.K." | b"M!K!" | b"M&K!" | b"FLT4" | b"4CHN" => 4,
+    b"FLT8" | b"OCTA" | b"CD81" => 8,
+    [n, b'C', b'H', b'N'] if n.is_ascii_digit() => (n - b'0') as usize,
+    [a, b, b'C', b'H'] if a.is_ascii_digit() && b.is_ascii_digit() => {
+      ((a - b'0') * 10 + (b - b'0')) as usize
+    }
+    _ => return None,
+  };
+  (channels > 0).then_some(channels)
+}
+
+pub fn load(data: &[u8]) -> Option<Module> {
+  let channels = channels(data)?;
+  let headers: Vec<&[u8]> = (0..31).map(|i| &data[20 + i * 30..50 + i * 30]).collect();
+  let song_length = (data[950] as usize).clamp(1, 128);
+  let order_table = &data[952..1080];
+  let pattern_count = *order_table.iter().max()? as usize + 1;
+
+  let mut offset = 1084;
+  let mut patterns = vec![];
+  for _ in 0..pattern_count {
+    let size = 64 * channels * 4;
+    let bytes = data.get(offset..offset + size)?;
+    offset += size;
+
+    let cells = bytes
+      .chunks_exact(4)
+      .map(|b| {
+        let period = ((b[0] as usize & 0x0f) << 8) | b[1] as usize;
+        Cell {
+          note: match period {
+            0 => Note::None,
+            p => Note::On(
+              (48. + 12. * (PERIOD_C4 / p as f64).log2())
+                .round()
+                .clamp(0., 119.) as u8,
+            ),
+          },
+          instrument: (b[0] & 0xf0) | (b[2] >> 4),
+          volume: Volume::None,
+          effect: effect(b[2] & 0x0f, b[3]),
+        }
+      })
+      .collect();
+    patterns.push(Pattern { rows: 64, cells });
+  }
+
+  let mut samples = vec![];
+  for header in &headers {
+    let length = u16_be(header, 22) * 2;
+    // the last sample is often truncated
+    let bytes = &data[offset.min(data.len())..(offset + length).min(data.len())];
+    offset += length;
+
+    // finetune is a signed nibble, in eighths of a semitone
+    let finetune = ((header[24] & 0xf) as i8) << 4 >> 4;
+    let (loop_start, loop_length) = (u16_be(header, 26) * 2, u16_be(header, 28) * 2);
+
+    samples.push(Sample {
+      data: bytes.iter().map(|b| *b as i8 as f32 / 128.).collect(),
+      c5_speed: 8363. * 2f64.powf(finetune as f64 / 96.),
+      volume: header[25].min(64),
+      global_volume: 1.,
+      pan: None,
+      looped: match loop_length > 2 && loop_start < bytes.len() {
+        true => Some(Loop {
+          start: loop_start,
+          end: (loop_start + loop_length).min(bytes.len()),
+          ping_pong: false,
+        }),
+        false => None,
+      },
+      sustain: None,
+    });
+  }
+
+  Some(Module {
+    kind: Kind::Mod,
+    title: text(&data[..20]),
+    texts: headers.iter().map(|h| text(&h[..22])).collect(),
+    channels,
+    orders: order_table[..song_length]
+      .iter()
+      .map(|p| Order::Pattern(*p as usize))
+      .collect(),
+    restart: match data[951] as usize {
+      r if r < song_length => r,
+      _ => 0,
+    },
+    patterns,
+    instruments: (0..samples.len()).map(Instrument::for_sample).collect(),
+    samples,
+    speed: 6,
+    tempo: 125,
+    global_volume: 1.,
+    // amiga channels are panned left, right, right, left
+    pan: (0..channels)
+      .map(|c| match c % 4 {
+        0 | 3 => -0.5,
+        _ => 0.5,
+      })
+      .collect(),
+    channel_volume: vec![1.; channels],
+    linear: false,
+  })
+}
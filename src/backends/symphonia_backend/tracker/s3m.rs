@@ -0,0 +1,250 @@
+use super::*;
+
+// Effects by letter (A = 1), which impulse tracker inherited with a few changes.
+pub fn effect(command: u8, param: u8, it: bool) -> Effect {
+  let (x, y) = (param >> 4, param & 0xf);
+  match (command + b'A' - 1) as char {
+    'A' => Effect::SetSpeed(param),
+    'B' => Effect::PositionJump(param),
+    'C' if it => Effect::PatternBreak(param),
+    // scream tracker stores the row in decimal digits
+    'C' => Effect::PatternBreak(x * 10 + y),
+    'D' => Effect::VolumeSlide(param),
+    'E' => Effect::PortaDown(param),
+    'F' => Effect::PortaUp(param),
+    'G' => Effect::TonePorta(param),
+    'H' => Effect::Vibrato(param),
+    'J' => Effect::Arpeggio(param),
+    'K' => Effect::VibratoVolumeSlide(param),
+    'L' => Effect::TonePortaVolumeSlide(param),
+    'M' => Effect::ChannelVolume(param),
+    'N' => Effect::ChannelVolumeSlide(param),
+    'O' => Effect::SampleOffset(param),
+    'Q' => Effect::Retrigger(param),
+    'R' => Effect::Tremolo(param),
+    'S' => match x {
+      0x8 => Effect::SetPan(y * 17),
+      0xb => Effect::PatternLoop(y),
+      0xc => Effect::NoteCut(y),
+      0xd => Effect::NoteDelay(y),
+      0xe => Effect::PatternDelay(y),
+      _ => Effect::None,
+    },
+    // T0x and T1x are tempo slides, which aren't supported
+    'T' if param >= 0x20 => Effect::SetTempo(param),
+    'U' => Effect::FineVibrato(param),
+    'V' if it => Effect::SetGlobalVolume(param / 2),
+    'V' => Effect::SetGlobalVolume(param),
+    'W' => Effect::GlobalVolumeSlide(param),
+    'X' if it => Effect::SetPan(param),
+    // 0 - 0x80, with 0xa4 for surround, played in the middle
+    'X' if param <= 0x80 => Effect::SetPan((param as u16 * 2).min(255) as u8),
+    'X' => Effect::SetPan(128),
+    _ => Effect::None,
+  }
+}
+
+fn pointer(data: &[u8], at: usize) -> usize {
+  u16_le(data, at) as usize * 16
+}
+
+fn sample(data: &[u8], at: usize, signed: bool) -> Option<Sample> {
+  let header = data.get(at..at + 0x50)?;
+  // adlib instruments and empty slots
+  if header[0] != 1 {
+    return Some(Sample {
+      data: vec![],
+      c5_speed: 8363.,
+      volume: 0,
+      global_volume: 1.,
+      pan: None,
+      looped: None,
+      sustain: None,
+    });
+  }
+
+  let offset = (((header[0x0d] as usize) << 16) | u16_le(header, 0x0e) as usize) * 16;
+  let length = u32_le(header, 0x10) as usize;
+  let (loop_start, loop_end) = (u32_le(header, 0x14) as usize, u32_le(header, 0x18) as usize);
+  let flags = header[0x1f];
+  let wide = flags & 4 != 0;
+
+  let bytes = length * if wide { 2 } else { 1 };
+  let raw = &data[offset.min(data.len())..(offset + bytes).min(data.len())];
+  // stereo samples store the right channel after the left, only the left is played
+  let data: Vec<f32> = match wide {
+    true => raw
+      .chunks_exact(2)
+      .map(|b| {
+        let v = u16::from_le_bytes([b[0], b[1]]);
+        match signed {
+          true => v as i16 as f32 / 32768.,
+          false => (v as f32 - 32768.) / 32768.,
+        }
+      })
+      .collect(),
+    false => raw
+      .iter()
+      .map(|b| match signed {
+        true => *b as i8 as f32 / 128.,
+        false => (*b as f32 - 128.) / 128.,
+      })
+      .collect(),
+  };
+
+  let looped = match flags & 1 != 0 && loop_start < loop_end.min(data.len()) {
+    true => Some(Loop {
+      start: loop_start,
+      end: loop_end.min(data.len()),
+      ping_pong: false,
+    }),
+    false => None,
+  };
+
+  Some(Sample {
+    data,
+    c5_speed: u32_le(header, 0x20).max(1) as f64,
+    volume: header[0x1c].min(64),
+    global_volume: 1.,
+    pan: None,
+    looped,
+    sustain: None,
+  })
+}
+
+fn pattern(data: &[u8], at: usize, columns: &[Option<usize>], channels: usize) -> Pattern {
+  let mut cells = vec![Cell::default(); 64 * channels];
+  let mut i = at + 2;
+
+  for row in 0..64 {
+    while let Some(&what) = data.get(i) {
+      i += 1;
+      if what == 0 {
+        break;
+      }
+
+      let mut cell = Cell::default();
+      if what & 0x20 != 0 {
+        let (note, instrument) = (data.get(i).copied(), data.get(i + 1).copied());
+        cell.note = match note {
+          Some(255) | None => Note::None,
+          Some(254) => Note::Cut,
+          Some(n) => Note::On(((n >> 4) * 12 + (n & 0xf)).min(119)),
+        };
+        cell.instrument = instrument.unwrap_or(0);
+        i += 2;
+      }
+      if what & 0x40 != 0 {
+        cell.volume = match data.get(i) {
+          Some(v) if *v <= 64 => Volume::Set(*v),
+          _ => Volume::None,
+        };
+        i += 1;
+      }
+      if what & 0x80 != 0 {
+        let (command, param) = (data.get(i).copied(), data.get(i + 1).copied());
+        if let (Some(command @ 1..=26), Some(param)) = (command, param) {
+          cell.effect = effect(command, param, false);
+        }
+        i += 2;
+      }
+
+      if let Some(Some(c)) = columns.get((what & 0x1f) as usize) {
+        cells[row * channels + c] = cell;
+      }
+    }
+  }
+
+  Pattern { rows: 64, cells }
+}
+
+pub fn load(data: &[u8]) -> Option<Module> {
+  let orders = u16_le(data, 0x20) as usize;
+  let instruments = u16_le(data, 0x22) as usize;
+  let patterns = u16_le(data, 0x24) as usize;
+  let signed = u16_le(data, 0x2a) == 1;
+  let stereo = *data.get(0x33)? & 0x80 != 0;
+
+  // only pcm channels are played, adlib and disabled ones are dropped
+  let settings = data.get(0x40..0x60)?;
+  let mut columns = vec![None; 32];
+  let mut pan = vec![];
+  for (i, setting) in settings.iter().enumerate() {
+    if *setting < 16 {
+      columns[i] = Some(pan.len());
+      pan.push(match (stereo, setting) {
+        (false, _) => 0.,
+        (true, 0..=7) => -0.6,
+        (true, _) => 0.6,
+      });
+    }
+  }
+  let channels = pan.len();
+  if channels == 0 {
+    return None;
+  }
+
+  let order_list = data.get(0x60..0x60 + orders)?;
+  let pointers = 0x60 + orders;
+
+  // an optional table of default pans follows the pointers
+  let pans = pointers + (instruments + patterns) * 2;
+  if data.get(0x35) == Some(&252) {
+    for (i, column) in columns.iter().enumerate() {
+      if let (Some(c), Some(p)) = (column, data.get(pans + i)) {
+        if p & 0x20 != 0 {
+          pan[*c] = (p & 0xf) as f32 / 7.5 - 1.;
+        }
+      }
+    }
+  }
+
+  let mut samples = vec![];
+  let mut texts = vec![];
+  for i in 0..instruments {
+    let at = pointer(data, pointers + i * 2);
+    samples.push(sample(data, at, signed)?);
+    texts.push(data.get(at + 0x30..at + 0x4c).map(text).unwrap_or_default());
+  }
+
+  let patterns = (0..patterns)
+    .map(|i| match pointer(data, pointers + (instruments + i) * 2) {
+      0 => Pattern {
+        rows: 64,
+        cells: vec![Cell::default(); 64 * channels],
+      },
+      at => pattern(data, at, &columns, channels),
+    })
+    .collect();
+
+  Some(Module {
+    kind: Kind::S3m,
+    title: text(&data[..28]),
+    texts,
+    channels,
+    orders: order_list
+      .iter()
+      .map(|o| match o {
+        255 => Order::End,
+        254 => Order::Skip,
+        p => Order::Pattern(*p as usize),
+      })
+      .collect(),
+    restart: 0,
+    patterns,
+    instruments: (0..samples.len()).map(Instrument::for_sample).collect(),
+    samples,
+    speed: match data[0x31] {
+      0 | 255 => 6,
+      speed => speed,
+    },
+    tempo: match data[0x32] {
+      t if t < 33 => 125,
+      t => t,
+    },
+    global_volume: data[0x30].min(64) as f32 / 64.,
+    pan,
+    channel_volume: vec![1.; channels],
+    linear: false,
+  })
+}
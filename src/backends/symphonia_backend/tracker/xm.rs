@@ -0,0 +1,271 @@
+use super::*;
+
+fn effect(command: u8, param: u8) -> Effect {
+  let (x, y) = (param >> 4, param & 0xf);
+  match command {
+    0x0..=0xf => protracker::effect(command, param),
+    // effects past F are numbered on with letters, G = 16
+    16 => Effect::SetGlobalVolume(param),
+    17 => Effect::GlobalVolumeSlide(param),
+    20 => Effect::KeyOff(param),
+    27 => Effect::Retrigger(param),
+    33 => match x {
+      1 => Effect::ExtraFinePortaUp(y),
+      2 => Effect::ExtraFinePortaDown(y),
+      _ => Effect::None,
+    },
+    _ => Effect::None,
+  }
+}
+
+fn volume(v: u8) -> Volume {
+  let x = v & 0xf;
+  match v {
+    0x10..=0x50 => Volume::Set(v - 0x10),
+    0x60..=0x6f => Volume::Slide(-(x as i8)),
+    0x70..=0x7f => Volume::Slide(x as i8),
+    0x80..=0x8f => Volume::FineSlide(-(x as i8)),
+    0x90..=0x9f => Volume::FineSlide(x as i8),
+    0xa0..=0xaf => Volume::VibratoSpeed(x),
+    0xb0..=0xbf => Volume::Vibrato(x),
+    0xc0..=0xcf => Volume::Pan(x * 64 / 15),
+    0xf0..=0xff => Volume::TonePorta(x << 4),
+    _ => Volume::None,
+  }
+}
+
+// Returns the pattern and where the next one starts.
+fn pattern(data: &[u8], at: usize, channels: usize) -> Option<(Pattern, usize)> {
+  let header = u32_le(data, at) as usize;
+  let rows = (u16_le(data, at + 5) as usize).max(1);
+  let size = u16_le(data, at + 7) as usize;
+  let start = at + header;
+  let packed = data.get(start..start + size)?;
+
+  let mut cells = vec![Cell::default(); rows * channels];
+  let mut bytes = packed.iter().copied();
+  // each cell is either 5 plain bytes, or a mask followed by the bytes it flags
+  for cell in cells.iter_mut() {
+    let first = match bytes.next() {
+      Some(first) => first,
+      None => break,
+    };
+    let mask = match first & 0x80 {
+      0 => 0x1f,
+      _ => first,
+    };
+    let mut field = |bit: u8, plain: Option<u8>| match mask & bit {
+      0 => 0,
+      _ => plain.or_else(|| bytes.next()).unwrap_or(0),
+    };
+    let note = field(1, (first & 0x80 == 0).then_some(first));
+    let instrument = field(2, None);
+    let volume_byte = field(4, None);
+    let command = field(8, None);
+    let param = field(16, None);
+
+    *cell = Cell {
+      note: match note {
+        1..=96 => Note::On(note - 1),
+        97 => Note::Off,
+        _ => Note::None,
+      },
+      instrument,
+      volume: volume(volume_byte),
+      effect: effect(command, param),
+    };
+  }
+
+  Some((Pattern { rows, cells }, start + size))
+}
+
+fn envelope(
+  header: &[u8],
+  at: usize,
+  count: usize,
+  flags: u8,
+  points: (u8, u8, u8),
+) -> Option<Envelope> {
+  if flags & 1 == 0 || count == 0 {
+    return None;
+  }
+  let (sustain, loop_start, loop_end) = points;
+  Some(Envelope {
+    points: (0..count.min(12))
+      .map(|i| {
+        (
+          u16_le(header, at + i * 4),
+          u16_le(header, at + i * 4 + 2).min(64) as u8,
+        )
+      })
+      .collect(),
+    sustain: (flags & 2 != 0).then_some((sustain as usize, sustain as usize)),
+    looped: (flags & 4 != 0).then_some((loop_start as usize, loop_end as usize)),
+  })
+}
+
+// Reads an instrument and its samples, returning where the next one starts.
+fn instrument(
+  data: &[u8],
+  at: usize,
+  samples: &mut Vec<Sample>,
+  texts: &mut Vec<String>,
+) -> Option<(Instrument, usize)> {
+  let size = u32_le(data, at) as usize;
+  let header = data.get(at..at + size.max(29))?;
+  texts.push(text(&header[4..26]));
+  let count = u16_le(header, 27) as usize;
+
+  let first = samples.len() as u8 + 1;
+  let mut instrument = Instrument {
+    keymap: (0..120).map(|n| (n, 0)).collect(),
+    volume_envelope: None,
+    fadeout: 0.,
+  };
+  if count == 0 {
+    return Some((instrument, at + size));
+  }
+
+  let header = data.get(at..at + size.max(241))?;
+  for (n, sample) in header[33..129].iter().enumerate() {
+    if (*sample as usize) < count {
+      instrument.keymap[n] = (n as u8, first.saturating_add(*sample));
+    }
+  }
+  instrument.volume_envelope = envelope(
+    header,
+    129,
+    header[225] as usize,
+    header[233],
+    (header[227], header[228], header[229]),
+  );
+  instrument.fadeout = u16_le(header, 239) as f32 / 32768.;
+
+  let sample_header = (u32_le(header, 29) as usize).max(40);
+  let mut offset = at + size;
+  let mut headers = vec![];
+  for _ in 0..count {
+    headers.push(data.get(offset..offset + 40)?);
+    offset += sample_header;
+  }
+
+  for h in headers {
+    let length = u32_le(h, 0) as usize;
+    let (mut loop_start, mut loop_length) = (u32_le(h, 4) as usize, u32_le(h, 8) as usize);
+    let flags = h[14];
+    let raw = &data[offset.min(data.len())..(offset + length).min(data.len())];
+    offset += length;
+
+    // sample data is stored as deltas
+    let data: Vec<f32> = match flags & 0x10 {
+      0 => {
+        let mut last = 0i8;
+        raw
+          .iter()
+          .map(|b| {
+            last = last.wrapping_add(*b as i8);
+            last as f32 / 128.
+          })
+          .collect()
+      }
+      _ => {
+        loop_start /= 2;
+        loop_length /= 2;
+        let mut last = 0i16;
+        raw
+          .chunks_exact(2)
+          .map(|b| {
+            last = last.wrapping_add(i16::from_le_bytes([b[0], b[1]]));
+            last as f32 / 32768.
+          })
+          .collect()
+      }
+    };
+
+    let end = (loop_start + loop_length).min(data.len());
+    let looped = match (flags & 3, loop_start < end) {
+      (1, true) => Some(Loop {
+        start: loop_start,
+        end,
+        ping_pong: false,
+      }),
+      (2, true) => Some(Loop {
+        start: loop_start,
+        end,
+        ping_pong: true,
+      }),
+      _ => None,
+    };
+    // relative note in semitones and finetune in 128ths of one
+    let (finetune, relative) = (h[13] as i8 as f64, h[16] as i8 as f64);
+
+    texts.push(text(&h[18..40]));
+    samples.push(Sample {
+      data,
+      c5_speed: 8363. * 2f64.powf((relative * 128. + finetune) / 1536.),
+      volume: h[12].min(64),
+      global_volume: 1.,
+      pan: Some(h[15] as f32 / 127.5 - 1.),
+      looped,
+      sustain: None,
+    });
+  }
+
+  Some((instrument, offset))
+}
+
+pub fn load(data: &[u8]) -> Option<Module> {
+  if data.len() < 80 {
+    return None;
+  }
+  let header = u32_le(data, 60) as usize;
+  let song_length = (u16_le(data, 64) as usize).min(256);
+  let channels = u16_le(data, 68) as usize;
+  let pattern_count = u16_le(data, 70) as usize;
+  let instrument_count = u16_le(data, 72) as usize;
+  if channels == 0 {
+    return None;
+  }
+
+  let mut offset = 60 + header;
+  let mut patterns = vec![];
+  for _ in 0..pattern_count {
+    let (pattern, next) = pattern(data, offset, channels)?;
+    patterns.push(pattern);
+    offset = next;
+  }
+
+  let (mut instruments, mut samples, mut texts) = (vec![], vec![], vec![]);
+  for _ in 0..instrument_count {
+    match instrument(data, offset, &mut samples, &mut texts) {
+      Some((instrument, next)) => {
+        instruments.push(instrument);
+        offset = next;
+      }
+      // keep what loaded from truncated files
+      None => break,
+    }
+  }
+
+  Some(Module {
+    kind: Kind::Xm,
+    title: text(&data[17..37]),
+    texts,
+    channels,
+    orders: data
+      .get(80..80 + song_length)?
+      .iter()
+      .map(|p| Order::Pattern(*p as usize))
+      .collect(),
+    restart: u16_le(data, 66) as usize,
+    patterns,
+    instruments,
+    samples,
+    speed: u16_le(data, 76).clamp(1, 31) as u8,
+    tempo: u16_le(data, 78).clamp(32, 255) as u8,
+    global_volume: 1.,
+    pan: vec![0.; channels],
+    channel_volume: vec![1.; channels],
+    linear: u16_le(data, 74) & 1 != 0,
+  })
+}
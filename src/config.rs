@@ -2,14 +2,65 @@ use crate::*;
 use serde_derive::{Deserialize, Serialize};
 
 #[derive(Deserialize, Serialize)]
+#[serde(default)]
 pub struct Config {
   pub scan_depth_limit: usize,
+  // files with these extensions are listed, ignoring case
+  pub extensions: Vec<String>,
+  // also list files with other extensions (or none) that the backend can play
+  pub probe_files: bool,
+  // sf2 used to play midi files
+  pub soundfont: Option<PathBuf>,
+  // times tracker modules repeat their song loop, 0 plays them once
+  pub module_loops: u32,
+  // how far below the root folder shuffle picks its folders
+  pub shuffle_depth: usize,
+  // read ratings from and write them to tags (POPM, FMPS_RATING), needs `metadata`
+  pub rating_tags: bool,
+  // where the organizer moves files to under the root, by their tags, needs `metadata`
+  pub organize_layout: String,
+  // where plays are scrobbled to, needs `scrobble`
+  pub scrobbler: Option<Scrobbler>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum ScrobbleApi {
+  ListenBrainz,
+  LastFm,
+}
+
+// [scrobbler]
+// api = "listenbrainz"
+// url = "https://api.listenbrainz.org"
+// token = "<user token>"
+//
+// For Last.fm (or a server speaking its api), `url` is the api root like
+// "https://ws.audioscrobbler.com/2.0/", `token` a session key, and
+// `api_key` / `api_secret` those of the application.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct Scrobbler {
+  pub api: ScrobbleApi,
+  pub url: String,
+  pub token: String,
+  #[serde(default)]
+  pub api_key: String,
+  #[serde(default)]
+  pub api_secret: String,
 }
 
 impl ::std::default::Default for Config {
   fn default() -> Self {
     Self {
       scan_depth_limit: 12,
+      extensions: SUPPORTED.iter().map(|e| e.to_string()).collect(),
+      probe_files: false,
+      soundfont: None,
+      module_loops: 0,
+      shuffle_depth: 1,
+      rating_tags: false,
+      organize_layout: "{albumartist}/{year} - {album}/{disc}{track:02} {title}.{ext}".to_owned(),
+      scrobbler: None,
     }
   }
 }
@@ -1,15 +1,21 @@
-use crate::app::AppCommand;
+use crate::app::{repeat, AppCommand};
 use crate::*;
 use crossbeam_channel::{Receiver, Sender};
+#[cfg(not(all(unix, not(target_os = "macos"))))]
 use souvlaki::{MediaControlEvent, MediaControls, MediaMetadata, MediaPlayback, PlatformConfig};
 
-#[derive(Default)]
+// souvlaki leaves out MPRIS's LoopStatus and Shuffle, so it's served here
+#[cfg(all(unix, not(target_os = "macos")))]
+mod mpris;
+
+#[derive(Default, Clone)]
 pub struct Metadata {
   pub title: Option<String>,
   pub artist: Option<String>,
   pub album: Option<String>,
 }
 
+#[cfg(not(all(unix, not(target_os = "macos"))))]
 impl<'a> From<&'a Metadata> for MediaMetadata<'a> {
   fn from(metadata: &'a Metadata) -> Self {
     Self {
@@ -24,8 +30,17 @@ impl<'a> From<&'a Metadata> for MediaMetadata<'a> {
 pub enum PlaybackStatus {
   Playing(Option<Metadata>),
   Paused,
+  Repeat(repeat::Mode),
+  Shuffle(bool), // on in any mode
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+pub fn event_listener(command: Arc<Sender<AppCommand>>, play_status: Receiver<PlaybackStatus>) {
+  // without a session bus there's nothing to serve
+  thread::spawn(move || mpris::serve(command, play_status));
 }
 
+#[cfg(not(all(unix, not(target_os = "macos"))))]
 pub fn event_listener(command: Arc<Sender<AppCommand>>, play_status: Receiver<PlaybackStatus>) {
   #[cfg(not(target_os = "windows"))]
   let hwnd = None;
@@ -81,6 +96,7 @@ pub fn event_listener(command: Arc<Sender<AppCommand>>, play_status: Receiver<Pl
           controls.set_playback(MediaPlayback::Playing { progress: None })
         }
         PlaybackStatus::Paused => controls.set_playback(MediaPlayback::Paused { progress: None }),
+        PlaybackStatus::Repeat(_) | PlaybackStatus::Shuffle(_) => Ok(()),
       };
     }
   });
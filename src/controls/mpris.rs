@@ -0,0 +1,193 @@
+use super::{Metadata, PlaybackStatus};
+use crate::app::{repeat, AppCommand};
+use crate::*;
+use dbus::arg::{PropMap, Variant};
+use dbus::blocking::stdintf::org_freedesktop_dbus::PropertiesPropertiesChanged;
+use dbus::blocking::Connection;
+use dbus::channel::{MatchingReceiver, Sender as _};
+use dbus::message::{MatchRule, SignalArgs};
+use dbus_crossroads::{Crossroads, IfaceBuilder};
+
+const PATH: &str = "/org/mpris/MediaPlayer2";
+const PLAYER: &str = "org.mpris.MediaPlayer2.Player";
+
+// What's published, kept up to date by the app.
+struct State {
+  status: &'static str,
+  metadata: Metadata,
+  repeat: repeat::Mode,
+  shuffle: bool,
+}
+
+type Commands = Arc<crossbeam_channel::Sender<AppCommand>>;
+
+// Serves org.mpris.MediaPlayer2 on the session bus until the app's gone,
+// passing calls on as commands and publishing what the app sends back.
+pub fn serve(commands: Commands, play_status: Receiver<PlaybackStatus>) -> Result<()> {
+  let connection = Connection::new_session()?;
+  connection.request_name("org.mpris.MediaPlayer2.aquinas", false, true, false)?;
+
+  let state = Arc::new(Mutex::new(State {
+    status: "Stopped",
+    metadata: Metadata::default(),
+    repeat: repeat::Mode::Off,
+    shuffle: false,
+  }));
+
+  let mut crossroads = Crossroads::new();
+  let app = crossroads.register("org.mpris.MediaPlayer2", |b: &mut IfaceBuilder<()>| {
+    b.method("Raise", (), (), |_, _, _: ()| Ok(()));
+    b.method("Quit", (), (), |_, _, _: ()| Ok(()));
+    b.property("Identity").get(|_, _| Ok("Aquinas".to_owned()));
+    b.property("CanQuit").get(|_, _| Ok(false));
+    b.property("CanRaise").get(|_, _| Ok(false));
+    b.property("HasTracklist").get(|_, _| Ok(false));
+    b.property("SupportedUriSchemes")
+      .get(|_, _| Ok(Vec::<String>::new()));
+    b.property("SupportedMimeTypes")
+      .get(|_, _| Ok(Vec::<String>::new()));
+  });
+
+  let player = crossroads.register(PLAYER, |b: &mut IfaceBuilder<()>| {
+    command(b, &commands, "Next", || AppCommand::Next);
+    command(b, &commands, "Previous", || AppCommand::Prev);
+    command(b, &commands, "Pause", || AppCommand::Pause);
+    command(b, &commands, "Stop", || AppCommand::Pause);
+    command(b, &commands, "PlayPause", || AppCommand::PlayPause);
+    command(b, &commands, "Play", || AppCommand::Play(None));
+
+    let read = state.clone();
+    b.property("PlaybackStatus")
+      .get(move |_, _| Ok(read.lock().status.to_owned()))
+      .emits_changed_true();
+    let read = state.clone();
+    b.property("Metadata")
+      .get(move |_, _| Ok(metadata(&read.lock().metadata)))
+      .emits_changed_true();
+
+    let (read, write, sender) = (state.clone(), state.clone(), commands.clone());
+    b.property("LoopStatus")
+      .get(move |_, _| Ok(repeat::loop_status(read.lock().repeat).to_owned()))
+      .set(move |_, _, status: String| {
+        let current = write.lock().repeat;
+        if let Some(mode) = repeat::from_loop_status(&status, current) {
+          let _ = sender.send(AppCommand::SetRepeat(mode));
+        }
+        // published once the app's changed it
+        Ok(None)
+      })
+      .emits_changed_true();
+    let (read, sender) = (state.clone(), commands.clone());
+    b.property("Shuffle")
+      .get(move |_, _| Ok(read.lock().shuffle))
+      .set(move |_, _, shuffle: bool| {
+        let _ = sender.send(AppCommand::SetShuffle(shuffle));
+        Ok(None)
+      })
+      .emits_changed_true();
+
+    b.property("Rate").get(|_, _| Ok(1.0));
+    b.property("MinimumRate").get(|_, _| Ok(1.0));
+    b.property("MaximumRate").get(|_, _| Ok(1.0));
+    b.property("Volume").get(|_, _| Ok(1.0));
+    b.property("Position").get(|_, _| Ok(0i64));
+    b.property("CanGoNext").get(|_, _| Ok(true));
+    b.property("CanGoPrevious").get(|_, _| Ok(true));
+    b.property("CanPlay").get(|_, _| Ok(true));
+    b.property("CanPause").get(|_, _| Ok(true));
+    b.property("CanSeek").get(|_, _| Ok(false));
+    b.property("CanControl").get(|_, _| Ok(true));
+  });
+  crossroads.insert(PATH, &[app, player], ());
+
+  connection.start_receive(
+    MatchRule::new_method_call(),
+    Box::new(move |message, connection| {
+      let _ = crossroads.handle_message(message, connection);
+      true
+    }),
+  );
+
+  loop {
+    connection.process(Duration::from_millis(50))?;
+
+    let mut changed = PropMap::new();
+    for status in play_status.try_iter() {
+      let mut state = state.lock();
+      match status {
+        PlaybackStatus::Playing(metadata) => {
+          state.status = "Playing";
+          if let Some(metadata) = metadata {
+            changed.insert(
+              "Metadata".into(),
+              Variant(Box::new(self::metadata(&metadata))),
+            );
+            state.metadata = metadata;
+          }
+          changed.insert(
+            "PlaybackStatus".into(),
+            Variant(Box::new("Playing".to_owned())),
+          );
+        }
+        PlaybackStatus::Paused => {
+          state.status = "Paused";
+          changed.insert(
+            "PlaybackStatus".into(),
+            Variant(Box::new("Paused".to_owned())),
+          );
+        }
+        PlaybackStatus::Repeat(mode) => {
+          state.repeat = mode;
+          let status = repeat::loop_status(mode).to_owned();
+          changed.insert("LoopStatus".into(), Variant(Box::new(status)));
+        }
+        PlaybackStatus::Shuffle(shuffle) => {
+          state.shuffle = shuffle;
+          changed.insert("Shuffle".into(), Variant(Box::new(shuffle)));
+        }
+      }
+    }
+    if changed.is_empty() {
+      continue;
+    }
+
+    let signal = PropertiesPropertiesChanged {
+      interface_name: PLAYER.to_owned(),
+      changed_properties: changed,
+      invalidated_properties: vec![],
+    };
+    let _ = connection.send(signal.to_emit_message(&PATH.into()));
+  }
+}
+
+fn command(
+  b: &mut IfaceBuilder<()>,
+  commands: &Commands,
+  name: &'static str,
+  command: fn() -> AppCommand,
+) {
+  let commands = commands.clone();
+  b.method(name, (), (), move |_, _, _: ()| {
+    let _ = commands.send(command());
+    Ok(())
+  });
+}
+
+fn metadata(metadata: &Metadata) -> PropMap {
+  let mut dict = PropMap::new();
+  let track = dbus::Path::from("/org/aquinas/track");
+  dict.insert("mpris:trackid".into(), Variant(Box::new(track)));
+  if let Some(title) = &metadata.title {
+    dict.insert("xesam:title".into(), Variant(Box::new(title.clone())));
+  }
+  if let Some(artist) = &metadata.artist {
+    dict.insert(
+      "xesam:artist".into(),
+      Variant(Box::new(vec![artist.clone()])),
+    );
+  }
+  if let Some(album) = &metadata.album {
+    dict.insert("xesam:album".into(), Variant(Box::new(album.clone())));
+  }
+  dict
+}
@@ -0,0 +1,158 @@
+use crate::*;
+use serde_derive::{Deserialize, Serialize};
+use std::{
+  io::Write,
+  sync::OnceLock,
+  time::{SystemTime, UNIX_EPOCH},
+};
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct Play {
+  pub start: i64, // unix seconds
+  pub path: PathBuf,
+  pub listened: u64, // seconds
+  pub skipped: bool,
+}
+
+// Every play, oldest first, in `history.toml`. Plays are appended as
+// `[[play]]` tables so the file never needs writing out whole.
+#[derive(Deserialize, Serialize, Default)]
+struct History {
+  #[serde(default, rename = "play")]
+  plays: Vec<Play>,
+  // (play count, last played) by path, skips left out
+  #[serde(skip)]
+  counts: HashMap<PathBuf, (u32, i64)>,
+}
+
+static HISTORY: OnceLock<Mutex<History>> = OnceLock::new();
+
+fn history() -> &'static Mutex<History> {
+  HISTORY.get_or_init(|| {
+    let mut history = History::load().unwrap_or_default();
+    for play in history.plays.clone() {
+      history.count(&play);
+    }
+    Mutex::new(history)
+  })
+}
+
+impl History {
+  fn path() -> Result<PathBuf> {
+    Ok(Meta::config_dir()?.join("history.toml"))
+  }
+
+  fn load() -> Result<Self> {
+    Ok(toml::from_str(&fs::read_to_string(Self::path()?)?)?)
+  }
+
+  fn append(play: &Play) -> Result<()> {
+    let path = Self::path()?;
+    if let Some(dir) = path.parent() {
+      let _ = fs::create_dir_all(dir);
+    }
+    let table = History {
+      plays: vec![play.clone()],
+      ..Default::default()
+    };
+    let mut file = fs::OpenOptions::new()
+      .create(true)
+      .append(true)
+      .open(path)?;
+    writeln!(file, "{}", toml::to_string(&table)?)?;
+    Ok(())
+  }
+
+  fn count(&mut self, play: &Play) {
+    if play.skipped {
+      return;
+    }
+    let (count, last) = self.counts.entry(play.path.clone()).or_default();
+    *count += 1;
+    *last = (*last).max(play.start);
+  }
+}
+
+pub fn now() -> i64 {
+  SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .map(|d| d.as_secs() as i64)
+    .unwrap_or(0)
+}
+
+pub fn record(play: Play) {
+  let _ = History::append(&play);
+  let mut history = history().lock();
+  history.count(&play);
+  history.plays.push(play);
+}
+
+// Times played through, or at least half way.
+pub fn plays(path: &Path) -> u32 {
+  history().lock().counts.get(path).map_or(0, |c| c.0)
+}
+
+pub fn last_played(path: &Path) -> Option<i64> {
+  history().lock().counts.get(path).map(|c| c.1)
+}
+
+// Every play, oldest first.
+pub fn all() -> Vec<Play> {
+  history().lock().plays.clone()
+}
+
+// The latest plays first, skips included.
+pub fn recent(limit: usize) -> Vec<Play> {
+  history()
+    .lock()
+    .plays
+    .iter()
+    .rev()
+    .take(limit)
+    .cloned()
+    .collect()
+}
+
+// Distinct files played, latest first.
+pub fn recent_paths(limit: usize) -> Vec<PathBuf> {
+  let history = history().lock();
+  let mut seen = HashSet::new();
+  history
+    .plays
+    .iter()
+    .rev()
+    .filter(|play| !play.skipped && seen.insert(play.path.clone()))
+    .take(limit)
+    .map(|play| play.path.clone())
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn appended_plays_read_back_in_order() {
+    let plays: Vec<Play> = (0..2)
+      .map(|i| Play {
+        start: 1_700_000_000 + i,
+        path: PathBuf::from(format!("/music/{}.mp3", i)),
+        listened: 200,
+        skipped: i == 1,
+      })
+      .collect();
+    let text: String = plays
+      .iter()
+      .map(|play| {
+        let table = History {
+          plays: vec![play.clone()],
+          ..Default::default()
+        };
+        format!("{}\n", toml::to_string(&table).unwrap())
+      })
+      .collect();
+
+    let history: History = toml::from_str(&text).unwrap();
+    assert_eq!(history.plays, plays);
+  }
+}
@@ -1,11 +1,32 @@
+mod admission;
+#[cfg(feature = "metadata")]
+pub mod browse;
+pub mod index;
+mod pool;
+mod watch;
+
 use crate::*;
+use crate::{
+  archive,
+  playlist::{self, smart},
+};
 use core::fmt;
-use std::fmt::Display;
+use std::{fmt::Display, time::Instant};
 
 type OpenDirs = HashSet<PathBuf>;
 type Dirs = HashMap<PathBuf, Arc<Node>>;
 type FileList = Vec<(Arc<Node>, usize)>;
 
+// Folders mostly wait on the disk, so more of them are read at once than
+// files' tags, which are parsed too.
+const SCAN_WORKERS: usize = 4;
+#[cfg(feature = "metadata")]
+const TAG_WORKERS: usize = 2;
+
+// How often the whole tree's walked again while folders and tags are still
+// coming in. What's on screen follows along in between.
+const RELIST: Duration = Duration::from_secs(1);
+
 enum MaybeNode {
   Node(Arc<Node>),
   Path(PathBuf),
@@ -15,19 +36,32 @@ pub struct Library {
   pub root: Arc<Node>,
   pub dirs: Dirs,
   pub open_dirs: OpenDirs,
+  // read once for the library, rather than by every folder
+  pub config: Arc<Config>,
   shallow_list: FileList,
   list: FileList,
   masked_list: FileList,
   query: String,
+  search: String, // the query as typed
+  watch: watch::Watch,
+  scanner: pool::Pool<Arc<Node>>,
+  // whether `list` is behind what's been read, and when it was last made
+  stale: bool,
+  listed: Instant,
 
+  // reads tags the index doesn't have yet, shown rows first
+  #[cfg(feature = "metadata")]
+  tagger: pool::Pool<Option<Metadata>>,
   #[cfg(feature = "metadata")]
-  _metadata: HashMap<PathBuf, Metadata>,
+  pub view: browse::View,
 }
 
 impl Library {
   pub fn new(root: impl AsRef<Path>) -> Self {
     let mut dirs = HashMap::new();
-    let root = Node::new(root);
+    smart::invalidate();
+    let config = Arc::new(Config::load().unwrap_or_default());
+    let root = Library::root_node(root.as_ref(), &config);
     dirs.insert(root.path.clone(), root.clone());
 
     let mut library = Self {
@@ -36,15 +70,36 @@ impl Library {
       open_dirs: HashSet::new(),
       shallow_list: Vec::new(),
       query: String::new(),
+      search: String::new(),
+      watch: watch::Watch::new(&root.path),
+      scanner: pool::Pool::new(SCAN_WORKERS, {
+        let config = config.clone();
+        move |path| Node::new(path, &config)
+      }),
+      config,
+      stale: false,
+      listed: Instant::now(),
       list: Vec::new(),
       masked_list: Vec::new(),
 
       #[cfg(feature = "metadata")]
-      _metadata: HashMap::new(),
+      tagger: pool::Pool::new(TAG_WORKERS, index::metadata),
+      #[cfg(feature = "metadata")]
+      view: browse::View::Folders,
     };
 
     library.open_dirs.insert(root.path.clone());
-    let nodes: FileList = root.path.as_path().to_iter(&library.dirs, None).collect();
+    library.tag_files(&root);
+    let nodes: FileList = root
+      .path
+      .as_path()
+      .to_iter(
+        &mut library.dirs,
+        Some(&mut library.scanner),
+        None,
+        &library.config,
+      )
+      .collect();
     // let mask_map = Library::build_index(root.path.clone(), &nodes);
 
     library.list = nodes;
@@ -53,6 +108,166 @@ impl Library {
     library
   }
 
+  // The root, with smart playlists at the top.
+  fn root_node(path: &Path, config: &Config) -> Arc<Node> {
+    let mut root = Node::new(path, config);
+    let smart = smart::folders(&root.path);
+    if let Some(folders) = &mut Arc::make_mut(&mut root).folders {
+      folders.splice(0..0, smart.into_iter().map(FolderKey::from));
+    }
+    root
+  }
+
+  // Drops the cached node for a folder, and for the playlists and archives
+  // in it, so they're read again on the next rebuild.
+  pub fn invalidate(&mut self, path: &Path) {
+    self.dirs.remove(path);
+    self.scanner.forget(path);
+    self
+      .dirs
+      .retain(|cached, _| cached.parent() != Some(path) || cached.is_dir());
+    if path == self.root.path {
+      self.root = Library::root_node(path, &self.config);
+      self.dirs.insert(self.root.path.clone(), self.root.clone());
+    }
+  }
+
+  // Drops the smart playlists' lists, so the scanner makes them again from
+  // the library as it is now.
+  pub fn invalidate_smart(&mut self) {
+    smart::invalidate();
+    let lists: Vec<PathBuf> = self
+      .dirs
+      .keys()
+      .filter(|path| smart::is_smart(path))
+      .cloned()
+      .collect();
+    for path in lists {
+      self.dirs.remove(&path);
+      self.scanner.forget(&path);
+    }
+  }
+
+  // Has the scanner make the smart playlists that go by plays again, after
+  // one. What's there stays up until they're in.
+  pub fn played(&mut self) {
+    let names = smart::forget_played();
+    let lists: Vec<PathBuf> = self
+      .dirs
+      .keys()
+      .filter(|path| smart::is_smart(path))
+      .filter(|path| {
+        let name = path.file_name().unwrap_or_default();
+        names.iter().any(|n| name == OsStr::new(n))
+      })
+      .cloned()
+      .collect();
+    for path in lists {
+      self.scanner.forget(&path);
+      self.scanner.request(&path, false);
+    }
+  }
+
+  // Picks up folders the scanner's read and what's changed on disk under
+  // the root, returning whether the tree's changed. Open folders stay open,
+  // unless they're gone. The whole tree, which browse views and searches
+  // are made from, is walked again at most every `RELIST` until the
+  // scanner and tagger are done.
+  pub fn refresh(&mut self) -> bool {
+    let scanned = self.scanner.finished();
+    let tagged = self.retag();
+    let changed = self.watch.settled();
+    let edited = smart::edited();
+    let any = !scanned.is_empty() || tagged || changed.is_some() || edited;
+
+    if !scanned.is_empty() {
+      let limit = self.config.scan_depth_limit;
+      for (path, node) in scanned {
+        self.tag_files(&node);
+        self.scan_folders(&node, limit);
+        self.dirs.insert(path, node);
+      }
+    }
+    if changed.is_some() || edited {
+      self.invalidate_smart();
+    }
+    for path in changed.iter().flatten() {
+      self.invalidate(path);
+    }
+    if any {
+      let root = self.root.path.clone();
+      self.open_dirs.retain(|path| {
+        path.exists()
+          || archive::split(path).is_some()
+          || smart::is_smart(path)
+          || is_browse(&root, path)
+      });
+      self.stale = true;
+    }
+
+    if !self.stale {
+      return false;
+    }
+    if !self.idle() && self.listed.elapsed() < RELIST {
+      if any {
+        self.rebuild();
+      }
+      return any;
+    }
+    self.relist();
+    true
+  }
+
+  // Walks the whole tree again, and makes what comes from it.
+  fn relist(&mut self) {
+    self.list = self
+      .root
+      .path
+      .as_path()
+      .to_iter(&mut self.dirs, Some(&mut self.scanner), None, &self.config)
+      .collect();
+    self.stale = false;
+    self.listed = Instant::now();
+    #[cfg(feature = "metadata")]
+    self.browse();
+    self.rebuild();
+    if !self.query.is_empty() {
+      let search = self.search.clone();
+      self.search(search);
+    }
+  }
+
+  // Asks for the folders in one that's just been read, so the scan carries
+  // on between walks of the whole tree, as deep as they'd go.
+  fn scan_folders(&mut self, node: &Node, limit: usize) {
+    let depth = match node.path.strip_prefix(&self.root.path) {
+      Ok(relative) => relative.components().count(),
+      Err(_) => return,
+    };
+    if depth >= limit {
+      return;
+    }
+    for folder in node.folders.iter().flatten() {
+      if !self.dirs.contains_key(&folder.path) {
+        self.scanner.request(&folder.path, false);
+      }
+    }
+  }
+
+  #[cfg(feature = "metadata")]
+  fn idle(&self) -> bool {
+    self.scanner.idle() && self.tagger.idle()
+  }
+  #[cfg(not(feature = "metadata"))]
+  fn idle(&self) -> bool {
+    self.scanner.idle()
+  }
+
+  // Why changes on disk aren't being followed, when they aren't.
+  pub fn watch_error(&self) -> Option<&str> {
+    self.watch.error()
+  }
+
   pub fn file_list(&self) -> &FileList {
     match self.query.as_str() {
       "" => &self.shallow_list,
@@ -60,6 +275,93 @@ impl Library {
     }
   }
 
+  // Reads a folder right away if it's still waiting on the scanner, for
+  // when its contents are needed now, like playing into it.
+  pub fn load(&mut self, path: &Path) {
+    let folder = path.is_dir()
+      || archive::is_dir(path)
+      || playlist::is_playlist(path)
+      || smart::is_smart(path);
+    if folder && !self.dirs.contains_key(path) {
+      let node = Node::new(path, &self.config);
+      self.tag_files(&node);
+      self.dirs.insert(path.to_owned(), node);
+    }
+  }
+
+  // Asks for the tags of a folder's files, after what's on screen.
+  #[cfg(feature = "metadata")]
+  fn tag_files(&mut self, node: &Node) {
+    for file in node.files.iter().flatten().filter(|f| !f.tagged) {
+      self.tagger.request(&file.path, false);
+    }
+  }
+  #[cfg(not(feature = "metadata"))]
+  fn tag_files(&mut self, _node: &Node) {}
+
+  // Asks for the tags of files in `rows` of the list ahead of the rest.
+  #[cfg(feature = "metadata")]
+  pub fn tag_visible(&mut self, rows: &Range<usize>) {
+    let untagged: Vec<PathBuf> = self
+      .file_list()
+      .get_range(rows)
+      .iter()
+      .filter(|(node, _)| node.is_file() && !node.tagged)
+      .map(|(node, _)| node.path.clone())
+      .collect();
+    for path in untagged {
+      self.tagger.request(&path, true);
+    }
+  }
+  #[cfg(not(feature = "metadata"))]
+  pub fn tag_visible(&mut self, _rows: &Range<usize>) {}
+
+  // Asks for the tags of files that are needed now, ahead of the rest.
+  #[cfg(feature = "metadata")]
+  pub fn tag_now(&mut self, files: &[Arc<Node>]) {
+    for file in files {
+      self.tagger.request(&file.path, true);
+    }
+  }
+
+  // Puts tags that have been read into the cached folders and playlists
+  // holding those files, returning whether there were any. Smart playlists
+  // pick them up from the index when they're next made.
+  #[cfg(feature = "metadata")]
+  fn retag(&mut self) -> bool {
+    let tagged: HashMap<PathBuf, Option<Metadata>> = self.tagger.finished().into_iter().collect();
+    if tagged.is_empty() {
+      return false;
+    }
+    let parents: HashSet<&Path> = tagged.keys().filter_map(|path| path.parent()).collect();
+
+    for (path, node) in self.dirs.iter_mut() {
+      if !parents.contains(path.as_path()) && !playlist::is_playlist(path) {
+        continue;
+      }
+      let holds_any = |node: &Node| {
+        let mut files = node.files.iter().flatten();
+        files.any(|file| tagged.contains_key(&file.path))
+      };
+      if !holds_any(node) {
+        continue;
+      }
+      for file in Arc::make_mut(node).files.iter_mut().flatten() {
+        if let Some(tags) = tagged.get(&file.path) {
+          Arc::make_mut(file).tag(tags.clone());
+        }
+      }
+    }
+    if let Some(root) = self.dirs.get(&self.root.path) {
+      self.root = root.clone();
+    }
+    true
+  }
+  #[cfg(not(feature = "metadata"))]
+  fn retag(&mut self) -> bool {
+    false
+  }
+
   pub fn expand(&mut self, path: impl AsRef<Path>) {
     self.expand_all(&[path]);
   }
@@ -67,7 +369,11 @@ impl Library {
   pub fn expand_all(&mut self, paths: &[impl AsRef<Path>]) {
     for path in paths {
       let path = path.as_ref();
-      if !path.is_dir() || self.open_dirs.get(path).is_some() {
+      let expandable = match self.dirs.get(path) {
+        Some(node) => node.is_dir(),
+        None => archive::is_dir(path) || playlist::is_playlist(path) || smart::is_smart(path),
+      };
+      if !expandable || self.open_dirs.get(path).is_some() {
         continue;
       }
 
@@ -77,47 +383,168 @@ impl Library {
     self.rebuild();
   }
 
+  // A folder as it's been read, or standing in as `scanning` until it is.
+  pub fn folder(&self, path: &Path) -> Arc<Node> {
+    match self.dirs.get(path) {
+      Some(node) => node.clone(),
+      None => Node::scanning(path.to_owned()),
+    }
+  }
+
+  // Every file under a folder, in tree order, from the folders read so far.
+  // Any that aren't are asked of the scanner ahead of the rest, and it's
+  // None until they're in.
+  pub fn all_files(&mut self, folder: &Arc<Node>) -> Option<Vec<Arc<Node>>> {
+    let folder = match self.dirs.get(&folder.path) {
+      Some(cached) => cached.clone(),
+      None if folder.scanning => {
+        self.scanner.request(&folder.path, true);
+        return None;
+      }
+      None => folder.clone(),
+    };
+    let mut files = vec![];
+    let mut read = true;
+    self.add_files(&folder, &mut files, &mut read);
+    match read {
+      true => Some(files),
+      false => None,
+    }
+  }
+
+  fn add_files(&mut self, folder: &Arc<Node>, files: &mut Vec<Arc<Node>>, read: &mut bool) {
+    for inner in folder.folders.iter().flatten() {
+      match self.dirs.get(&inner.path) {
+        Some(inner) => self.add_files(&inner.clone(), files, read),
+        None => {
+          self.scanner.request(&inner.path, true);
+          *read = false;
+        }
+      }
+    }
+    let found = folder.files.iter().flatten();
+    files.extend(found.filter(|file| file.missing == 0).cloned());
+  }
+
   pub fn collapse(&mut self, path: impl AsRef<Path>) {
     self.open_dirs.remove(path.as_ref());
 
     self.rebuild();
   }
 
+  // `*N` keeps files rated N stars or more, `*fav` keeps favourites.
   pub fn search(&mut self, query: impl AsRef<str>) {
-    self.query = searchify(query.as_ref());
+    self.search = query.as_ref().to_owned();
+    let (filter, text) = rating_filter(query.as_ref());
+    self.query = searchify(&text);
     self.masked_list = Vec::new();
 
     for (node, depth) in &self.list {
-      if node.name_search.contains(&self.query) {
+      if !node.name_search.contains(&self.query) {
+        continue;
+      }
+      let rated = match filter {
+        None => true,
+        Some(_) if !node.is_file() => false,
+        Some((stars, favourite)) => {
+          let rating = ratings::get(node);
+          rating.stars >= stars && (rating.favourite || !favourite)
+        }
+      };
+      if rated {
         self.masked_list.push((node.clone(), *depth));
       }
     }
   }
 
+  // The folder the tree is shown from: the root, or a browse view's.
+  fn top(&self) -> PathBuf {
+    #[cfg(feature = "metadata")]
+    if let Some(top) = self.view.root(&self.root.path) {
+      return top;
+    }
+    self.root.path.clone()
+  }
+
+  #[cfg(feature = "metadata")]
+  pub fn set_view(&mut self, view: browse::View) {
+    self.view = view;
+    self.browse();
+    self.rebuild();
+  }
+
+  // Makes the browse view's folders from the files found so far, replacing
+  // the last ones. They're made again as folders are read and tagged.
+  #[cfg(feature = "metadata")]
+  fn browse(&mut self) {
+    let browse_dir = browse::browse_dir(&self.root.path);
+    self.dirs.retain(|path, _| !path.starts_with(&browse_dir));
+    if self.view == browse::View::Folders {
+      return;
+    }
+
+    // files in playlists and smart playlists are found in their folders too
+    let mut seen = HashSet::new();
+    let files: Vec<Arc<Node>> = self
+      .list
+      .iter()
+      .filter(|(node, _)| node.is_file() && node.missing == 0)
+      .filter(|(node, _)| seen.insert(node.path.clone()))
+      .map(|(node, _)| node.clone())
+      .collect();
+    for node in browse::build(self.view, &self.root.path, &files) {
+      self.dirs.insert(node.path.clone(), node);
+    }
+  }
+
   pub fn rebuild(&mut self) {
     self.shallow_list = self
-      .root
-      .path
+      .top()
       .as_path()
-      .to_iter(&self.dirs, Some(&self.open_dirs))
-      // .to_iter(&self.dirs, None)
+      .to_iter(
+        &mut self.dirs,
+        Some(&mut self.scanner),
+        Some(&self.open_dirs),
+        &self.config,
+      )
       .collect();
+    // not while scanning, when it'd be written out over and over
+    if self.idle() {
+      index::save();
+      admission::save();
+    }
   }
 }
 
+// Walks the tree, caching every node it makes in `dirs` so later walks
+// don't go back to the disk. With a scanner, folders not yet read are handed to it and stand in as
+// empty `scanning` nodes until it's done.
 pub struct DirsIter<'a> {
-  dirs: &'a Dirs,
+  dirs: &'a mut Dirs,
+  scanner: Option<&'a mut pool::Pool<Arc<Node>>>,
   open_dirs: Option<&'a OpenDirs>,
   stack: FileList,
-  config: Config,
+  config: &'a Config,
 }
 
 pub trait IterablePath<'a> {
-  fn to_iter(&'a self, dirs: &'a Dirs, open_dirs: Option<&'a OpenDirs>) -> DirsIter<'a>;
+  fn to_iter(
+    &'a self,
+    dirs: &'a mut Dirs,
+    scanner: Option<&'a mut pool::Pool<Arc<Node>>>,
+    open_dirs: Option<&'a OpenDirs>,
+    config: &'a Config,
+  ) -> DirsIter<'a>;
 }
 impl<'a> IterablePath<'a> for &Path {
-  fn to_iter(&self, dirs: &'a Dirs, open_dirs: Option<&'a OpenDirs>) -> DirsIter<'a> {
-    let start_path = match self.is_dir() {
+  fn to_iter(
+    &self,
+    dirs: &'a mut Dirs,
+    scanner: Option<&'a mut pool::Pool<Arc<Node>>>,
+    open_dirs: Option<&'a OpenDirs>,
+    config: &'a Config,
+  ) -> DirsIter<'a> {
+    let start_path = match dirs.contains_key(*self) || self.is_dir() {
       true => self,
       false => self.parent().unwrap(),
     };
@@ -129,9 +556,10 @@ impl<'a> IterablePath<'a> for &Path {
 
     DirsIter {
       dirs,
+      scanner,
       open_dirs,
       stack,
-      config: Config::load().unwrap_or_default(),
+      config,
     }
   }
 }
@@ -150,7 +578,17 @@ impl<'a> Iterator for DirsIter<'a> {
       let child = match child {
         MaybeNode::Path(p) => match self.dirs.get(&p) {
           Some(node) => node.clone(),
-          _ => Node::new(p),
+          // what's on screen is read before what only searching needs
+          None if self.scanner.is_some() => {
+            let urgent = self.open_dirs.is_some();
+            self.scanner.as_mut().unwrap().request(&p, urgent);
+            Node::scanning(p)
+          }
+          None => {
+            let node = Node::new(&p, self.config);
+            self.dirs.insert(p, node.clone());
+            node
+          }
         },
         MaybeNode::Node(n) => n,
       };
@@ -175,12 +613,21 @@ pub struct Node {
   pub path: PathBuf,
   pub files: Option<Vec<Arc<Node>>>,
   pub folders: Option<Vec<FolderKey>>,
+  // playlist entries whose files are gone, 1 for such an entry itself
+  pub missing: usize,
+  // a playable file, as found when the node was made
+  file: bool,
+  // a folder the scanner hasn't read yet
+  pub scanning: bool,
   name: String,
   name_search: String,
   sort_key: String,
 
   #[cfg(feature = "metadata")]
   pub metadata: Option<Metadata>,
+  // whether `metadata` has been read, as tags are read in the background
+  #[cfg(feature = "metadata")]
+  pub tagged: bool,
 }
 
 #[derive(Clone, Debug, PartialEq, PartialOrd, Eq, Ord)]
@@ -200,16 +647,26 @@ impl From<PathBuf> for FolderKey {
 
 impl Node {
   pub fn is_dir(&self) -> bool {
-    self.path.is_dir()
+    self.folders.is_some()
   }
   pub fn is_file(&self) -> bool {
-    self.path.is_file()
+    self.file
+  }
+  // Stars in the file's rating tag, once the tagger's read it.
+  #[cfg(feature = "metadata")]
+  pub fn stars(&self) -> Option<u8> {
+    self.metadata.as_ref()?.stars
   }
+  #[cfg(not(feature = "metadata"))]
+  pub fn stars(&self) -> Option<u8> {
+    None
+  }
+
   pub fn title(&self) -> &str {
     #[cfg(feature = "metadata")]
     if let Some(m) = &self.metadata {
       if let Some(t) = &m.title {
-        return &t;
+        return t;
       }
     }
     &self.name
@@ -232,63 +689,301 @@ impl Node {
     None
   }
 
-  pub fn new(path: impl AsRef<Path>) -> Arc<Self> {
+  pub fn new(path: impl AsRef<Path>, config: &Config) -> Arc<Self> {
     let path = path.as_ref().to_path_buf();
+    // tags the index doesn't have are read later, by the library's tagger
     #[cfg(feature = "metadata")]
-    let metadata = get_metadata(&path);
+    let (metadata, tagged) = match index::indexed_metadata(&path) {
+      Some(tags) => (tags, true),
+      None => (None, false),
+    };
     let name = path.file_name().unwrap().to_string_lossy().to_string();
 
-    let (files, folders) = match path.is_dir() {
-      true => {
-        let mut files = vec![];
-        let mut folders: Vec<FolderKey> = vec![];
-
-        if let Ok(paths) = fs::read_dir(&path) {
-          for entry in paths {
-            let path = entry.unwrap().path();
-
-            if path.is_dir() {
-              // better filtering in the future, for now remove the obvious junk
-              if let Some(name) = path.file_name() {
-                if let Some(name) = name.to_str() {
-                  if name.chars().nth(0) != Some('.') {
-                    folders.push(path.into());
-                  }
-                }
-              }
-            } else {
-              // filter out bad files
-              if let Some(ext) = path.extension() {
-                if let Some(ext) = ext.to_str() {
-                  if SUPPORTED.contains(&ext) {
-                    files.push(Node::new(path));
-                  }
-                }
-              }
-            }
-          }
-        }
+    if let Some(files) = smart::list(&path, config) {
+      let files = files
+        .map(|files| files.iter().map(|file| Node::new(file, config)).collect())
+        .unwrap_or_default();
+      return Arc::new(Self {
+        path,
+        name_search: searchify(&name),
+        sort_key: name.to_lowercase(),
+        name: format!("✦ {}", name),
+        files: Some(files),
+        folders: Some(vec![]),
+        missing: 0,
+        scanning: false,
+        file: false,
+
+        #[cfg(feature = "metadata")]
+        metadata,
+        #[cfg(feature = "metadata")]
+        tagged,
+      });
+    }
+
+    if playlist::is_playlist(&path) {
+      let files: Vec<Arc<Node>> = playlist::read(&path)
+        .unwrap_or_default()
+        .into_iter()
+        .map(|entry| Node::listed(entry, config))
+        .collect();
+      return Arc::new(Self {
+        missing: files.iter().map(|f| f.missing).sum(),
+        scanning: false,
+        file: false,
+        path,
+        name_search: searchify(&name),
+        sort_key: name.to_lowercase(),
+        name,
+        files: Some(files),
+        folders: Some(vec![]),
+
+        #[cfg(feature = "metadata")]
+        metadata,
+        #[cfg(feature = "metadata")]
+        tagged,
+      });
+    }
+
+    // archives are listed like folders
+    let listing = match path.is_dir() {
+      true => Some(index::listing(&path, read_dir)),
+      false => archive::list(&path),
+    };
+
+    let (files, folders) = match listing {
+      Some((file_paths, folder_paths)) => {
+        let mut files: Vec<Arc<Node>> = file_paths
+          .into_iter()
+          .filter(|path| admission::admit(path, config))
+          .map(Node::file)
+          .collect();
+        let mut folders: Vec<FolderKey> = folder_paths.into_iter().map(FolderKey::from).collect();
 
         files.sort_by(|a, b| a.sort_key.partial_cmp(&b.sort_key).unwrap());
         folders.sort_by(|a, b| a.sort_key.partial_cmp(&b.sort_key).unwrap());
 
         (Some(files), Some(folders))
       }
-      false => (None, None),
+      None => (None, None),
     };
 
     Arc::new(Self {
+      file: folders.is_none() && (path.is_file() || archive::split(&path).is_some()),
       path,
       name_search: searchify(&name),
       sort_key: name.to_lowercase(),
       name,
       files,
       folders,
+      missing: 0,
+      scanning: false,
+
+      #[cfg(feature = "metadata")]
+      metadata,
+      #[cfg(feature = "metadata")]
+      tagged,
+    })
+  }
 
+  // A file from a folder's listing, which needs none of the checks `new`
+  // makes to tell what a path is.
+  fn file(path: PathBuf) -> Arc<Self> {
+    let name = path
+      .file_name()
+      .unwrap_or_default()
+      .to_string_lossy()
+      .to_string();
+    #[cfg(feature = "metadata")]
+    let (metadata, tagged) = match index::indexed_metadata(&path) {
+      Some(tags) => (tags, true),
+      None => (None, false),
+    };
+    Arc::new(Self {
       #[cfg(feature = "metadata")]
       metadata,
+      #[cfg(feature = "metadata")]
+      tagged,
+      path,
+      name_search: searchify(&name),
+      sort_key: name.to_lowercase(),
+      name,
+      files: None,
+      folders: None,
+      missing: 0,
+      scanning: false,
+      file: true,
+    })
+  }
+
+  // A browse view's group, holding files from all over.
+  #[cfg(feature = "metadata")]
+  fn group(path: PathBuf, files: Vec<Arc<Node>>, folders: Vec<PathBuf>) -> Arc<Self> {
+    let name = path
+      .file_name()
+      .unwrap_or_default()
+      .to_string_lossy()
+      .to_string();
+    Arc::new(Self {
+      metadata: None,
+      tagged: true,
+      path,
+      name_search: searchify(&name),
+      sort_key: name.to_lowercase(),
+      name,
+      files: Some(files),
+      folders: Some(folders.into_iter().map(FolderKey::from).collect()),
+      missing: 0,
+      scanning: false,
+      file: false,
+    })
+  }
+
+  // Stands in for a folder while the scanner reads it.
+  fn scanning(path: PathBuf) -> Arc<Self> {
+    let name = path
+      .file_name()
+      .unwrap_or_default()
+      .to_string_lossy()
+      .to_string();
+    Arc::new(Self {
+      #[cfg(feature = "metadata")]
+      metadata: None,
+      #[cfg(feature = "metadata")]
+      tagged: false,
+      path,
+      name_search: searchify(&name),
+      sort_key: name.to_lowercase(),
+      name,
+      files: Some(vec![]),
+      folders: Some(vec![]),
+      missing: 0,
+      scanning: true,
+      file: false,
     })
   }
+
+  // Fills in tags read in the background. Like in `listed`, what's in the
+  // file wins over what a playlist said.
+  #[cfg(feature = "metadata")]
+  fn tag(&mut self, tags: Option<Metadata>) {
+    self.tagged = true;
+    let mut tags = match tags {
+      Some(tags) => tags,
+      None => return,
+    };
+    if let Some(listed) = self.metadata.take() {
+      tags.title = tags.title.or(listed.title);
+      tags.artist = tags.artist.or(listed.artist);
+      tags.album = tags.album.or(listed.album);
+    }
+    self.metadata = Some(tags);
+  }
+
+  // A playlist entry, shown under the title it was given there.
+  pub fn listed(entry: playlist::Entry, config: &Config) -> Arc<Self> {
+    let mut node = Node::new(&entry.path, config);
+    let node_mut = Arc::make_mut(&mut node);
+    node_mut.missing = playlist::is_missing(&entry.path) as usize;
+    if let Some(title) = &entry.title {
+      node_mut.name_search = searchify(title);
+      node_mut.name = title.clone();
+    }
+
+    // tags in the file win over what the playlist says
+    #[cfg(feature = "metadata")]
+    {
+      let metadata = node_mut.metadata.get_or_insert_with(Metadata::default);
+      metadata.title = metadata.title.take().or(entry.title);
+      metadata.artist = metadata.artist.take().or(entry.artist);
+      metadata.album = metadata.album.take().or(entry.album);
+    }
+    node
+  }
+}
+
+// A browse view's group, which isn't on disk.
+#[cfg(feature = "metadata")]
+fn is_browse(root: &Path, path: &Path) -> bool {
+  path.starts_with(browse::browse_dir(root))
+}
+#[cfg(not(feature = "metadata"))]
+fn is_browse(_root: &Path, _path: &Path) -> bool {
+  false
+}
+
+// Every file under a folder, in tree order, from the index's listings
+// rather than nodes. Files in playlists are in their folders too, so
+// playlists are left out.
+pub fn files_under(folder: &Path, config: &Config) -> Vec<PathBuf> {
+  let mut files = vec![];
+  add_files(folder, config, &mut files);
+  admission::save();
+  files
+}
+
+fn add_files(folder: &Path, config: &Config, files: &mut Vec<PathBuf>) {
+  let listing = match folder.is_dir() {
+    true => Some(index::listing(folder, read_dir)),
+    false => archive::list(folder),
+  };
+  let (mut found, mut folders) = match listing {
+    Some(listing) => listing,
+    None => return,
+  };
+  // as nodes sort them
+  folders.sort_by_key(|path| path.display().to_string().to_lowercase());
+  for inner in folders.iter().filter(|path| !playlist::is_playlist(path)) {
+    add_files(inner, config, files);
+  }
+  found.retain(|path| admission::admit(path, config));
+  found.sort_by_key(|path| {
+    let name = path.file_name().unwrap_or_default();
+    name.to_string_lossy().to_lowercase()
+  });
+  files.extend(found);
+}
+
+// Splits a directory into (files, folders), counting archives and playlists as folders.
+fn read_dir(path: &Path) -> (Vec<PathBuf>, Vec<PathBuf>) {
+  let (mut files, mut folders) = (vec![], vec![]);
+  if let Ok(paths) = fs::read_dir(path) {
+    for entry in paths {
+      let path = entry.unwrap().path();
+
+      if path.is_dir() || archive::is_archive(&path) || playlist::is_playlist(&path) {
+        // better filtering in the future, for now remove the obvious junk
+        if let Some(name) = path.file_name() {
+          if let Some(name) = name.to_str() {
+            if !name.starts_with('.') {
+              folders.push(path);
+            }
+          }
+        }
+      } else {
+        files.push(path);
+      }
+    }
+  }
+  (files, folders)
+}
+
+// Splits the rating tokens out of a search, as (least stars, favourites only).
+fn rating_filter(query: &str) -> (Option<(u8, bool)>, String) {
+  let mut filter = None;
+  let mut text = vec![];
+  for word in query.split(' ') {
+    let (stars, favourite) = filter.unwrap_or((0, false));
+    match word.strip_prefix('*') {
+      Some("fav") => filter = Some((stars, true)),
+      Some(n) => match n.parse::<u8>() {
+        Ok(n) => filter = Some((n, favourite)),
+        Err(_) => text.push(word),
+      },
+      None => text.push(word),
+    }
+  }
+  (filter, text.join(" "))
 }
 
 fn searchify(key: &str) -> String {
@@ -306,7 +1001,10 @@ impl Display for Node {
 #[cfg(test)]
 mod tests {
   use std::env;
+  use std::fs;
   use std::path::Path;
+  use std::thread;
+  use std::time::Duration;
 
   use super::Library;
 
@@ -330,4 +1028,179 @@ mod tests {
 
     // println!("Mask: {:?}", library.mask_map);
   }
+
+  #[test]
+  fn folders_stay_cached_until_invalidated() {
+    let root = env::temp_dir().join(format!("aquinas-library-{}", std::process::id()));
+    let album = root.join("album");
+    let _ = fs::create_dir_all(&album);
+    fs::write(album.join("01.mp3"), b"").unwrap();
+
+    let in_album = |library: &Library| {
+      let list = library.file_list().iter();
+      list
+        .filter(|(node, _)| node.path.parent() == Some(&album))
+        .count()
+    };
+
+    // waits on the scanner, which reads folders as the tree reaches them
+    let scanned = |library: &mut Library| {
+      for _ in 0..500 {
+        if library.dirs.contains_key(&album) {
+          return;
+        }
+        library.refresh();
+        thread::sleep(Duration::from_millis(10));
+      }
+    };
+
+    let mut library = Library::new(&root);
+    library.expand(&album);
+    scanned(&mut library);
+    assert_eq!(in_album(&library), 1);
+
+    fs::write(album.join("02.mp3"), b"").unwrap();
+    library.rebuild();
+    assert_eq!(in_album(&library), 1);
+
+    library.invalidate(&album);
+    library.rebuild();
+    assert_eq!(in_album(&library), 0);
+    scanned(&mut library);
+    assert_eq!(in_album(&library), 2);
+
+    let _ = fs::remove_dir_all(&root);
+  }
+
+  #[test]
+  fn ratings_are_split_out_of_searches() {
+    use super::rating_filter;
+    assert_eq!(rating_filter("queen"), (None, "queen".to_owned()));
+    assert_eq!(
+      rating_filter("*4 live at wembley"),
+      (Some((4, false)), "live at wembley".to_owned())
+    );
+    assert_eq!(
+      rating_filter("*fav queen *3"),
+      (Some((3, true)), "queen".to_owned())
+    );
+    assert_eq!(rating_filter("*x"), (None, "*x".to_owned()));
+  }
+
+  #[test]
+  fn scans_go_deep_between_walks() {
+    let root = env::temp_dir().join(format!("aquinas-deep-{}", std::process::id()));
+    let deep = root.join("a/b/c/d");
+    let _ = fs::create_dir_all(&deep);
+    fs::write(deep.join("1.mp3"), b"").unwrap();
+
+    let mut library = Library::new(&root);
+    let listed = library.listed;
+    for _ in 0..50 {
+      library.refresh();
+      if library.dirs.contains_key(&deep) {
+        break;
+      }
+      thread::sleep(Duration::from_millis(5));
+    }
+    // each folder read asks for the ones in it, without walking the tree
+    assert!(library.dirs.contains_key(&deep));
+    for _ in 0..50 {
+      if library
+        .list
+        .iter()
+        .any(|(node, _)| node.path == deep.join("1.mp3"))
+      {
+        break;
+      }
+      library.refresh();
+      thread::sleep(Duration::from_millis(5));
+    }
+    assert!(library.listed > listed);
+    assert!(library
+      .list
+      .iter()
+      .any(|(node, _)| node.path == deep.join("1.mp3")));
+
+    let _ = fs::remove_dir_all(&root);
+  }
+
+  #[test]
+  fn all_files_wait_on_the_scanner() {
+    let root = env::temp_dir().join(format!("aquinas-all-files-{}", std::process::id()));
+    let _ = fs::create_dir_all(root.join("a/b"));
+    for file in ["a/b/2.mp3", "a/1.mp3", "3.mp3"] {
+      fs::write(root.join(file), b"").unwrap();
+    }
+
+    let mut library = Library::new(&root);
+    let top = library.root.clone();
+    // nothing's been picked up from the scanner yet
+    assert!(library.all_files(&top).is_none());
+    let mut files = None;
+    for _ in 0..500 {
+      library.refresh();
+      files = library.all_files(&top);
+      if files.is_some() {
+        break;
+      }
+      thread::sleep(Duration::from_millis(10));
+    }
+    let paths: Vec<_> = files.unwrap().iter().map(|f| f.path.clone()).collect();
+    assert_eq!(
+      paths,
+      [
+        root.join("a/b/2.mp3"),
+        root.join("a/1.mp3"),
+        root.join("3.mp3")
+      ]
+    );
+
+    let _ = fs::remove_dir_all(&root);
+  }
+
+  #[cfg(feature = "metadata")]
+  #[test]
+  fn browse_groups_stay_open_through_refreshes() {
+    let root = env::temp_dir().join(format!("aquinas-browse-{}", std::process::id()));
+    let album = root.join("album");
+    let _ = fs::create_dir_all(&album);
+    let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+    fs::copy(fixtures.join("tagged.flac"), album.join("tagged.flac")).unwrap();
+
+    // refreshes until the album's read and its files tagged
+    let settled = |library: &mut Library, count: usize| {
+      for _ in 0..500 {
+        let album = library.dirs.get(&album);
+        let files = album.map(|album| album.files.iter().flatten().filter(|f| f.tagged).count());
+        if files == Some(count) {
+          return;
+        }
+        library.refresh();
+        thread::sleep(Duration::from_millis(10));
+      }
+    };
+
+    let mut library = Library::new(&root);
+    settled(&mut library, 1);
+    library.set_view(super::browse::View::Artists);
+    let artist = super::browse::View::Artists
+      .root(&library.root.path)
+      .unwrap()
+      .join("Fixture Artist");
+    library.expand(&artist);
+    assert!(library.open_dirs.contains(&artist));
+
+    // seen by the watcher, then scanned and tagged, making the views again
+    fs::copy(fixtures.join("tagged.mp3"), album.join("tagged.mp3")).unwrap();
+    settled(&mut library, 2);
+    assert!(library.open_dirs.contains(&artist));
+    let open = library
+      .file_list()
+      .iter()
+      .any(|(node, _)| node.path.parent() == Some(&artist));
+    assert!(open);
+
+    let _ = fs::remove_dir_all(&root);
+  }
 }
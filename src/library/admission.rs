@@ -0,0 +1,107 @@
+use crate::*;
+use serde_derive::{Deserialize, Serialize};
+use std::{sync::OnceLock, time::UNIX_EPOCH};
+
+// Probing opens every unknown file, so results are kept by path until the file changes.
+#[derive(Deserialize, Serialize, Default)]
+struct ProbeCache {
+  files: HashMap<String, Probed>,
+  #[serde(skip)]
+  dirty: bool,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy)]
+struct Probed {
+  modified: u64,
+  playable: bool,
+}
+
+static CACHE: OnceLock<Mutex<ProbeCache>> = OnceLock::new();
+
+fn cache() -> &'static Mutex<ProbeCache> {
+  CACHE.get_or_init(|| Mutex::new(ProbeCache::load().unwrap_or_default()))
+}
+
+impl ProbeCache {
+  fn path() -> Result<PathBuf> {
+    Ok(Meta::config_dir()?.join("probe_cache.toml"))
+  }
+
+  fn load() -> Result<Self> {
+    Ok(toml::from_str(&fs::read_to_string(Self::path()?)?)?)
+  }
+
+  fn save(&mut self) -> Result<()> {
+    let path = Self::path()?;
+    if let Some(dir) = path.parent() {
+      let _ = fs::create_dir_all(dir);
+    }
+    fs::write(path, toml::to_string(self)?)?;
+    self.dirty = false;
+    Ok(())
+  }
+}
+
+// Whether a file belongs in the library: a listed extension, or when
+// `probe_files` is on, contents the backend recognises.
+pub fn admit(path: &Path, config: &Config) -> bool {
+  let listed = path
+    .extension()
+    .and_then(|e| e.to_str())
+    .map(|e| config.extensions.iter().any(|x| x.eq_ignore_ascii_case(e)))
+    .unwrap_or(false);
+
+  let hidden = path
+    .file_name()
+    .map(|n| n.to_string_lossy().starts_with('.'))
+    .unwrap_or(true);
+
+  listed || (config.probe_files && !hidden && probe(path))
+}
+
+fn probe(path: &Path) -> bool {
+  let modified = fs::metadata(path)
+    .and_then(|m| m.modified())
+    .ok()
+    .and_then(|m| m.duration_since(UNIX_EPOCH).ok())
+    .map(|d| d.as_secs())
+    .unwrap_or(0);
+  let key = path.to_string_lossy().to_string();
+
+  if let Some(probed) = cache().lock().files.get(&key) {
+    if probed.modified == modified {
+      return probed.playable;
+    }
+  }
+
+  let playable = crate::backends::probe(path);
+  let mut cache = cache().lock();
+  cache.files.insert(key, Probed { modified, playable });
+  cache.dirty = true;
+  playable
+}
+
+// Writes out newly probed files, once the scanner's done or a walk is.
+pub fn save() {
+  let mut cache = match CACHE.get() {
+    Some(cache) => cache.lock(),
+    None => return,
+  };
+  if cache.dirty {
+    let _ = cache.save();
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn extensions_ignore_case() {
+    let config = Config::default();
+    assert!(admit(Path::new("/music/TRACK.MP3"), &config));
+    assert!(admit(Path::new("/music/song.Flac"), &config));
+    assert!(!admit(Path::new("/music/cover.jpg"), &config));
+    assert!(!admit(Path::new("/music/README"), &config));
+  }
+}
@@ -0,0 +1,212 @@
+use super::*;
+use std::collections::BTreeMap;
+
+// Ways through the library other than its folders, made from the tags of
+// the files found so far. Each view's groups are virtual folders under
+// `root/.aquinas-browse/<view>`, so the tree shows and plays them like any
+// other.
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub enum View {
+  #[default]
+  Folders,
+  Artists,      // artist, album, track
+  AlbumArtists, // album artist, album, track
+  Genres,       // genre, artist, track
+  Years,        // year, album, track
+}
+
+// first level group -> second level group -> tracks, with their tags
+type Groups<'a> = BTreeMap<String, BTreeMap<String, Vec<(&'a Metadata, Arc<Node>)>>>;
+
+pub const UNKNOWN: &str = "Unknown";
+const VARIOUS: &str = "Various Artists";
+
+impl View {
+  pub fn next(self) -> Self {
+    match self {
+      View::Folders => View::Artists,
+      View::Artists => View::AlbumArtists,
+      View::AlbumArtists => View::Genres,
+      View::Genres => View::Years,
+      View::Years => View::Folders,
+    }
+  }
+
+  pub fn name(self) -> &'static str {
+    match self {
+      View::Folders => "Folders",
+      View::Artists => "Artists",
+      View::AlbumArtists => "Album artists",
+      View::Genres => "Genres",
+      View::Years => "Years",
+    }
+  }
+
+  // Where the view's groups go, or None for the folders themselves.
+  pub fn root(self, root: &Path) -> Option<PathBuf> {
+    let dir = match self {
+      View::Folders => return None,
+      View::Artists => "artists",
+      View::AlbumArtists => "album-artists",
+      View::Genres => "genres",
+      View::Years => "years",
+    };
+    Some(browse_dir(root).join(dir))
+  }
+}
+
+pub fn browse_dir(root: &Path) -> PathBuf {
+  root.join(".aquinas-browse")
+}
+
+// A name that's safe as one path component.
+pub fn component(name: &str) -> String {
+  match name.trim() {
+    "" => UNKNOWN.to_owned(),
+    "." | ".." => name.replace('.', "·"),
+    name => name.replace('/', "∕"),
+  }
+}
+
+// Albums whose tracks have more than one artist, by (album, folder). These
+// go under their album artist in the artists view, not every track artist.
+fn compilations(files: &[Arc<Node>]) -> HashSet<(String, PathBuf)> {
+  let mut artists: HashMap<(String, PathBuf), HashSet<String>> = HashMap::new();
+  for file in files {
+    let tags = match &file.metadata {
+      Some(tags) => tags,
+      None => continue,
+    };
+    if let (Some(album), Some(artist)) = (&tags.album, &tags.artist) {
+      let folder = file.path.parent().unwrap_or(&file.path).to_owned();
+      artists
+        .entry((album.clone(), folder))
+        .or_default()
+        .insert(artist.clone());
+    }
+  }
+  artists
+    .into_iter()
+    .filter(|(_, artists)| artists.len() > 1)
+    .map(|(album, _)| album)
+    .collect()
+}
+
+// The view's nodes, its root first: two levels of groups with the tracks
+// in the second, in album order.
+pub fn build(view: View, root: &Path, files: &[Arc<Node>]) -> Vec<Arc<Node>> {
+  let top = match view.root(root) {
+    Some(top) => top,
+    None => return vec![],
+  };
+  let compilations = match view {
+    View::Artists => compilations(files),
+    _ => HashSet::new(),
+  };
+
+  let untagged = Metadata::default();
+  let mut groups = Groups::new();
+  for file in files {
+    let tags = file.metadata.as_ref().unwrap_or(&untagged);
+    let text = |value: &Option<String>| value.clone().unwrap_or_else(|| UNKNOWN.to_owned());
+    let album_artist = || tags.album_artist.clone().or(tags.artist.clone());
+    let (first, second) = match view {
+      View::Artists => {
+        let folder = file.path.parent().unwrap_or(&file.path).to_owned();
+        let compilation = match &tags.album {
+          Some(album) => compilations.contains(&(album.clone(), folder)),
+          None => false,
+        };
+        let artist = match compilation {
+          true => Some(
+            tags
+              .album_artist
+              .clone()
+              .unwrap_or_else(|| VARIOUS.to_owned()),
+          ),
+          false => tags.artist.clone(),
+        };
+        (text(&artist), text(&tags.album))
+      }
+      View::AlbumArtists => (text(&album_artist()), text(&tags.album)),
+      View::Genres => (text(&tags.genre), text(&album_artist())),
+      View::Years => {
+        let year = tags.year().map(|year| year.to_string());
+        (text(&year), text(&tags.album))
+      }
+      View::Folders => return vec![],
+    };
+    groups
+      .entry(component(&first))
+      .or_default()
+      .entry(component(&second))
+      .or_default()
+      .push((tags, file.clone()));
+  }
+
+  let mut nodes = vec![];
+  let mut firsts = vec![];
+  for (first, seconds) in groups {
+    let first_path = top.join(&first);
+    let mut second_paths = vec![];
+    for (second, mut tracks) in seconds {
+      tracks.sort_by(|(a, x), (b, y)| {
+        let key = |tags: &Metadata| (tags.album.clone(), tags.disc_number, tags.track_number);
+        key(a).cmp(&key(b)).then(x.sort_key.cmp(&y.sort_key))
+      });
+      let path = first_path.join(&second);
+      let files = tracks.into_iter().map(|(_, file)| file).collect();
+      nodes.push(Node::group(path.clone(), files, vec![]));
+      second_paths.push(path);
+    }
+    nodes.push(Node::group(first_path.clone(), vec![], second_paths));
+    firsts.push(first_path);
+  }
+  nodes.insert(0, Node::group(top, vec![], firsts));
+  nodes
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn file(path: &str, artist: &str, album: &str, track: u16) -> Arc<Node> {
+    let mut node = Node::file(PathBuf::from(path));
+    let node_mut = Arc::make_mut(&mut node);
+    node_mut.metadata = Some(Metadata {
+      artist: Some(artist.to_owned()),
+      album: Some(album.to_owned()),
+      track_number: Some(track),
+      ..Default::default()
+    });
+    node_mut.tagged = true;
+    node
+  }
+
+  #[test]
+  fn compilations_go_under_their_album_artist() {
+    let files = [
+      file("/m/hits/02.mp3", "B", "Hits", 2),
+      file("/m/hits/01.mp3", "A", "Hits", 1),
+      file("/m/a/01.mp3", "A", "Solo", 1),
+    ];
+    let nodes = build(View::Artists, Path::new("/m"), &files);
+    let paths: Vec<&Path> = nodes.iter().map(|n| n.path.as_path()).collect();
+    let top = Path::new("/m/.aquinas-browse/artists");
+    assert_eq!(nodes[0].path, top);
+    assert!(paths.contains(&top.join("A/Solo").as_path()));
+    assert!(!paths.contains(&top.join("B").as_path()));
+
+    let hits = nodes
+      .iter()
+      .find(|n| n.path == top.join(VARIOUS).join("Hits"))
+      .unwrap();
+    let tracks: Vec<&str> = hits
+      .files
+      .iter()
+      .flatten()
+      .map(|f| f.name.as_str())
+      .collect();
+    assert_eq!(tracks, ["01.mp3", "02.mp3"]);
+  }
+}
@@ -0,0 +1,248 @@
+use crate::*;
+use serde_derive::{Deserialize, Serialize};
+use std::{sync::OnceLock, time::UNIX_EPOCH};
+
+// What's been read from disk, kept in `library_index` so the library opens
+// without walking it again. Folders are read again when their mtime changes,
+// and files' tags and durations when their mtime or size does. It's written
+// with postcard rather than toml, which takes too long to read for a big
+// collection.
+#[derive(Deserialize, Serialize, Default)]
+struct Index {
+  dirs: HashMap<String, Dir>,
+  files: HashMap<String, Indexed>,
+  #[serde(skip)]
+  dirty: bool,
+}
+
+// Written ahead of the index. An index from another version, or a build
+// with or without `metadata` (which changes `Indexed`), is started over.
+const VERSION: (u32, bool) = (3, cfg!(feature = "metadata"));
+
+#[derive(Deserialize, Serialize, Clone, PartialEq, Debug)]
+struct Dir {
+  modified: u64,
+  files: Vec<PathBuf>,
+  folders: Vec<PathBuf>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Default)]
+struct Indexed {
+  modified: u64,
+  size: u64,
+  duration: Option<u64>,
+  #[cfg(feature = "metadata")]
+  tags: Option<Metadata>,
+  // whether the fields above have been read, as opposed to missing
+  timed: bool,
+  tagged: bool,
+}
+
+static INDEX: OnceLock<Mutex<Index>> = OnceLock::new();
+
+fn index() -> &'static Mutex<Index> {
+  INDEX.get_or_init(|| Mutex::new(Index::load().unwrap_or_default()))
+}
+
+impl Index {
+  fn path() -> Result<PathBuf> {
+    Ok(Meta::config_dir()?.join("library_index"))
+  }
+
+  fn load() -> Result<Self> {
+    let bytes = fs::read(Self::path()?)?;
+    let (version, rest): ((u32, bool), _) = postcard::take_from_bytes(&bytes)?;
+    if version != VERSION {
+      bail!("index version {:?}, expected {:?}", version, VERSION);
+    }
+    Ok(postcard::from_bytes(rest)?)
+  }
+
+  fn save(&mut self) -> Result<()> {
+    let path = Self::path()?;
+    if let Some(dir) = path.parent() {
+      let _ = fs::create_dir_all(dir);
+    }
+    let mut bytes = postcard::to_stdvec(&VERSION)?;
+    bytes.extend(postcard::to_stdvec(self)?);
+    fs::write(path, bytes)?;
+    self.dirty = false;
+    Ok(())
+  }
+
+  // The listing of a folder if it hasn't changed since it was indexed.
+  fn dir(&self, path: &Path, modified: u64) -> Option<(Vec<PathBuf>, Vec<PathBuf>)> {
+    let dir = self.dirs.get(path.to_string_lossy().as_ref())?;
+    match dir.modified == modified {
+      true => Some((dir.files.clone(), dir.folders.clone())),
+      false => None,
+    }
+  }
+
+  // Indexes a folder's listing, forgetting files that have gone from it.
+  fn set_dir(&mut self, path: &Path, modified: u64, files: &[PathBuf], folders: &[PathBuf]) {
+    let key = path.to_string_lossy().to_string();
+    if let Some(old) = self.dirs.get(&key) {
+      for gone in old.files.iter().filter(|f| !files.contains(f)) {
+        self.files.remove(gone.to_string_lossy().as_ref());
+      }
+    }
+    let dir = Dir {
+      modified,
+      files: files.to_vec(),
+      folders: folders.to_vec(),
+    };
+    self.dirs.insert(key, dir);
+    self.dirty = true;
+  }
+
+  // A file's entry, emptied when the file has changed since.
+  fn file(&mut self, path: &Path, modified: u64, size: u64) -> &mut Indexed {
+    let indexed = self
+      .files
+      .entry(path.to_string_lossy().to_string())
+      .or_default();
+    if indexed.modified != modified || indexed.size != size {
+      *indexed = Indexed {
+        modified,
+        size,
+        ..Default::default()
+      };
+    }
+    indexed
+  }
+}
+
+// (mtime in nanoseconds, size, whether it's a folder), or None for what
+// isn't on disk, like files inside archives.
+fn stat(path: &Path) -> Option<(u64, u64, bool)> {
+  let metadata = fs::metadata(path).ok()?;
+  let modified = metadata
+    .modified()
+    .ok()?
+    .duration_since(UNIX_EPOCH)
+    .ok()?
+    .as_nanos() as u64;
+  Some((modified, metadata.len(), metadata.is_dir()))
+}
+
+// A folder's (files, folders), from the index while it's unchanged.
+pub fn listing(
+  path: &Path,
+  read: impl FnOnce(&Path) -> (Vec<PathBuf>, Vec<PathBuf>),
+) -> (Vec<PathBuf>, Vec<PathBuf>) {
+  let modified = match stat(path) {
+    Some((modified, _, _)) => modified,
+    None => return read(path),
+  };
+  if let Some(listing) = index().lock().dir(path, modified) {
+    return listing;
+  }
+
+  let (files, folders) = read(path);
+  index().lock().set_dir(path, modified, &files, &folders);
+  (files, folders)
+}
+
+#[cfg(feature = "metadata")]
+pub fn metadata(path: &Path) -> Option<Metadata> {
+  let (modified, size) = match stat(path) {
+    Some((_, _, true)) => return None,
+    Some((modified, size, false)) => (modified, size),
+    None => return get_metadata(path),
+  };
+  {
+    let mut index = index().lock();
+    let indexed = index.file(path, modified, size);
+    if indexed.tagged {
+      return indexed.tags.clone();
+    }
+  }
+
+  let tags = get_metadata(path);
+  let mut index = index().lock();
+  let indexed = index.file(path, modified, size);
+  indexed.tags = tags.clone();
+  indexed.tagged = true;
+  index.dirty = true;
+  tags
+}
+
+// A file's tags if they're indexed and it hasn't changed since, without
+// reading them otherwise.
+#[cfg(feature = "metadata")]
+pub fn indexed_metadata(path: &Path) -> Option<Option<Metadata>> {
+  let (modified, size) = match stat(path)? {
+    (_, _, true) => return Some(None),
+    (modified, size, false) => (modified, size),
+  };
+  let index = index().lock();
+  let indexed = index.files.get(path.to_string_lossy().as_ref())?;
+  match indexed.tagged && indexed.modified == modified && indexed.size == size {
+    true => Some(indexed.tags.clone()),
+    false => None,
+  }
+}
+
+// Length in seconds, probing the file only when it isn't indexed.
+pub fn duration(path: &Path) -> Option<u64> {
+  let (modified, size) = match stat(path) {
+    Some((_, _, true)) => return None,
+    Some((modified, size, false)) => (modified, size),
+    None => return backends::duration(path),
+  };
+  {
+    let mut index = index().lock();
+    let indexed = index.file(path, modified, size);
+    if indexed.timed {
+      return indexed.duration;
+    }
+  }
+
+  let duration = backends::duration(path);
+  let mut index = index().lock();
+  let indexed = index.file(path, modified, size);
+  indexed.duration = duration;
+  indexed.timed = true;
+  index.dirty = true;
+  duration
+}
+
+// Writes out what's changed.
+pub fn save() {
+  let mut index = match INDEX.get() {
+    Some(index) => index.lock(),
+    None => return,
+  };
+  if index.dirty {
+    let _ = index.save();
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn folders_are_read_again_once_changed() {
+    let dir = std::env::temp_dir().join(format!("aquinas-index-{}", std::process::id()));
+    let _ = fs::create_dir_all(&dir);
+    fs::write(dir.join("a.mp3"), b"a").unwrap();
+
+    let mut index = Index::default();
+    let (modified, _, _) = stat(&dir).unwrap();
+    index.set_dir(&dir, modified, &[dir.join("a.mp3")], &[]);
+    index.file(&dir.join("a.mp3"), 1, 1).duration = Some(60);
+    assert_eq!(index.dir(&dir, modified).unwrap().0, [dir.join("a.mp3")]);
+
+    std::thread::sleep(Duration::from_millis(10));
+    fs::remove_file(dir.join("a.mp3")).unwrap();
+    let (changed, _, _) = stat(&dir).unwrap();
+    assert!(index.dir(&dir, changed).is_none());
+
+    index.set_dir(&dir, changed, &[], &[]);
+    assert!(index.files.is_empty());
+
+    let _ = fs::remove_dir_all(&dir);
+  }
+}
@@ -0,0 +1,141 @@
+use crate::*;
+use crossbeam_channel::select;
+
+// Runs `work` over paths on a few threads, so slow disks don't hold up the
+// UI. Paths on screen go ahead of the ones only wanted eventually. Folders
+// are read this way, and with `metadata`, tags.
+pub struct Pool<T> {
+  urgent: Sender<(PathBuf, u64)>,
+  background: Sender<(PathBuf, u64)>,
+  results: Receiver<(PathBuf, u64, T)>,
+  // whether each was asked for urgently
+  pending: HashMap<PathBuf, bool>,
+  // bumped as paths are forgotten, so work already under way on them is
+  // told apart from work asked for since
+  generations: HashMap<PathBuf, u64>,
+}
+
+impl<T: Send + 'static> Pool<T> {
+  pub fn new(workers: usize, work: impl Fn(&Path) -> T + Send + Sync + 'static) -> Self {
+    let work = Arc::new(work);
+    let (urgent, urgent_rx) = crossbeam_channel::unbounded::<(PathBuf, u64)>();
+    let (background, background_rx) = crossbeam_channel::unbounded::<(PathBuf, u64)>();
+    let (results_tx, results) = crossbeam_channel::unbounded();
+
+    for _ in 0..workers {
+      let (urgent_rx, background_rx) = (urgent_rx.clone(), background_rx.clone());
+      let results_tx = results_tx.clone();
+      let work = work.clone();
+      // the workers stop once the pool, and so the senders, are dropped
+      thread::spawn(move || loop {
+        let job = match urgent_rx.try_recv() {
+          Ok(job) => Some(job),
+          Err(_) => select! {
+            recv(urgent_rx) -> job => job,
+            recv(background_rx) -> job => job,
+          }
+          .ok(),
+        };
+        let (path, generation) = match job {
+          Some(job) => job,
+          None => return,
+        };
+        let result = work(&path);
+        if results_tx.send((path, generation, result)).is_err() {
+          return;
+        }
+      });
+    }
+
+    Self {
+      urgent,
+      background,
+      results,
+      pending: HashMap::new(),
+      generations: HashMap::new(),
+    }
+  }
+
+  pub fn request(&mut self, path: &Path, urgent: bool) {
+    // something waiting in the background can still jump the queue, after
+    // which whichever copy's done first is kept
+    match self.pending.get(path) {
+      Some(true) => return,
+      Some(false) if !urgent => return,
+      _ => self.pending.insert(path.to_owned(), urgent),
+    };
+    let job = (path.to_owned(), self.generation(path));
+    let _ = match urgent {
+      true => self.urgent.send(job),
+      false => self.background.send(job),
+    };
+  }
+
+  // Forgets a path that's changed while it was being worked on, so the
+  // result is thrown away rather than kept, even once it's asked for again.
+  pub fn forget(&mut self, path: &Path) {
+    self.pending.remove(path);
+    *self.generations.entry(path.to_owned()).or_default() += 1;
+  }
+
+  fn generation(&self, path: &Path) -> u64 {
+    self.generations.get(path).copied().unwrap_or(0)
+  }
+
+  pub fn idle(&self) -> bool {
+    self.pending.is_empty()
+  }
+
+  // Results since last asked.
+  pub fn finished(&mut self) -> Vec<(PathBuf, T)> {
+    let mut finished = vec![];
+    while let Ok((path, generation, result)) = self.results.try_recv() {
+      if generation == self.generation(&path) && self.pending.remove(&path).is_some() {
+        finished.push((path, result));
+      }
+    }
+    finished
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::sync::atomic::{AtomicUsize, Ordering};
+
+  static CALLS: AtomicUsize = AtomicUsize::new(0);
+
+  // Which call this was, once the path's `.go` file is there.
+  fn count(path: &Path) -> usize {
+    while !path.with_extension("go").exists() {
+      thread::sleep(Duration::from_millis(1));
+    }
+    CALLS.fetch_add(1, Ordering::SeqCst)
+  }
+
+  #[test]
+  fn forgotten_work_is_thrown_away() {
+    let dir = std::env::temp_dir().join(format!("aquinas-pool-{}", std::process::id()));
+    let _ = fs::create_dir_all(&dir);
+    let path = dir.join("a");
+
+    let mut pool = Pool::new(1, count);
+    pool.request(&path, false);
+    pool.forget(&path);
+    pool.request(&path, false);
+    fs::write(path.with_extension("go"), b"").unwrap();
+
+    let mut finished = vec![];
+    for _ in 0..500 {
+      finished.extend(pool.finished());
+      if pool.idle() {
+        break;
+      }
+      thread::sleep(Duration::from_millis(2));
+    }
+    // the first call's result came from before the path was forgotten
+    assert_eq!(finished, [(path, 1)]);
+
+    let _ = fs::remove_dir_all(&dir);
+  }
+}
@@ -0,0 +1,148 @@
+use crate::*;
+use notify::{event::ModifyKind, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::time::Instant;
+
+// How long changes have to stop coming before the library's refreshed, so
+// copying in an album refreshes it once rather than for every file.
+const SETTLE: Duration = Duration::from_millis(800);
+
+// Watches the root (inotify on linux) from notify's own thread, collecting
+// the folders whose listings changed. Setting up a recursive watch goes
+// through the whole tree, so that's done on a thread of its own, which
+// keeps the watcher until the library's done with it.
+pub struct Watch {
+  changes: Receiver<PathBuf>,
+  started: Receiver<Result<(), String>>,
+  _stop: Sender<()>,
+  // why there's no watching, like running out of inotify watches
+  error: Option<String>,
+  pending: HashSet<PathBuf>,
+  last_change: Option<Instant>,
+}
+
+impl Watch {
+  pub fn new(root: &Path) -> Self {
+    let (sender, changes) = crossbeam_channel::unbounded();
+    let (started_tx, started) = crossbeam_channel::bounded(1);
+    let (_stop, stop) = crossbeam_channel::bounded::<()>(0);
+    let root = root.to_owned();
+    thread::spawn(move || {
+      let watcher = watch(&root, sender);
+      let _ = started_tx.send(watcher.as_ref().map(|_| ()).map_err(|e| e.to_string()));
+      // gives up once the library's dropped the other end
+      let _ = stop.recv();
+      drop(watcher);
+    });
+
+    Self {
+      changes,
+      started,
+      _stop,
+      error: None,
+      pending: HashSet::new(),
+      last_change: None,
+    }
+  }
+
+  // Why changes on disk aren't being picked up, if they aren't.
+  pub fn error(&self) -> Option<&str> {
+    self.error.as_deref()
+  }
+
+  // The folders that changed, once they've stopped changing for a moment.
+  pub fn settled(&mut self) -> Option<HashSet<PathBuf>> {
+    if let Ok(Err(error)) = self.started.try_recv() {
+      self.error = Some(error);
+    }
+    for dir in self.changes.try_iter() {
+      self.pending.insert(dir);
+      self.last_change = Some(Instant::now());
+    }
+
+    match self.last_change {
+      Some(last) if last.elapsed() >= SETTLE => {
+        self.last_change = None;
+        Some(std::mem::take(&mut self.pending))
+      }
+      _ => None,
+    }
+  }
+}
+
+fn watch(root: &Path, sender: Sender<PathBuf>) -> notify::Result<RecommendedWatcher> {
+  let watched = root.to_owned();
+  let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+    let event = match event {
+      Ok(event) => event,
+      Err(_) => return,
+    };
+    // reading tags opens files, and that's all these are
+    if matches!(
+      event.kind,
+      EventKind::Access(_) | EventKind::Modify(ModifyKind::Metadata(_))
+    ) {
+      return;
+    }
+    for path in event.paths {
+      if let Some(dir) = path.parent().filter(|dir| !hidden(&watched, dir)) {
+        let _ = sender.send(dir.to_owned());
+      }
+    }
+  })?;
+  watcher.watch(root, RecursiveMode::Recursive)?;
+  Ok(watcher)
+}
+
+// Hidden folders aren't listed, so changes in them don't matter. This also
+// keeps the data directory quiet when it's under the root.
+fn hidden(root: &Path, dir: &Path) -> bool {
+  dir
+    .strip_prefix(root)
+    .map(|relative| {
+      relative
+        .components()
+        .any(|c| c.as_os_str().to_string_lossy().starts_with('.'))
+    })
+    .unwrap_or(true)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn new_files_are_seen_once_settled() {
+    let root = std::env::temp_dir().join(format!("aquinas-watch-{}", std::process::id()));
+    let _ = fs::create_dir_all(root.join("album"));
+    let mut watch = Watch::new(&root);
+    // once it's watching
+    let started = watch.started.recv_timeout(Duration::from_secs(5));
+    assert_eq!(started, Ok(Ok(())));
+
+    fs::write(root.join("album/01.mp3"), b"").unwrap();
+    fs::create_dir_all(root.join(".hidden")).unwrap();
+    fs::write(root.join(".hidden/x.mp3"), b"").unwrap();
+    thread::sleep(Duration::from_millis(100));
+    assert_eq!(watch.settled(), None);
+
+    thread::sleep(SETTLE);
+    let changed = watch.settled().unwrap();
+    assert!(changed.contains(&root.join("album")));
+    assert!(!changed.contains(&root.join(".hidden")));
+
+    let _ = fs::remove_dir_all(&root);
+  }
+
+  #[test]
+  fn failures_are_kept_to_be_shown() {
+    let mut watch = Watch::new(Path::new("/nonexistent/aquinas"));
+    for _ in 0..500 {
+      watch.settled();
+      if watch.error().is_some() {
+        break;
+      }
+      thread::sleep(Duration::from_millis(10));
+    }
+    assert!(watch.error().is_some());
+  }
+}
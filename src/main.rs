@@ -1,12 +1,20 @@
 mod app;
+mod archive;
 mod backends;
 mod config;
 mod controls;
+mod history;
 mod library;
 mod meta;
 #[cfg(feature = "metadata")]
 mod metadata;
+#[cfg(feature = "metadata")]
+mod organize;
+mod playlist;
 mod prelude;
+mod ratings;
+#[cfg(feature = "scrobble")]
+mod scrobble;
 
 pub use backends::Backend as AudioBackend;
 pub use config::Config;
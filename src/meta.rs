@@ -1,12 +1,21 @@
+use crate::app::{repeat, shuffle, timer::Timers};
 use crate::*;
 use serde_derive::{Deserialize, Serialize};
 
 #[derive(Deserialize, Serialize, Default)]
 pub struct Meta {
   pub last_path: Option<PathBuf>,
+  #[serde(default)]
+  pub timers: Timers,
+  #[serde(default)]
+  pub shuffle: shuffle::Mode,
+  #[serde(default)]
+  pub repeat: repeat::Mode,
+  pub playlist_target: Option<String>,
 }
 
 impl Meta {
+  #[cfg(not(test))]
   pub fn config_dir() -> Result<PathBuf> {
     let config_dir = dirs::data_local_dir()
       .expect("Cannot save meta info")
@@ -15,6 +24,19 @@ impl Meta {
     Ok(config_dir)
   }
 
+  // Tests keep to a folder of their own, emptied as the run starts, so the
+  // user's history, index and settings are never touched.
+  #[cfg(test)]
+  pub fn config_dir() -> Result<PathBuf> {
+    static DIR: std::sync::OnceLock<PathBuf> = std::sync::OnceLock::new();
+    let dir = DIR.get_or_init(|| {
+      let dir = std::env::temp_dir().join("aquinas-test-data");
+      let _ = fs::remove_dir_all(&dir);
+      dir
+    });
+    Ok(dir.clone())
+  }
+
   pub fn save(&self) -> Result<()> {
     let config_dir = Self::config_dir()?;
     let _ = std::fs::create_dir_all(&config_dir);
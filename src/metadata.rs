@@ -1,52 +1,642 @@
 use crate::prelude::*;
-use audiotags::Tag;
+use id3::TagLike;
 use lewton::inside_ogg::OggStreamReader;
-use std::fs;
+use serde_derive::{Deserialize, Serialize};
+use std::{fs, io};
+use symphonia::core::{
+  codecs::CODEC_TYPE_NULL,
+  io::MediaSourceStream,
+  meta::{MetadataRevision, StandardTagKey, Tag, Value},
+  probe::Hint,
+};
 
-#[derive(PartialEq, Default, Clone, Debug, PartialOrd, Eq, Ord)]
+#[derive(PartialEq, Default, Clone, Debug, PartialOrd, Eq, Ord, Deserialize, Serialize)]
 pub struct Metadata {
   pub title: Option<String>,
   pub artist: Option<String>,
   pub album: Option<String>,
+  pub album_artist: Option<String>,
   pub track_number: Option<u16>,
+  pub track_total: Option<u16>,
+  pub disc_number: Option<u16>,
+  pub disc_total: Option<u16>,
+  pub date: Option<String>, // as tagged, a year or a full date
+  pub genre: Option<String>,
+  pub composer: Option<String>,
+  pub comment: Option<String>,
+  pub stars: Option<u8>, // 1-5, from a rating tag
+
+  // from the stream rather than tags
+  pub duration: Option<u64>, // seconds
+  pub codec: Option<String>,
+  pub bitrate: Option<u32>, // kbps, averaged over the file
+  pub sample_rate: Option<u32>,
+  pub channels: Option<u16>,
 }
 
-pub fn get_metadata(path: &Path) -> Option<Metadata> {
-  if let Some(ext) = extension(path) {
-    return match ext {
-      "ogg" => vorbis(path),
-      _ => other(path),
+impl Metadata {
+  pub fn year(&self) -> Option<i32> {
+    self.date.as_ref()?.get(..4)?.parse().ok()
+  }
+
+  fn set(&mut self, tag: &Tag) {
+    let value = match &tag.value {
+      Value::String(value) => value
+        .trim_matches(|c: char| c == '\0' || c.is_whitespace())
+        .to_owned(),
+      value => value.to_string(),
+    };
+    if value.is_empty() {
+      return;
+    }
+    let text = Some(value.clone());
+    // "3/12" numbers and totals
+    let (number, total) = numbered(&value);
+
+    match tag.std_key {
+      Some(StandardTagKey::TrackTitle) => self.title = text,
+      Some(StandardTagKey::Artist) => self.artist = text,
+      Some(StandardTagKey::Album) => self.album = text,
+      Some(StandardTagKey::AlbumArtist) => self.album_artist = text,
+      Some(StandardTagKey::Date) => self.date = text,
+      Some(StandardTagKey::Genre) => self.genre = text,
+      Some(StandardTagKey::Composer) => self.composer = text,
+      Some(StandardTagKey::Comment) => self.comment = text,
+      Some(StandardTagKey::TrackNumber) => {
+        self.track_number = number;
+        self.track_total = total.or(self.track_total);
+      }
+      Some(StandardTagKey::DiscNumber) => {
+        self.disc_number = number;
+        self.disc_total = total.or(self.disc_total);
+      }
+      Some(StandardTagKey::TrackTotal) => self.track_total = number,
+      Some(StandardTagKey::DiscTotal) => self.disc_total = number,
+      _ => {}
+    }
+  }
+
+  fn set_all(&mut self, revision: Option<&MetadataRevision>) {
+    for tag in revision.iter().flat_map(|revision| revision.tags()) {
+      self.set(tag);
     }
-    .ok();
   }
-  None
 }
 
-fn other(path: &Path) -> Result<Metadata> {
-  let md = Tag::new().read_from_path(path).map(|t| Metadata {
-    title: t.title().map(|t| t.to_owned()),
-    artist: t.artist().map(|t| t.to_owned()),
-    album: None, // handle later
-    track_number: t.track().0,
-  })?;
-  Ok(md)
+fn numbered(value: &str) -> (Option<u16>, Option<u16>) {
+  let mut parts = value.splitn(2, '/').map(|part| part.trim().parse().ok());
+  (parts.next().flatten(), parts.next().flatten())
+}
+
+pub fn get_metadata(path: &Path) -> Option<Metadata> {
+  let mut metadata = match extension(path)?.to_lowercase().as_str() {
+    "opus" => opus(path),
+    _ => read(path),
+  }
+  .ok()?;
+  metadata.stars = read_stars(path).filter(|&stars| stars > 0);
+  Some(metadata)
 }
 
-fn vorbis(path: &Path) -> Result<Metadata> {
-  let file = fs::File::open(path)?;
-  let source = OggStreamReader::new(&file)?;
+// Tags and stream details through Symphonia, for any format it can open.
+fn read(path: &Path) -> Result<Metadata> {
+  let file = File::open(path)?;
+  let size = file.metadata()?.len();
+  let mss = MediaSourceStream::new(Box::new(file), Default::default());
+  let mut hint = Hint::new();
+  if let Some(extension) = extension(path) {
+    hint.with_extension(&extension.to_lowercase());
+  }
+  let mut probed =
+    symphonia::default::get_probe().format(&hint, mss, &Default::default(), &Default::default())?;
 
+  // tags ahead of the container, like ID3v2, then the container's own
   let mut metadata = Metadata::default();
+  if let Some(outside) = probed.metadata.get() {
+    metadata.set_all(outside.current());
+  }
+  metadata.set_all(probed.format.metadata().current());
 
-  for (k, v) in source.comment_hdr.comment_list {
-    match k.to_lowercase().as_str() {
-      "title" => metadata.title = Some(v),
-      "artist" => metadata.artist = Some(v),
-      "album" => metadata.album = Some(v),
-      "tracknumber" => metadata.track_number = v.parse().ok(),
-      _ => {}
+  let track = probed
+    .format
+    .tracks()
+    .iter()
+    .find(|t| t.codec_params.codec != CODEC_TYPE_NULL);
+  if let Some(track) = track {
+    let params = &track.codec_params;
+    metadata.codec = symphonia::default::get_codecs()
+      .get_codec(params.codec)
+      .map(|codec| codec.short_name.to_owned());
+    metadata.sample_rate = params.sample_rate;
+    metadata.channels = params.channels.map(|channels| channels.count() as u16);
+    if let (Some(time_base), Some(frames)) = (params.time_base, params.n_frames) {
+      let time = time_base.calc_time(frames);
+      metadata.duration = Some(time.seconds);
+      let seconds = time.seconds as f64 + time.frac;
+      if seconds > 0. {
+        metadata.bitrate = Some((size as f64 * 8. / seconds / 1000.).round() as u32);
+      }
     }
   }
+  Ok(metadata)
+}
+
+// Symphonia can't decode Opus, and may not know much about the stream, so
+// its headers are read here. Whatever Symphonia did find is kept.
+fn opus(path: &Path) -> Result<Metadata> {
+  let headers = opus_headers::parse_from_path(path)?;
+  let mut metadata = read(path).unwrap_or_default();
+  metadata.codec = Some("opus".to_owned());
+  // always decoded at 48kHz, whatever it was made from
+  metadata.sample_rate = Some(48000);
+  metadata.channels = Some(headers.id.channel_count as u16);
 
+  for (key, value) in headers.comments.user_comments {
+    if let Some(std_key) = vorbis_key(&key) {
+      metadata.set(&Tag::new(Some(std_key), &key, Value::from(value)));
+    }
+  }
   Ok(metadata)
 }
+
+// The Vorbis comments `Metadata` is read from, under their usual names.
+fn vorbis_key(key: &str) -> Option<StandardTagKey> {
+  Some(match key.to_uppercase().as_str() {
+    "TITLE" => StandardTagKey::TrackTitle,
+    "ARTIST" => StandardTagKey::Artist,
+    "ALBUM" => StandardTagKey::Album,
+    "ALBUMARTIST" | "ALBUM ARTIST" => StandardTagKey::AlbumArtist,
+    "TRACKNUMBER" => StandardTagKey::TrackNumber,
+    "TRACKTOTAL" | "TOTALTRACKS" => StandardTagKey::TrackTotal,
+    "DISCNUMBER" => StandardTagKey::DiscNumber,
+    "DISCTOTAL" | "TOTALDISCS" => StandardTagKey::DiscTotal,
+    "DATE" | "YEAR" => StandardTagKey::Date,
+    "GENRE" => StandardTagKey::Genre,
+    "COMPOSER" => StandardTagKey::Composer,
+    "COMMENT" | "DESCRIPTION" => StandardTagKey::Comment,
+    _ => return None,
+  })
+}
+
+// The text tags `write_metadata` writes, as (ID3 frame, Vorbis comment)
+// and the value, with None removing it.
+fn text_tags(metadata: &Metadata) -> [(&'static str, &'static str, Option<String>); 8] {
+  [
+    ("TIT2", "TITLE", metadata.title.clone()),
+    ("TPE1", "ARTIST", metadata.artist.clone()),
+    ("TALB", "ALBUM", metadata.album.clone()),
+    ("TPE2", "ALBUMARTIST", metadata.album_artist.clone()),
+    ("TDRC", "DATE", metadata.date.clone()),
+    ("TCON", "GENRE", metadata.genre.clone()),
+    ("TCOM", "COMPOSER", metadata.composer.clone()),
+    ("COMM", "COMMENT", metadata.comment.clone()),
+  ]
+}
+
+// The Vorbis comments `write_metadata` writes, numbers included.
+fn vorbis_comments(metadata: &Metadata) -> Vec<(&'static str, Option<String>)> {
+  let number = |n: Option<u16>| n.map(|n| n.to_string());
+  let numbers = [
+    ("TRACKNUMBER", number(metadata.track_number)),
+    ("TRACKTOTAL", number(metadata.track_total)),
+    ("DISCNUMBER", number(metadata.disc_number)),
+    ("DISCTOTAL", number(metadata.disc_total)),
+  ];
+  let texts = text_tags(metadata).map(|(_, key, value)| (key, value));
+  texts.into_iter().chain(numbers).collect()
+}
+
+// Writes the common tags: ID3 for mp3, Vorbis comments for flac, ogg and
+// opus, and MP4 atoms for m4a / mp4. Stream details are left alone, being
+// read off the stream anyway.
+pub fn write_metadata(path: &Path, metadata: &Metadata) -> Result<()> {
+  let number = |n: Option<u16>| n.map(|n| n.to_string());
+  match extension(path).map(|e| e.to_lowercase()).as_deref() {
+    Some("mp3") => {
+      let mut tag = id3::Tag::read_from_path(path).unwrap_or_default();
+      // "3/12", or just "3"
+      let numbered = |n: Option<u16>, total: Option<u16>| match total {
+        Some(total) => number(n).map(|n| format!("{}/{}", n, total)),
+        None => number(n),
+      };
+      let numbers = [
+        (
+          "TRCK",
+          numbered(metadata.track_number, metadata.track_total),
+        ),
+        ("TPOS", numbered(metadata.disc_number, metadata.disc_total)),
+      ];
+      let texts = text_tags(metadata).map(|(frame, _, value)| (frame, value));
+      for (frame, value) in texts.into_iter().chain(numbers) {
+        match (frame, value) {
+          ("COMM", value) => {
+            tag.remove_comment(Some(""), None);
+            if let Some(text) = value {
+              tag.add_frame(id3::frame::Comment {
+                lang: "eng".to_owned(),
+                description: String::new(),
+                text,
+              });
+            }
+          }
+          (frame, Some(value)) => tag.set_text(frame, value),
+          (frame, None) => drop(tag.remove(frame)),
+        }
+      }
+      tag.write_to_path(path, id3::Version::Id3v24)?;
+    }
+    Some("flac") => {
+      let mut tag = metaflac::Tag::read_from_path(path)?;
+      for (key, value) in vorbis_comments(metadata) {
+        tag.remove_vorbis(key);
+        if let Some(value) = value {
+          tag.set_vorbis(key, vec![value]);
+        }
+      }
+      tag.save()?;
+    }
+    Some("ogg" | "oga" | "opus") => write_ogg(path, metadata)?,
+    Some("m4a" | "mp4" | "m4b") => {
+      let mut tag = mp4ameta::Tag::read_from_path(path)?;
+      let m = metadata.clone();
+      match m.title {
+        Some(v) => tag.set_title(v),
+        None => tag.remove_title(),
+      }
+      match m.artist {
+        Some(v) => tag.set_artist(v),
+        None => tag.remove_artists(),
+      }
+      match m.album {
+        Some(v) => tag.set_album(v),
+        None => tag.remove_album(),
+      }
+      match m.album_artist {
+        Some(v) => tag.set_album_artist(v),
+        None => tag.remove_album_artists(),
+      }
+      match m.date {
+        Some(v) => tag.set_year(v),
+        None => tag.remove_year(),
+      }
+      match m.genre {
+        Some(v) => tag.set_genre(v),
+        None => tag.remove_genres(),
+      }
+      match m.composer {
+        Some(v) => tag.set_composer(v),
+        None => tag.remove_composers(),
+      }
+      match m.comment {
+        Some(v) => tag.set_comment(v),
+        None => tag.remove_comments(),
+      }
+      match (m.track_number, m.track_total) {
+        (None, None) => tag.remove_track(),
+        (n, total) => tag.set_track(n.unwrap_or(0), total.unwrap_or(0)),
+      }
+      match (m.disc_number, m.disc_total) {
+        (None, None) => tag.remove_disc(),
+        (n, total) => tag.set_disc(n.unwrap_or(0), total.unwrap_or(0)),
+      }
+      tag.write_to_path(path)?;
+    }
+    _ => bail!("can't write tags to {}", path.display()),
+  }
+  Ok(())
+}
+
+// Ogg files are copied out packet by packet, with a new comment header in
+// place of each stream's second packet, then put over the original.
+fn write_ogg(path: &Path, metadata: &Metadata) -> Result<()> {
+  use ogg::{PacketReader, PacketWriteEndInfo, PacketWriter};
+
+  let mut reader = PacketReader::new(io::BufReader::new(fs::File::open(path)?));
+  let mut writer = PacketWriter::new(vec![]);
+  let mut packets: HashMap<u32, usize> = HashMap::new();
+  while let Some(packet) = reader.read_packet()? {
+    let serial = packet.stream_serial();
+    let end = match (packet.last_in_stream(), packet.last_in_page()) {
+      (true, _) => PacketWriteEndInfo::EndStream,
+      (false, true) => PacketWriteEndInfo::EndPage,
+      (false, false) => PacketWriteEndInfo::NormalPacket,
+    };
+    let absgp = packet.absgp_page();
+    let index = packets.entry(serial).or_default();
+    let data = match *index {
+      1 => comment_header(&packet.data, metadata)?,
+      _ => packet.data,
+    };
+    *index += 1;
+    writer.write_packet(data.into_boxed_slice(), serial, end, absgp)?;
+  }
+
+  // written beside it first, so a failure part way leaves the file be
+  let written = path.with_extension("aquinas-tmp");
+  fs::write(&written, writer.into_inner())?;
+  fs::rename(&written, path)?;
+  Ok(())
+}
+
+// A Vorbis ("\x03vorbis") or Opus ("OpusTags") comment header with ours in
+// place of the comments `Metadata` is read from. Others, like ratings, and
+// whatever follows the comments are kept.
+fn comment_header(packet: &[u8], metadata: &Metadata) -> Result<Vec<u8>> {
+  let magic = match packet {
+    p if p.starts_with(b"\x03vorbis") => 7,
+    p if p.starts_with(b"OpusTags") => 8,
+    _ => bail!("no comment header"),
+  };
+  let mut at = magic;
+  let vendor = field(packet, &mut at)?;
+  let count = u32_at(packet, &mut at)?;
+  let mut comments = vec![];
+  for _ in 0..count {
+    let comment = field(packet, &mut at)?;
+    let key = comment.split(|b| *b == b'=').next().unwrap_or_default();
+    if vorbis_key(&String::from_utf8_lossy(key)).is_none() {
+      comments.push(comment.to_vec());
+    }
+  }
+  for (key, value) in vorbis_comments(metadata) {
+    if let Some(value) = value {
+      comments.push(format!("{}={}", key, value).into_bytes());
+    }
+  }
+
+  let mut header = packet[..magic].to_vec();
+  header.extend((vendor.len() as u32).to_le_bytes());
+  header.extend(vendor);
+  header.extend((comments.len() as u32).to_le_bytes());
+  for comment in comments {
+    header.extend((comment.len() as u32).to_le_bytes());
+    header.extend(comment);
+  }
+  header.extend(&packet[at..]);
+  Ok(header)
+}
+
+fn u32_at(packet: &[u8], at: &mut usize) -> Result<u32> {
+  match packet.get(*at..*at + 4) {
+    Some(b) => {
+      *at += 4;
+      Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+    None => bail!("comment header cut short"),
+  }
+}
+
+// A length and that many bytes.
+fn field<'a>(packet: &'a [u8], at: &mut usize) -> Result<&'a [u8]> {
+  let len = u32_at(packet, at)? as usize;
+  match packet.get(*at..*at + len) {
+    Some(bytes) => {
+      *at += len;
+      Ok(bytes)
+    }
+    None => bail!("comment header cut short"),
+  }
+}
+
+// Star ratings in tags: POPM's 1-255 in ID3, FMPS_RATING's 0.0-1.0 in
+// Vorbis comments. Ogg files can only be read.
+const POPM_USER: &str = "aquinas";
+const POPM_STARS: [u8; 6] = [0, 1, 64, 128, 196, 255];
+
+pub fn read_stars(path: &Path) -> Option<u8> {
+  let fmps = |value: &str| {
+    value
+      .trim()
+      .parse::<f32>()
+      .ok()
+      .map(|v| (v * 5.).round() as u8)
+  };
+  match extension(path)?.to_lowercase().as_str() {
+    "mp3" => {
+      let tag = id3::Tag::read_from_path(path).ok()?;
+      // ours, or another player's
+      let popms: Vec<_> = tag
+        .frames()
+        .filter_map(|f| f.content().popularimeter())
+        .collect();
+      let popm = popms
+        .iter()
+        .find(|popm| popm.user == POPM_USER)
+        .or(popms.first())?;
+      POPM_STARS
+        .iter()
+        .rposition(|&min| popm.rating >= min)
+        .map(|s| s as u8)
+    }
+    "flac" => {
+      let tag = metaflac::Tag::read_from_path(path).ok()?;
+      let value = tag.get_vorbis("FMPS_RATING")?.next()?;
+      fmps(value)
+    }
+    "ogg" => {
+      let source = OggStreamReader::new(fs::File::open(path).ok()?).ok()?;
+      let comments = source.comment_hdr.comment_list;
+      let (_, value) = comments
+        .iter()
+        .find(|(k, _)| k.eq_ignore_ascii_case("FMPS_RATING"))?;
+      fmps(value)
+    }
+    _ => None,
+  }
+}
+
+pub fn write_stars(path: &Path, stars: u8) -> Result<()> {
+  match extension(path).map(|e| e.to_lowercase()).as_deref() {
+    Some("mp3") => {
+      let mut tag = id3::Tag::read_from_path(path).unwrap_or_default();
+      // other players' ratings are left as they were
+      for frame in tag.remove("POPM") {
+        let theirs = frame
+          .content()
+          .popularimeter()
+          .map(|popm| popm.user != POPM_USER);
+        if theirs == Some(true) {
+          tag.add_frame(frame);
+        }
+      }
+      if stars > 0 {
+        tag.add_frame(id3::frame::Popularimeter {
+          user: POPM_USER.to_owned(),
+          rating: POPM_STARS[stars.min(5) as usize],
+          counter: 0,
+        });
+      }
+      tag.write_to_path(path, id3::Version::Id3v24)?;
+    }
+    Some("flac") => {
+      let mut tag = metaflac::Tag::read_from_path(path)?;
+      match stars {
+        0 => tag.remove_vorbis("FMPS_RATING"),
+        stars => tag.set_vorbis("FMPS_RATING", vec![format!("{}", stars as f32 / 5.)]),
+      }
+      tag.save()?;
+    }
+    _ => bail!("can't write ratings to {}", path.display()),
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn fixture(name: &str) -> Metadata {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+      .join("tests/fixtures")
+      .join(name);
+    get_metadata(&path).unwrap()
+  }
+
+  // what every fixture's tagged with, give or take what the format holds
+  fn assert_common(metadata: &Metadata) {
+    assert_eq!(metadata.title.as_deref(), Some("Fixture Song"));
+    assert_eq!(metadata.artist.as_deref(), Some("Fixture Artist"));
+    assert_eq!(metadata.album.as_deref(), Some("Fixture Album"));
+    assert_eq!(metadata.track_number, Some(3));
+    assert_eq!(metadata.track_total, Some(12));
+    assert_eq!(metadata.year(), Some(2021));
+    assert_eq!(metadata.genre.as_deref(), Some("Ambient"));
+    assert_eq!(metadata.comment.as_deref(), Some("Quiet"));
+  }
+
+  #[test]
+  fn numbers_and_totals_split() {
+    assert_eq!(numbered("3/12"), (Some(3), Some(12)));
+    assert_eq!(numbered(" 3 "), (Some(3), None));
+    assert_eq!(numbered("x/2"), (None, Some(2)));
+  }
+
+  #[test]
+  fn mp3_reads_id3v2() {
+    let metadata = fixture("tagged.mp3");
+    assert_common(&metadata);
+    assert_eq!(metadata.album_artist.as_deref(), Some("Various"));
+    assert_eq!(
+      (metadata.disc_number, metadata.disc_total),
+      (Some(1), Some(2))
+    );
+    assert_eq!(metadata.date.as_deref(), Some("2021-05-04"));
+    assert_eq!(metadata.composer.as_deref(), Some("Someone"));
+    assert_eq!(metadata.codec.as_deref(), Some("mp3"));
+    assert_eq!(metadata.sample_rate, Some(32000));
+    assert_eq!(metadata.channels, Some(1));
+    assert_eq!(metadata.duration, Some(1));
+    assert_eq!(metadata.bitrate, Some(34)); // 32kbps frames, and the tag
+  }
+
+  #[test]
+  fn flac_reads_vorbis_comments() {
+    let metadata = fixture("tagged.flac");
+    assert_common(&metadata);
+    assert_eq!(metadata.album_artist.as_deref(), Some("Various"));
+    assert_eq!(
+      (metadata.disc_number, metadata.disc_total),
+      (Some(1), Some(2))
+    );
+    assert_eq!(metadata.composer.as_deref(), Some("Someone"));
+    assert_eq!(metadata.codec.as_deref(), Some("flac"));
+    assert_eq!(metadata.sample_rate, Some(44100));
+    assert_eq!(metadata.channels, Some(2));
+    assert_eq!(metadata.duration, Some(2));
+  }
+
+  #[test]
+  fn wav_reads_riff_info() {
+    let metadata = fixture("tagged.wav");
+    assert_common(&metadata);
+    assert_eq!(metadata.codec.as_deref(), Some("pcm_u8"));
+    assert_eq!(metadata.sample_rate, Some(4000));
+    assert_eq!(metadata.channels, Some(2));
+    assert_eq!(metadata.duration, Some(2));
+    assert_eq!(metadata.bitrate, Some(65));
+  }
+
+  #[test]
+  fn stars_go_in_our_own_rating_tags() {
+    let dir = std::env::temp_dir().join(format!("aquinas-stars-{}", std::process::id()));
+    let _ = fs::create_dir_all(&dir);
+    let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+    let (mp3, flac) = (dir.join("tagged.mp3"), dir.join("tagged.flac"));
+    fs::copy(fixtures.join("tagged.mp3"), &mp3).unwrap();
+    fs::copy(fixtures.join("tagged.flac"), &flac).unwrap();
+
+    let mut tag = id3::Tag::read_from_path(&mp3).unwrap();
+    let theirs = id3::frame::Popularimeter {
+      user: "someone@else".to_owned(),
+      rating: 10,
+      counter: 7,
+    };
+    tag.add_frame(theirs.clone());
+    tag.write_to_path(&mp3, id3::Version::Id3v24).unwrap();
+
+    for stars in 1..=5 {
+      write_stars(&mp3, stars).unwrap();
+      assert_eq!(read_stars(&mp3), Some(stars));
+      write_stars(&flac, stars).unwrap();
+      assert_eq!(read_stars(&flac), Some(stars));
+    }
+    let flac_tag = metaflac::Tag::read_from_path(&flac).unwrap();
+    let fmps: Vec<_> = flac_tag.get_vorbis("FMPS_RATING").unwrap().collect();
+    assert_eq!(fmps, ["1"]);
+
+    write_stars(&mp3, 0).unwrap();
+    let tag = id3::Tag::read_from_path(&mp3).unwrap();
+    let popms: Vec<_> = tag
+      .frames()
+      .filter_map(|f| f.content().popularimeter())
+      .collect();
+    assert_eq!(popms, [&theirs]);
+    // with ours gone, theirs is what's read
+    assert_eq!(read_stars(&mp3), Some(1));
+    write_stars(&flac, 0).unwrap();
+    assert_eq!(read_stars(&flac), None);
+
+    let _ = fs::remove_dir_all(&dir);
+  }
+
+  #[test]
+  fn written_tags_read_back() {
+    let dir = std::env::temp_dir().join(format!("aquinas-tags-{}", std::process::id()));
+    let _ = fs::create_dir_all(&dir);
+    for name in ["tagged.mp3", "tagged.flac", "tagged.opus"] {
+      let path = dir.join(name);
+      let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+      fs::copy(fixtures.join(name), &path).unwrap();
+
+      let mut metadata = get_metadata(&path).unwrap();
+      metadata.title = Some("Renamed".to_owned());
+      metadata.comment = None;
+      metadata.track_number = Some(4);
+      write_metadata(&path, &metadata).unwrap();
+
+      let written = get_metadata(&path).unwrap();
+      assert_eq!(written.title.as_deref(), Some("Renamed"));
+      assert_eq!(written.comment, None);
+      assert_eq!(
+        (written.track_number, written.track_total),
+        (Some(4), Some(12))
+      );
+      assert_eq!(written.album_artist.as_deref(), Some("Various"));
+    }
+    let _ = fs::remove_dir_all(&dir);
+  }
+
+  #[test]
+  fn opus_reads_its_headers() {
+    let metadata = fixture("tagged.opus");
+    assert_common(&metadata);
+    assert_eq!(metadata.album_artist.as_deref(), Some("Various"));
+    assert_eq!(
+      (metadata.disc_number, metadata.disc_total),
+      (Some(1), Some(2))
+    );
+    assert_eq!(metadata.codec.as_deref(), Some("opus"));
+    assert_eq!(metadata.sample_rate, Some(48000));
+    assert_eq!(metadata.channels, Some(2));
+  }
+}
@@ -0,0 +1,375 @@
+use crate::library::browse::{component, UNKNOWN};
+use crate::*;
+use serde_derive::{Deserialize, Serialize};
+use std::sync::OnceLock;
+
+// How many batches of moves can be undone.
+const KEPT: usize = 20;
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct Move {
+  pub from: PathBuf,
+  pub to: PathBuf,
+}
+
+// Batches of moves made, oldest first, in `organize.toml`, so the latest
+// can be moved back.
+#[derive(Deserialize, Serialize, Default)]
+struct Journal {
+  #[serde(default, rename = "batch")]
+  batches: Vec<Batch>,
+}
+
+#[derive(Deserialize, Serialize)]
+struct Batch {
+  root: PathBuf, // emptied folders are removed up to here
+  #[serde(rename = "move")]
+  moves: Vec<Move>,
+}
+
+static JOURNAL: OnceLock<Mutex<Journal>> = OnceLock::new();
+
+fn journal() -> &'static Mutex<Journal> {
+  JOURNAL.get_or_init(|| Mutex::new(Journal::load().unwrap_or_default()))
+}
+
+impl Journal {
+  fn path() -> Result<PathBuf> {
+    Ok(Meta::config_dir()?.join("organize.toml"))
+  }
+
+  fn load() -> Result<Self> {
+    Ok(toml::from_str(&fs::read_to_string(Self::path()?)?)?)
+  }
+
+  fn save(&self) -> Result<()> {
+    let path = Self::path()?;
+    if let Some(dir) = path.parent() {
+      let _ = fs::create_dir_all(dir);
+    }
+    fs::write(path, toml::to_string(self)?)?;
+    Ok(())
+  }
+}
+
+// Where a file goes by its tags: the layout's parts between `/` are folders
+// under `root`, with each `{field}` filled in, and `{field:02}` padded with
+// zeros.
+pub fn destination(root: &Path, layout: &str, path: &Path, tags: &Metadata) -> Result<PathBuf> {
+  let mut to = root.to_owned();
+  for part in layout.split('/') {
+    let name = fill(part, path, tags)?;
+    if !name.trim().is_empty() {
+      to.push(component(&name));
+    }
+  }
+  Ok(to)
+}
+
+fn fill(part: &str, path: &Path, tags: &Metadata) -> Result<String> {
+  let mut text = String::new();
+  let mut rest = part;
+  while let Some(start) = rest.find('{') {
+    text.push_str(&rest[..start]);
+    let end = match rest[start..].find('}') {
+      Some(end) => start + end,
+      None => bail!("unclosed {{ in organize_layout"),
+    };
+    let (name, width) = match rest[start + 1..end].split_once(':') {
+      Some((name, width)) => (name, width.parse().unwrap_or(0)),
+      None => (&rest[start + 1..end], 0),
+    };
+    let value = field(name, path, tags)?;
+    match value.is_empty() {
+      true => {}
+      false => text.push_str(&format!("{:0>width$}", value, width = width)),
+    }
+    rest = &rest[end + 1..];
+  }
+  text.push_str(rest);
+  Ok(text)
+}
+
+fn field(name: &str, path: &Path, tags: &Metadata) -> Result<String> {
+  let text = |value: &Option<String>| value.clone().unwrap_or_else(|| UNKNOWN.to_owned());
+  let number = |n: Option<u16>| n.map(|n| n.to_string()).unwrap_or_default();
+  Ok(match name {
+    "title" => match &tags.title {
+      Some(title) => title.clone(),
+      None => path
+        .file_stem()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .into(),
+    },
+    "artist" => text(&tags.artist),
+    "albumartist" => text(&tags.album_artist.clone().or(tags.artist.clone())),
+    "album" => text(&tags.album),
+    "year" => text(&tags.year().map(|year| year.to_string())),
+    "genre" => text(&tags.genre),
+    "composer" => text(&tags.composer),
+    "track" => number(tags.track_number),
+    // left out for albums of one disc
+    "disc" => match (tags.disc_number, tags.disc_total) {
+      (Some(disc), Some(total)) if total > 1 => disc.to_string(),
+      (Some(disc), _) if disc > 1 => disc.to_string(),
+      _ => String::new(),
+    },
+    "ext" => extension(path).unwrap_or_default().to_owned(),
+    name => bail!("unknown field {{{}}} in organize_layout", name),
+  })
+}
+
+// The moves that put files where the layout says, leaving out those
+// already there. Each comes with why it can't be made, if it can't: files
+// aren't moved over others.
+pub fn plan(
+  root: &Path,
+  layout: &str,
+  files: &[(PathBuf, Metadata)],
+) -> Result<Vec<(Move, Option<&'static str>)>> {
+  let mut moves = vec![];
+  let mut targets: HashMap<PathBuf, usize> = HashMap::new();
+  for (path, tags) in files {
+    let to = destination(root, layout, path, tags)?;
+    if to != *path {
+      *targets.entry(to.clone()).or_default() += 1;
+      moves.push(Move {
+        from: path.clone(),
+        to,
+      });
+    }
+  }
+  Ok(
+    moves
+      .into_iter()
+      .map(|step| {
+        let clash = if targets[&step.to] > 1 {
+          Some("more than one file goes here")
+        } else if step.to.exists() {
+          Some("already exists")
+        } else {
+          None
+        };
+        (step, clash)
+      })
+      .collect(),
+  )
+}
+
+// Makes the moves, journaling them and carrying their ratings along. Gives
+// back the moves made, and why the others weren't.
+pub fn apply(root: &Path, moves: &[Move]) -> (Vec<Move>, Vec<String>) {
+  let (done, failed) = shift(root, moves);
+  if !done.is_empty() {
+    let mut journal = journal().lock();
+    journal.batches.push(Batch {
+      root: root.to_owned(),
+      moves: done.clone(),
+    });
+    let excess = journal.batches.len().saturating_sub(KEPT);
+    journal.batches.drain(..excess);
+    let _ = journal.save();
+  }
+  (done, failed)
+}
+
+// Moves the latest batch back. What can't be stays journaled, to be tried
+// again.
+pub fn undo() -> (Vec<Move>, Vec<String>) {
+  let mut journal = journal().lock();
+  let batch = match journal.batches.pop() {
+    Some(batch) => batch,
+    None => return (vec![], vec![]),
+  };
+  let back: Vec<Move> = batch
+    .moves
+    .iter()
+    .rev()
+    .map(|step| Move {
+      from: step.to.clone(),
+      to: step.from.clone(),
+    })
+    .collect();
+  let (done, failed) = shift(&batch.root, &back);
+
+  let left: Vec<Move> = batch
+    .moves
+    .into_iter()
+    .filter(|step| !done.iter().any(|back| back.from == step.to))
+    .collect();
+  if !left.is_empty() {
+    journal.batches.push(Batch {
+      root: batch.root,
+      moves: left,
+    });
+  }
+  let _ = journal.save();
+  (done, failed)
+}
+
+pub fn can_undo() -> bool {
+  !journal().lock().batches.is_empty()
+}
+
+// Makes the moves in order, making folders as needed and removing those
+// left empty, up to `root`.
+fn shift(root: &Path, moves: &[Move]) -> (Vec<Move>, Vec<String>) {
+  let mut done = vec![];
+  let mut failed = vec![];
+  for step in moves {
+    match shift_one(step) {
+      Ok(()) => {
+        ratings::moved(&step.from, &step.to);
+        if let Some(dir) = step.from.parent() {
+          prune(root, dir);
+        }
+        done.push(step.clone());
+      }
+      Err(error) => failed.push(format!("{}: {}", step.from.display(), error)),
+    }
+  }
+  (done, failed)
+}
+
+fn shift_one(step: &Move) -> Result<()> {
+  if step.to.exists() {
+    bail!("{} already exists", step.to.display());
+  }
+  if let Some(dir) = step.to.parent() {
+    fs::create_dir_all(dir)?;
+  }
+  fs::rename(&step.from, &step.to)?;
+  Ok(())
+}
+
+fn prune(root: &Path, dir: &Path) {
+  let mut dir = Some(dir);
+  while let Some(empty) = dir.filter(|dir| dir.starts_with(root) && *dir != root) {
+    if fs::remove_dir(empty).is_err() {
+      break;
+    }
+    dir = empty.parent();
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const LAYOUT: &str = "{albumartist}/{year} - {album}/{disc}{track:02} {title}.{ext}";
+
+  fn tags(title: &str, track: u16, disc: Option<(u16, u16)>) -> Metadata {
+    Metadata {
+      title: Some(title.to_owned()),
+      artist: Some("Artist".to_owned()),
+      album: Some("Album".to_owned()),
+      date: Some("1999-03-01".to_owned()),
+      track_number: Some(track),
+      disc_number: disc.map(|(disc, _)| disc),
+      disc_total: disc.map(|(_, total)| total),
+      ..Default::default()
+    }
+  }
+
+  #[test]
+  fn layouts_fill_in_tags() {
+    let root = Path::new("/m");
+    let to =
+      |path: &str, tags: &Metadata| destination(root, LAYOUT, Path::new(path), tags).unwrap();
+
+    let song = tags("A/B", 3, None);
+    assert_eq!(
+      to("/m/x/a.flac", &song),
+      Path::new("/m/Artist/1999 - Album/03 A∕B.flac")
+    );
+    let song = tags("Two", 4, Some((2, 2)));
+    assert_eq!(
+      to("/m/x/b.mp3", &song),
+      Path::new("/m/Artist/1999 - Album/204 Two.mp3")
+    );
+    let untagged = Metadata::default();
+    assert_eq!(
+      to("/m/x/c.mp3", &untagged),
+      Path::new("/m/Unknown/Unknown - Unknown/c.mp3")
+    );
+    assert!(destination(root, "{nope}", Path::new("/m/a.mp3"), &untagged).is_err());
+  }
+
+  #[test]
+  fn journal_reads_back() {
+    let step = Move {
+      from: PathBuf::from("/m/in/a.mp3"),
+      to: PathBuf::from("/m/Artist/1999 - Album/01 One.mp3"),
+    };
+    let journal = Journal {
+      batches: vec![Batch {
+        root: PathBuf::from("/m"),
+        moves: vec![step.clone()],
+      }],
+    };
+    let journal: Journal = toml::from_str(&toml::to_string(&journal).unwrap()).unwrap();
+    assert_eq!(journal.batches[0].moves, [step]);
+  }
+
+  #[test]
+  fn moves_clash_and_go_back() {
+    let root = std::env::temp_dir().join(format!("aquinas-organize-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&root);
+    fs::create_dir_all(root.join("in")).unwrap();
+    fs::create_dir_all(root.join("Artist/1999 - Album")).unwrap();
+    for name in [
+      "in/a.mp3",
+      "in/b.mp3",
+      "in/c.mp3",
+      "Artist/1999 - Album/09 Nine.mp3",
+    ] {
+      fs::write(root.join(name), name).unwrap();
+    }
+    let files = [
+      (root.join("in/a.mp3"), tags("One", 1, None)),
+      (root.join("in/b.mp3"), tags("One", 1, None)),
+      (root.join("in/c.mp3"), tags("Nine", 9, None)),
+    ];
+    let planned = plan(&root, LAYOUT, &files).unwrap();
+    let clashes: Vec<_> = planned.iter().map(|(_, clash)| *clash).collect();
+    assert_eq!(
+      clashes,
+      [
+        Some("more than one file goes here"),
+        Some("more than one file goes here"),
+        Some("already exists"),
+      ]
+    );
+
+    let files = [(root.join("in/a.mp3"), tags("One", 1, None))];
+    let moves: Vec<Move> = plan(&root, LAYOUT, &files)
+      .unwrap()
+      .into_iter()
+      .map(|(step, _)| step)
+      .collect();
+    fs::remove_file(root.join("in/b.mp3")).unwrap();
+    fs::remove_file(root.join("in/c.mp3")).unwrap();
+    let (done, failed) = shift(&root, &moves);
+    assert!(failed.is_empty());
+    assert_eq!(
+      fs::read_to_string(root.join("Artist/1999 - Album/01 One.mp3")).unwrap(),
+      "in/a.mp3"
+    );
+    // the emptied folder goes with it
+    assert!(!root.join("in").exists());
+
+    let back: Vec<Move> = done
+      .iter()
+      .map(|step| Move {
+        from: step.to.clone(),
+        to: step.from.clone(),
+      })
+      .collect();
+    let (_, failed) = shift(&root, &back);
+    assert!(failed.is_empty());
+    assert!(root.join("in/a.mp3").exists());
+    assert!(root.join("Artist/1999 - Album/09 Nine.mp3").exists());
+    let _ = fs::remove_dir_all(&root);
+  }
+}
@@ -0,0 +1,222 @@
+mod m3u;
+pub mod managed;
+mod pls;
+pub mod smart;
+mod xspf;
+
+use crate::*;
+use std::path::Component;
+
+// Playlists are shown as folders holding their entries, in their own order.
+const PLAYLISTS: &[&str] = &["m3u", "m3u8", "pls", "xspf"];
+
+#[derive(Default, Debug, PartialEq)]
+pub struct Entry {
+  pub path: PathBuf,
+  pub title: Option<String>,
+  pub artist: Option<String>,
+  pub album: Option<String>,
+}
+
+pub fn is_playlist(path: &Path) -> bool {
+  format(path).is_some() && path.is_file()
+}
+
+fn format(path: &Path) -> Option<&'static str> {
+  let ext = extension(path)?;
+  PLAYLISTS
+    .iter()
+    .find(|p| p.eq_ignore_ascii_case(ext))
+    .copied()
+}
+
+// All entries, including ones whose files are missing so they can be shown
+// as such. Nested playlists are left out so one can't list itself forever.
+pub fn read(path: &Path) -> Result<Vec<Entry>> {
+  let text = text(&fs::read(path)?);
+  let dir = path.parent().unwrap_or(Path::new("/"));
+
+  let entries = match format(path) {
+    Some("pls") => pls::parse(&text, dir),
+    Some("xspf") => xspf::parse(&text, dir)?,
+    _ => m3u::parse(&text, dir),
+  };
+  Ok(
+    entries
+      .into_iter()
+      .filter(|e| !is_playlist(&e.path))
+      .collect(),
+  )
+}
+
+pub fn is_missing(path: &Path) -> bool {
+  !path.is_file() && archive::split(path).is_none()
+}
+
+// Writes in the format the extension names, m3u8 when it's something else.
+// Paths are relative to the playlist where they share a folder with it, so
+// playlists keep working when moved along with the music, or checked out
+// somewhere else.
+pub fn write(path: &Path, nodes: &[Arc<Node>]) -> Result<()> {
+  let dir = path.parent().unwrap_or(Path::new("/"));
+  let entries: Vec<Entry> = nodes.iter().map(|node| entry(node)).collect();
+
+  let text = match format(path) {
+    Some("pls") => pls::write(&entries, dir),
+    Some("xspf") => xspf::write(&entries, dir),
+    _ => m3u::write(&entries, dir),
+  };
+  fs::write(path, text)?;
+  Ok(())
+}
+
+fn entry(node: &Node) -> Entry {
+  #[allow(unused_mut)]
+  let mut entry = Entry {
+    path: node.path.clone(),
+    title: Some(node.title().to_owned()),
+    ..Default::default()
+  };
+  #[cfg(feature = "metadata")]
+  if let Some(metadata) = &node.metadata {
+    entry.artist = metadata.artist.clone();
+    entry.album = metadata.album.clone();
+  }
+  entry
+}
+
+// Playlist files are meant to be utf-8, older ones are often latin-1.
+fn text(bytes: &[u8]) -> String {
+  let bytes = bytes.strip_prefix(b"\xef\xbb\xbf").unwrap_or(bytes);
+  match std::str::from_utf8(bytes) {
+    Ok(text) => text.to_owned(),
+    Err(_) => bytes.iter().map(|&b| b as char).collect(),
+  }
+}
+
+// Resolves an entry against the playlist's folder. Streams are skipped.
+fn entry_path(entry: &str, dir: &Path) -> Option<PathBuf> {
+  let entry = match entry.strip_prefix("file://") {
+    Some(path) => percent_decode(path),
+    None if entry.contains("://") => return None,
+    None => entry.to_owned(),
+  };
+  // playlists made on windows
+  let entry = entry.replace('\\', "/");
+
+  let mut path = PathBuf::new();
+  for component in dir.join(entry).components() {
+    match component {
+      Component::ParentDir => {
+        path.pop();
+      }
+      Component::CurDir => {}
+      component => path.push(component),
+    }
+  }
+  Some(path)
+}
+
+fn percent_decode(text: &str) -> String {
+  let bytes = text.as_bytes();
+  let mut out = Vec::with_capacity(bytes.len());
+  let mut i = 0;
+  while i < bytes.len() {
+    let hex = bytes
+      .get(i + 1..i + 3)
+      .and_then(|h| std::str::from_utf8(h).ok())
+      .and_then(|h| u8::from_str_radix(h, 16).ok());
+    match (bytes[i], hex) {
+      (b'%', Some(byte)) => {
+        out.push(byte);
+        i += 3;
+      }
+      (byte, _) => {
+        out.push(byte);
+        i += 1;
+      }
+    }
+  }
+  String::from_utf8_lossy(&out).into_owned()
+}
+
+fn percent_encode(text: &str) -> String {
+  let mut out = String::new();
+  for byte in text.bytes() {
+    match byte {
+      b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+        out.push(byte as char)
+      }
+      _ => out.push_str(&format!("%{:02X}", byte)),
+    }
+  }
+  out
+}
+
+// `to` as seen from `dir`, or left absolute when all they share is the root.
+fn relative(dir: &Path, to: &Path) -> PathBuf {
+  let shared = dir
+    .components()
+    .zip(to.components())
+    .take_while(|(a, b)| a == b)
+    .count();
+  if shared <= 1 {
+    return to.to_owned();
+  }
+
+  let mut path = PathBuf::new();
+  for _ in dir.components().skip(shared) {
+    path.push("..");
+  }
+  path.extend(to.components().skip(shared));
+  path
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn entries(paths: &[&str]) -> Vec<Entry> {
+    paths
+      .iter()
+      .map(|path| Entry {
+        path: PathBuf::from(path),
+        title: Some(format!("{} title", path)),
+        artist: Some("Artist".to_owned()),
+        album: None,
+      })
+      .collect()
+  }
+
+  #[test]
+  fn written_paths_are_relative_within_a_tree() {
+    let dir = Path::new("/music/lists");
+    assert_eq!(
+      relative(dir, Path::new("/music/rock/song.mp3")),
+      Path::new("../rock/song.mp3")
+    );
+    assert_eq!(
+      relative(dir, Path::new("/music/lists/a.mp3")),
+      Path::new("a.mp3")
+    );
+    assert_eq!(
+      relative(dir, Path::new("/mnt/b.mp3")),
+      Path::new("/mnt/b.mp3")
+    );
+  }
+
+  #[test]
+  fn formats_read_back_what_they_write() {
+    let dir = Path::new("/music/lists");
+    let written = entries(&["/music/rock/a song.mp3", "/mnt/b & c.flac"]);
+
+    let pls = pls::parse(&pls::write(&written, dir), dir);
+    assert_eq!(pls.len(), 2);
+    assert_eq!(pls[0].path, written[0].path);
+    assert_eq!(pls[1].title, written[1].title);
+
+    // xspf is the only one that keeps artists
+    let xspf = xspf::parse(&xspf::write(&written, dir), dir).unwrap();
+    assert_eq!(xspf, written);
+  }
+}
@@ -0,0 +1,72 @@
+use super::*;
+
+pub fn parse(text: &str, dir: &Path) -> Vec<Entry> {
+  let mut entries = vec![];
+  let mut title = None;
+  for line in text.lines().map(str::trim) {
+    if let Some(info) = line.strip_prefix("#EXTINF:") {
+      // `#EXTINF:<seconds> [attributes],<title>`
+      title = info
+        .split_once(',')
+        .map(|(_, t)| t.trim().to_owned())
+        .filter(|t| !t.is_empty());
+      continue;
+    }
+    if line.is_empty() || line.starts_with('#') {
+      continue;
+    }
+    if let Some(path) = entry_path(line, dir) {
+      entries.push(Entry {
+        path,
+        title: title.take(),
+        ..Default::default()
+      });
+    }
+    title = None;
+  }
+  entries
+}
+
+// Extended m3u, in utf-8 whatever the extension.
+pub fn write(entries: &[Entry], dir: &Path) -> String {
+  let mut out = String::from("#EXTM3U\n");
+  for entry in entries {
+    if let Some(title) = &entry.title {
+      out.push_str(&format!("#EXTINF:-1,{}\n", title));
+    }
+    out.push_str(&relative(dir, &entry.path).to_string_lossy());
+    out.push('\n');
+  }
+  out
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn entries_resolve_against_the_playlist() {
+    let dir = Path::new("/music/lists");
+    let text = "#EXTM3U\n\
+      #EXTINF:123,Artist - Song\n\
+      ../rock/song.mp3\n\
+      /abs/other.flac\n\
+      http://radio.example/stream\n\
+      file:///music/with%20space.ogg\n\
+      sub\\win.mp3\n";
+
+    let entries = parse(text, dir);
+    let paths: Vec<&Path> = entries.iter().map(|e| e.path.as_path()).collect();
+    assert_eq!(
+      paths,
+      vec![
+        Path::new("/music/rock/song.mp3"),
+        Path::new("/abs/other.flac"),
+        Path::new("/music/with space.ogg"),
+        Path::new("/music/lists/sub/win.mp3"),
+      ]
+    );
+    assert_eq!(entries[0].title.as_deref(), Some("Artist - Song"));
+    assert_eq!(entries[1].title, None);
+  }
+}
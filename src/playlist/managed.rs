@@ -0,0 +1,223 @@
+use super::*;
+use serde_derive::{Deserialize, Serialize};
+use std::io::{Read, Seek, SeekFrom};
+
+// How much of each end of a file goes into its fingerprint.
+const FINGERPRINT_SPAN: u64 = 64 * 1024;
+
+// A playlist kept in the data directory, one toml file per playlist.
+#[derive(Deserialize, Serialize, Default)]
+pub struct Managed {
+  #[serde(skip)]
+  pub name: String,
+  #[serde(default)]
+  pub tracks: Vec<Track>,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+pub struct Track {
+  pub path: PathBuf,
+  pub title: String,
+  // size and a hash of the file's ends, to find it again after it's moved
+  pub size: u64,
+  pub fingerprint: u64,
+}
+
+impl Track {
+  pub fn new(node: &Node) -> Self {
+    let (size, fingerprint) = fingerprint(&node.path).unwrap_or_default();
+    Self {
+      path: node.path.clone(),
+      title: node.title().to_owned(),
+      size,
+      fingerprint,
+    }
+  }
+
+  pub fn entry(&self) -> Entry {
+    Entry {
+      path: self.path.clone(),
+      title: Some(self.title.clone()),
+      ..Default::default()
+    }
+  }
+}
+
+fn dir() -> Result<PathBuf> {
+  Ok(Meta::config_dir()?.join("playlists"))
+}
+
+fn file(name: &str) -> Result<PathBuf> {
+  // names become file names, so keep them to one path component
+  let name: String = name
+    .chars()
+    .map(|c| match c {
+      '/' | '\\' | '\0' => '_',
+      c => c,
+    })
+    .collect();
+  Ok(dir()?.join(format!("{}.toml", name.trim_start_matches('.'))))
+}
+
+// Names of the saved playlists, sorted.
+pub fn names() -> Vec<String> {
+  let mut names: Vec<String> = fs::read_dir(dir().unwrap_or_default())
+    .map(|entries| {
+      entries
+        .flatten()
+        .map(|e| e.path())
+        .filter(|p| extension(p) == Some("toml"))
+        .filter_map(|p| Some(p.file_stem()?.to_string_lossy().to_string()))
+        .collect()
+    })
+    .unwrap_or_default();
+  names.sort_by_key(|n| n.to_lowercase());
+  names
+}
+
+impl Managed {
+  pub fn new(name: &str) -> Self {
+    Self {
+      name: name.to_owned(),
+      tracks: vec![],
+    }
+  }
+
+  pub fn load(name: &str) -> Result<Self> {
+    let mut managed: Self = toml::from_str(&fs::read_to_string(file(name)?)?)?;
+    managed.name = name.to_owned();
+    Ok(managed)
+  }
+
+  pub fn save(&self) -> Result<()> {
+    let _ = fs::create_dir_all(dir()?);
+    fs::write(file(&self.name)?, toml::to_string(self)?)?;
+    Ok(())
+  }
+
+  pub fn rename(&mut self, name: &str) -> Result<()> {
+    let (old, new) = (file(&self.name)?, file(name)?);
+    // saving over another playlist would lose it
+    if old != new && new.exists() {
+      bail!("there's already a playlist named {}", name);
+    }
+    self.name = name.to_owned();
+    self.save()?;
+    if old != new {
+      fs::remove_file(old)?;
+    }
+    Ok(())
+  }
+
+  pub fn delete(name: &str) -> Result<()> {
+    fs::remove_file(file(name)?)?;
+    Ok(())
+  }
+
+  // Finds tracks whose files have gone missing under `root` by their
+  // fingerprint, returning whether any were found.
+  pub fn relocate(&mut self, root: &Path) -> bool {
+    let lost: Vec<usize> = (0..self.tracks.len())
+      .filter(|&i| is_missing(&self.tracks[i].path) && self.tracks[i].size > 0)
+      .collect();
+    if lost.is_empty() {
+      return false;
+    }
+
+    let sizes: HashSet<u64> = lost.iter().map(|&i| self.tracks[i].size).collect();
+    let mut candidates = vec![];
+    let depth = Config::load().unwrap_or_default().scan_depth_limit;
+    files_with_sizes(root, &sizes, depth, &mut candidates);
+
+    let mut found = false;
+    for (path, size) in candidates {
+      let lost_here: Vec<usize> = lost
+        .iter()
+        .copied()
+        .filter(|&i| self.tracks[i].size == size && is_missing(&self.tracks[i].path))
+        .collect();
+      if lost_here.is_empty() {
+        continue;
+      }
+      let (_, fingerprint) = match fingerprint(&path) {
+        Some(fingerprint) => fingerprint,
+        None => continue,
+      };
+      for i in lost_here {
+        if self.tracks[i].fingerprint == fingerprint {
+          self.tracks[i].path = path.clone();
+          found = true;
+        }
+      }
+    }
+    found
+  }
+}
+
+fn files_with_sizes(dir: &Path, sizes: &HashSet<u64>, depth: usize, out: &mut Vec<(PathBuf, u64)>) {
+  if depth == 0 {
+    return;
+  }
+  for entry in fs::read_dir(dir).into_iter().flatten().flatten() {
+    if entry.file_name().to_string_lossy().starts_with('.') {
+      continue;
+    }
+    let path = entry.path();
+    match entry.metadata() {
+      Ok(m) if m.is_dir() => files_with_sizes(&path, sizes, depth - 1, out),
+      Ok(m) if sizes.contains(&m.len()) => out.push((path, m.len())),
+      _ => {}
+    }
+  }
+}
+
+// FNV-1a over the size and both ends of the file, which stays the same
+// wherever the file goes.
+pub fn fingerprint(path: &Path) -> Option<(u64, u64)> {
+  let mut file = File::open(path).ok()?;
+  let size = file.metadata().ok()?.len();
+
+  let mut bytes = vec![];
+  file
+    .by_ref()
+    .take(FINGERPRINT_SPAN)
+    .read_to_end(&mut bytes)
+    .ok()?;
+  if size > FINGERPRINT_SPAN * 2 {
+    file.seek(SeekFrom::End(-(FINGERPRINT_SPAN as i64))).ok()?;
+    file.read_to_end(&mut bytes).ok()?;
+  }
+
+  let mut hash: u64 = 0xcbf29ce484222325;
+  for byte in size.to_le_bytes().iter().chain(bytes.iter()) {
+    hash ^= *byte as u64;
+    hash = hash.wrapping_mul(0x100000001b3);
+  }
+  Some((size, hash))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn moved_tracks_are_found_by_fingerprint() {
+    let root = std::env::temp_dir().join(format!("aquinas-managed-{}", std::process::id()));
+    let _ = fs::create_dir_all(root.join("old"));
+    let _ = fs::create_dir_all(root.join("new"));
+    fs::write(root.join("old/song.mp3"), b"some song data").unwrap();
+    fs::write(root.join("new/decoy.mp3"), b"some song DATA").unwrap();
+
+    let mut managed = Managed::new("test");
+    managed.tracks.push(Track::new(&Node::new(
+      root.join("old/song.mp3"),
+      &Config::default(),
+    )));
+    fs::rename(root.join("old/song.mp3"), root.join("new/song.mp3")).unwrap();
+
+    assert!(managed.relocate(&root));
+    assert_eq!(managed.tracks[0].path, root.join("new/song.mp3"));
+
+    let _ = fs::remove_dir_all(&root);
+  }
+}
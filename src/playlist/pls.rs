@@ -0,0 +1,52 @@
+use super::*;
+
+// `[playlist]` with numbered `FileN`, `TitleN` and `LengthN` keys. Entries
+// keep the order of their numbers rather than of the lines.
+pub fn parse(text: &str, dir: &Path) -> Vec<Entry> {
+  let mut files: Vec<(u32, PathBuf)> = vec![];
+  let mut titles: HashMap<u32, String> = HashMap::new();
+
+  for line in text.lines().map(str::trim) {
+    let (key, value) = match line.split_once('=') {
+      Some((key, value)) => (key.trim().to_lowercase(), value.trim()),
+      None => continue,
+    };
+    let number = |prefix: &str| key.strip_prefix(prefix)?.parse::<u32>().ok();
+
+    if let Some(n) = number("file") {
+      if let Some(path) = entry_path(value, dir) {
+        files.push((n, path));
+      }
+    } else if let Some(n) = number("title") {
+      titles.insert(n, value.to_owned());
+    }
+  }
+
+  files.sort_by_key(|(n, _)| *n);
+  files
+    .into_iter()
+    .map(|(n, path)| Entry {
+      path,
+      title: titles.remove(&n),
+      ..Default::default()
+    })
+    .collect()
+}
+
+pub fn write(entries: &[Entry], dir: &Path) -> String {
+  let mut out = String::from("[playlist]\n");
+  for (i, entry) in entries.iter().enumerate() {
+    let n = i + 1;
+    out.push_str(&format!(
+      "File{}={}\n",
+      n,
+      relative(dir, &entry.path).to_string_lossy()
+    ));
+    if let Some(title) = &entry.title {
+      out.push_str(&format!("Title{}={}\n", n, title));
+    }
+    out.push_str(&format!("Length{}=-1\n", n));
+  }
+  out.push_str(&format!("NumberOfEntries={}\nVersion=2\n", entries.len()));
+  out
+}
@@ -0,0 +1,547 @@
+use super::*;
+use serde_derive::{Deserialize, Serialize};
+use std::{
+  sync::OnceLock,
+  time::{SystemTime, UNIX_EPOCH},
+};
+
+// Smart playlists show up as folders at the top of the root, under paths
+// in this (nonexistent) folder.
+const SMART_DIR: &str = ".aquinas-smart";
+
+// Always there once anything's been played, made again after each play.
+const RECENT: &str = "Recently played";
+const RECENT_LIMIT: usize = 100;
+
+// `smart_playlists.toml`, next to `config.toml`:
+//
+// [[playlist]]
+// name = "Unplayed FLACs"
+// rules = ["ext = flac", "plays = 0", "added < 30d"]
+//
+// Rules are `<field> <op> <value>`. Text fields (title, artist,
+// albumartist, album, genre, ext) take `=`, `!=` and `~` (contains), `path`
+// takes a glob (relative to the root unless it starts with `/`), and
+// numbers (year, duration, plays, rating, played, added) take `=`, `!=`,
+// `<`, `<=`, `>` and `>=`. `played` and `added` compare how long ago, like
+// `30d`; durations are like `3m` or `4:20`.
+#[derive(Deserialize, Serialize, Default)]
+pub struct SmartPlaylists {
+  #[serde(default, rename = "playlist")]
+  pub playlists: Vec<Smart>,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+pub struct Smart {
+  pub name: String,
+  pub rules: Vec<String>,
+  // match any rule rather than all of them
+  #[serde(default)]
+  pub any: bool,
+  pub limit: Option<usize>,
+}
+
+impl SmartPlaylists {
+  fn path() -> Result<PathBuf> {
+    Ok(Meta::config_dir()?.join("smart_playlists.toml"))
+  }
+
+  pub fn load() -> Result<Self> {
+    Ok(toml::from_str(&fs::read_to_string(Self::path()?)?)?)
+  }
+}
+
+type Generated = HashMap<PathBuf, Arc<Vec<PathBuf>>>;
+
+// Generated lists, kept until the library changes.
+static GENERATED: OnceLock<Mutex<(Option<SystemTime>, Generated)>> = OnceLock::new();
+
+fn generated() -> &'static Mutex<(Option<SystemTime>, Generated)> {
+  GENERATED.get_or_init(|| Mutex::new((None, HashMap::new())))
+}
+
+// Drops generated lists so they're made again from the library as it is now.
+pub fn invalidate() {
+  generated().lock().1.clear();
+}
+
+// Drops the lists whose rules go by plays, which change with every one,
+// giving their names along with "Recently played".
+pub fn forget_played() -> Vec<String> {
+  let names: Vec<String> = SmartPlaylists::load()
+    .map(|smart| smart.playlists)
+    .unwrap_or_default()
+    .into_iter()
+    .filter(|smart| {
+      smart.rules.iter().any(|rule| {
+        let field = Rule::parse(rule).map(|rule| rule.field);
+        matches!(field, Ok(Field::Plays | Field::Played))
+      })
+    })
+    .map(|smart| smart.name)
+    .collect();
+  let named = |path: &Path| {
+    let name = path.file_name().unwrap_or_default();
+    names.iter().any(|n| name == OsStr::new(n))
+  };
+  generated().lock().1.retain(|path, _| !named(path));
+  names.into_iter().chain([RECENT.to_owned()]).collect()
+}
+
+// Whether the rules have been edited since the lists were generated, which
+// drops them.
+pub fn edited() -> bool {
+  let modified = SmartPlaylists::path()
+    .and_then(|p| Ok(fs::metadata(p)?.modified()?))
+    .ok();
+  let mut generated = generated().lock();
+  if generated.0 == modified {
+    return false;
+  }
+  *generated = (modified, HashMap::new());
+  true
+}
+
+pub fn is_smart(path: &Path) -> bool {
+  path.parent().and_then(|p| p.file_name()) == Some(OsStr::new(SMART_DIR)) && !path.exists()
+}
+
+// The smart playlist folders to show in a root.
+pub fn folders(root: &Path) -> Vec<PathBuf> {
+  let recent = match history::recent_paths(1).is_empty() {
+    true => None,
+    false => Some(RECENT.to_owned()),
+  };
+  let names = SmartPlaylists::load()
+    .map(|smart| smart.playlists)
+    .unwrap_or_default()
+    .into_iter()
+    .map(|smart| smart.name)
+    .filter(|name| name != RECENT);
+  recent
+    .into_iter()
+    .chain(names)
+    .map(|name| root.join(SMART_DIR).join(name))
+    .collect()
+}
+
+// The files a smart playlist folder holds, or None when the path isn't one.
+pub fn list(path: &Path, config: &Config) -> Option<Result<Arc<Vec<PathBuf>>>> {
+  if !is_smart(path) {
+    return None;
+  }
+  if path.file_name() == Some(OsStr::new(RECENT)) {
+    return Some(Ok(Arc::new(recent(path))));
+  }
+
+  edited();
+  if let Some(files) = generated().lock().1.get(path) {
+    return Some(Ok(files.clone()));
+  }
+
+  Some(generate(path, config).map(|files| {
+    let files = Arc::new(files);
+    generated().lock().1.insert(path.to_owned(), files.clone());
+    files
+  }))
+}
+
+fn recent(path: &Path) -> Vec<PathBuf> {
+  let root = path.parent().and_then(Path::parent).unwrap_or(path);
+  history::recent_paths(RECENT_LIMIT)
+    .into_iter()
+    .filter(|file| file.starts_with(root) && !is_missing(file))
+    .collect()
+}
+
+// Run by the library's scanner, which reads smart playlist folders like any
+// other, so the tags and durations rules ask about come from the index off
+// the UI thread.
+fn generate(path: &Path, config: &Config) -> Result<Vec<PathBuf>> {
+  let root = path.parent().and_then(Path::parent).unwrap_or(path);
+  let name = path.file_name().unwrap_or_default().to_string_lossy();
+  let smart = match SmartPlaylists::load()?
+    .playlists
+    .into_iter()
+    .find(|smart| smart.name == name)
+  {
+    Some(smart) => smart,
+    None => bail!("no smart playlist named {}", name),
+  };
+  let rules = smart
+    .rules
+    .iter()
+    .map(|rule| Rule::parse(rule))
+    .collect::<Result<Vec<_>>>()?;
+
+  let files = library::files_under(root, config)
+    .into_iter()
+    .filter(|path| {
+      let facts = Facts::new(path, root);
+      match smart.any {
+        true => rules.iter().any(|rule| rule.matches(&facts)),
+        false => rules.iter().all(|rule| rule.matches(&facts)),
+      }
+    })
+    .take(smart.limit.unwrap_or(usize::MAX))
+    .collect();
+  Ok(files)
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Field {
+  Title,
+  Artist,
+  AlbumArtist,
+  Album,
+  Genre,
+  Ext,
+  Year,
+  Path,
+  Duration,
+  Plays,
+  Rating,
+  Played,
+  Added,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Op {
+  Eq,
+  Ne,
+  Lt,
+  Le,
+  Gt,
+  Ge,
+  Contains,
+}
+
+#[derive(Debug)]
+struct Rule {
+  field: Field,
+  op: Op,
+  text: String,
+  number: f64, // the value for number fields
+}
+
+// Longer operators first, so `<=` isn't read as `<`.
+const OPS: &[(&str, Op)] = &[
+  ("!=", Op::Ne),
+  ("<=", Op::Le),
+  (">=", Op::Ge),
+  ("=", Op::Eq),
+  ("<", Op::Lt),
+  (">", Op::Gt),
+  ("~", Op::Contains),
+];
+
+impl Rule {
+  fn parse(rule: &str) -> Result<Self> {
+    let (at, symbol, op) = match OPS
+      .iter()
+      .filter_map(|(symbol, op)| Some((rule.find(symbol)?, *symbol, *op)))
+      .min_by_key(|(at, symbol, _)| (*at, usize::MAX - symbol.len()))
+    {
+      Some(found) => found,
+      None => bail!("no operator in rule `{}`", rule),
+    };
+    let name = rule[..at].trim().to_lowercase();
+    let text = rule[at + symbol.len()..]
+      .trim()
+      .trim_matches('"')
+      .to_owned();
+
+    let field = match name.as_str() {
+      "title" => Field::Title,
+      "artist" => Field::Artist,
+      "albumartist" => Field::AlbumArtist,
+      "album" => Field::Album,
+      "genre" => Field::Genre,
+      "ext" | "extension" => Field::Ext,
+      "year" => Field::Year,
+      "path" => Field::Path,
+      "duration" => Field::Duration,
+      "plays" => Field::Plays,
+      "rating" => Field::Rating,
+      "played" => Field::Played,
+      "added" => Field::Added,
+      _ => bail!("unknown field `{}`", name),
+    };
+
+    let number = match field {
+      Field::Duration | Field::Played | Field::Added => secs(&text),
+      Field::Year | Field::Plays | Field::Rating => text.parse().ok(),
+      _ => Some(0.),
+    };
+    let number = match number {
+      Some(number) => number,
+      None => bail!("`{}` isn't a value for {}", text, name),
+    };
+    let text_field = matches!(
+      field,
+      Field::Title
+        | Field::Artist
+        | Field::AlbumArtist
+        | Field::Album
+        | Field::Genre
+        | Field::Ext
+        | Field::Path
+    );
+    match (text_field, op) {
+      (true, Op::Lt | Op::Le | Op::Gt | Op::Ge) | (false, Op::Contains) => {
+        bail!("`{}` can't be used with {}", symbol, name)
+      }
+      _ => {}
+    }
+
+    Ok(Self {
+      field,
+      op,
+      text,
+      number,
+    })
+  }
+
+  fn matches(&self, facts: &Facts) -> bool {
+    let text = match self.field {
+      Field::Title => Some(facts.title()),
+      Field::Artist | Field::AlbumArtist | Field::Album | Field::Genre => facts.tag(self.field),
+      Field::Ext => extension(facts.path).map(str::to_owned),
+      Field::Path => {
+        let path = facts.path(self.text.starts_with('/'));
+        return match self.op {
+          Op::Contains => path.contains(&self.text),
+          op => glob(&self.text, &path) == (op == Op::Eq),
+        };
+      }
+      _ => None,
+    };
+    if let Some(text) = text {
+      let (text, value) = (text.to_lowercase(), self.text.to_lowercase());
+      return match self.op {
+        Op::Eq => text == value,
+        Op::Ne => text != value,
+        Op::Contains => text.contains(&value),
+        _ => false,
+      };
+    }
+
+    let number = match self.field {
+      Field::Year => facts.year().map(|year| year as f64),
+      Field::Duration => library::index::duration(facts.path).map(|d| d as f64),
+      Field::Plays => Some(facts.plays() as f64),
+      Field::Rating => facts.rating().map(|r| r as f64),
+      Field::Played => facts.last_played().map(age),
+      Field::Added => facts.added().map(age),
+      // a text field with no tag
+      _ => return self.op == Op::Ne,
+    };
+    match number {
+      Some(number) => match self.op {
+        Op::Eq => number == self.number,
+        Op::Ne => number != self.number,
+        Op::Lt => number < self.number,
+        Op::Le => number <= self.number,
+        Op::Gt => number > self.number,
+        Op::Ge => number >= self.number,
+        Op::Contains => false,
+      },
+      // never played is longer ago than anything, other unknowns only differ
+      None => match self.field {
+        Field::Played => matches!(self.op, Op::Ne | Op::Gt | Op::Ge),
+        _ => self.op == Op::Ne,
+      },
+    }
+  }
+}
+
+// What rules can ask about a file. Its tags are read once, from the index,
+// when a rule first needs them.
+struct Facts<'a> {
+  path: &'a Path,
+  root: &'a Path,
+  #[cfg(feature = "metadata")]
+  tags: std::cell::OnceCell<Option<Metadata>>,
+}
+
+impl<'a> Facts<'a> {
+  fn new(path: &'a Path, root: &'a Path) -> Self {
+    Self {
+      path,
+      root,
+      #[cfg(feature = "metadata")]
+      tags: std::cell::OnceCell::new(),
+    }
+  }
+
+  // relative to the root, unless asked for in full
+  fn path(&self, absolute: bool) -> String {
+    let path = match absolute {
+      true => self.path,
+      false => self.path.strip_prefix(self.root).unwrap_or(self.path),
+    };
+    path.to_string_lossy().to_string()
+  }
+
+  fn title(&self) -> String {
+    let name = || {
+      self
+        .path
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_string()
+    };
+    self.tag(Field::Title).unwrap_or_else(name)
+  }
+
+  #[cfg(feature = "metadata")]
+  fn tags(&self) -> Option<&Metadata> {
+    let tags = self
+      .tags
+      .get_or_init(|| library::index::metadata(self.path));
+    tags.as_ref()
+  }
+
+  // a text field's tag
+  #[cfg(feature = "metadata")]
+  fn tag(&self, field: Field) -> Option<String> {
+    let tags = self.tags()?;
+    match field {
+      Field::Title => tags.title.clone(),
+      Field::Artist => tags.artist.clone(),
+      Field::AlbumArtist => tags.album_artist.clone().or(tags.artist.clone()),
+      Field::Album => tags.album.clone(),
+      Field::Genre => tags.genre.clone(),
+      _ => None,
+    }
+  }
+  #[cfg(not(feature = "metadata"))]
+  fn tag(&self, _field: Field) -> Option<String> {
+    None
+  }
+
+  #[cfg(feature = "metadata")]
+  fn year(&self) -> Option<i32> {
+    self.tags()?.year()
+  }
+  #[cfg(not(feature = "metadata"))]
+  fn year(&self) -> Option<i32> {
+    None
+  }
+
+  #[cfg(feature = "metadata")]
+  fn stars(&self) -> Option<u8> {
+    self.tags()?.stars
+  }
+  #[cfg(not(feature = "metadata"))]
+  fn stars(&self) -> Option<u8> {
+    None
+  }
+
+  fn plays(&self) -> u32 {
+    history::plays(self.path)
+  }
+
+  fn rating(&self) -> Option<u8> {
+    match ratings::rating(self.path, self.stars()).stars {
+      0 => None,
+      stars => Some(stars),
+    }
+  }
+
+  fn last_played(&self) -> Option<i64> {
+    history::last_played(self.path)
+  }
+
+  // when the file arrived, going by the file system
+  fn added(&self) -> Option<i64> {
+    let metadata = fs::metadata(self.path).ok()?;
+    let time = metadata.created().or(metadata.modified()).ok()?;
+    Some(time.duration_since(UNIX_EPOCH).ok()?.as_secs() as i64)
+  }
+}
+
+fn age(timestamp: i64) -> f64 {
+  let now = SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .map(|d| d.as_secs() as i64)
+    .unwrap_or(0);
+  (now - timestamp) as f64
+}
+
+// `90`, `90s`, `3m`, `2h`, `30d`, `2w`, `1y` or `4:20`, in seconds.
+fn secs(text: &str) -> Option<f64> {
+  if let Some((minutes, seconds)) = text.split_once(':') {
+    return Some(minutes.parse::<f64>().ok()? * 60. + seconds.parse::<f64>().ok()?);
+  }
+  let split = text
+    .find(|c: char| !c.is_ascii_digit() && c != '.')
+    .unwrap_or(text.len());
+  let (number, unit) = text.split_at(split);
+  let unit = match unit.trim() {
+    "" | "s" => 1.,
+    "m" => 60.,
+    "h" => 60. * 60.,
+    "d" => 60. * 60. * 24.,
+    "w" => 60. * 60. * 24. * 7.,
+    "y" => 60. * 60. * 24. * 365.,
+    _ => return None,
+  };
+  Some(number.parse::<f64>().ok()? * unit)
+}
+
+// `*` and `?` stay within a path component, `**` crosses them.
+fn glob(pattern: &str, path: &str) -> bool {
+  fn matches(pattern: &[u8], path: &[u8]) -> bool {
+    match pattern {
+      [] => path.is_empty(),
+      [b'*', b'*', rest @ ..] => (0..=path.len()).any(|i| matches(rest, &path[i..])),
+      [b'*', rest @ ..] => (0..=path.len())
+        .take_while(|&i| i == 0 || path[i - 1] != b'/')
+        .any(|i| matches(rest, &path[i..])),
+      [b'?', rest @ ..] => matches!(path, [c, ..] if *c != b'/') && matches(rest, &path[1..]),
+      [c, rest @ ..] => path.first() == Some(c) && matches(rest, &path[1..]),
+    }
+  }
+  matches(pattern.as_bytes(), path.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn rules_parse() {
+    let rule = Rule::parse("added < 30d").unwrap();
+    assert_eq!((rule.field, rule.op), (Field::Added, Op::Lt));
+    assert_eq!(rule.number, 30. * 24. * 60. * 60.);
+
+    let rule = Rule::parse("duration >= 4:20").unwrap();
+    assert_eq!(
+      (rule.field, rule.op, rule.number),
+      (Field::Duration, Op::Ge, 260.)
+    );
+
+    assert_eq!(Rule::parse("artist != Queen").unwrap().op, Op::Ne);
+    let rule = Rule::parse("year >= 1990").unwrap();
+    assert_eq!((rule.field, rule.number), (Field::Year, 1990.));
+    assert_eq!(
+      Rule::parse("albumartist ~ various").unwrap().field,
+      Field::AlbumArtist
+    );
+    assert!(Rule::parse("genre < Rock").is_err());
+    assert!(Rule::parse("plays ~ 3").is_err());
+    assert!(Rule::parse("colour = red").is_err());
+    assert!(Rule::parse("plays = lots").is_err());
+  }
+
+  #[test]
+  fn globs_respect_components() {
+    assert!(glob("*/Live/**", "Band/Live/1999/track.flac"));
+    assert!(!glob("*/Live/*", "Band/Live/1999/track.flac"));
+    assert!(glob("**.flac", "a/b/c.flac"));
+    assert!(glob("?and/*.mp3", "Band/x.mp3"));
+    assert!(!glob("*.mp3", "Band/x.mp3"));
+  }
+}
@@ -0,0 +1,80 @@
+use super::*;
+
+const NAMESPACE: &str = "http://xspf.org/ns/0/";
+
+// Tracks in `trackList`, each with the first `location` we can play from
+// and the `title`, `creator` and `album` they were given.
+pub fn parse(text: &str, dir: &Path) -> Result<Vec<Entry>> {
+  let document = roxmltree::Document::parse(text)?;
+  let child = |node: roxmltree::Node, name: &str| {
+    node
+      .children()
+      .find(|c| c.has_tag_name(name))
+      .and_then(|c| c.text())
+      .map(|t| t.trim().to_owned())
+      .filter(|t| !t.is_empty())
+  };
+
+  let mut entries = vec![];
+  for track in document.descendants().filter(|n| n.has_tag_name("track")) {
+    let path = track
+      .children()
+      .filter(|c| c.has_tag_name("location"))
+      .filter_map(|c| c.text())
+      .find_map(|location| match location.trim().contains("://") {
+        true => entry_path(location.trim(), dir),
+        // relative locations are URI references too
+        false => entry_path(&percent_decode(location.trim()), dir),
+      });
+
+    if let Some(path) = path {
+      entries.push(Entry {
+        path,
+        title: child(track, "title"),
+        artist: child(track, "creator"),
+        album: child(track, "album"),
+      });
+    }
+  }
+  Ok(entries)
+}
+
+pub fn write(entries: &[Entry], dir: &Path) -> String {
+  let mut out = format!(
+    "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<playlist version=\"1\" xmlns=\"{}\">\n  <trackList>\n",
+    NAMESPACE
+  );
+  for entry in entries {
+    let path = relative(dir, &entry.path);
+    let location = match path.is_absolute() {
+      true => format!("file://{}", percent_encode(&path.to_string_lossy())),
+      false => percent_encode(&path.to_string_lossy()),
+    };
+
+    out.push_str("    <track>\n");
+    out.push_str(&format!(
+      "      <location>{}</location>\n",
+      escape(&location)
+    ));
+    for (tag, value) in [
+      ("title", &entry.title),
+      ("creator", &entry.artist),
+      ("album", &entry.album),
+    ] {
+      if let Some(value) = value {
+        out.push_str(&format!("      <{tag}>{}</{tag}>\n", escape(value)));
+      }
+    }
+    out.push_str("    </track>\n");
+  }
+  out.push_str("  </trackList>\n</playlist>\n");
+  out
+}
+
+fn escape(text: &str) -> String {
+  text
+    .replace('&', "&amp;")
+    .replace('<', "&lt;")
+    .replace('>', "&gt;")
+    .replace('"', "&quot;")
+}
@@ -15,8 +15,11 @@ pub use std::{
   time::Duration,
 };
 
-pub const SUPPORTED: &'static [&'static str] =
-  &["mp3", "ogg", "opus", "flac", "wav", "webm", "mp4", "m4a"];
+// the default for `Config::extensions`
+pub const SUPPORTED: &'static [&'static str] = &[
+  "mp3", "ogg", "opus", "flac", "wav", "webm", "mp4", "m4a", "mid", "midi", "mod", "s3m", "xm",
+  "it", "aiff", "aif", "caf", "aac", "mka",
+];
 
 pub fn extension<'a>(path: &'a Path) -> Option<&'a str> {
   if let Some(ext) = path.extension() {
@@ -0,0 +1,131 @@
+use crate::*;
+use serde_derive::{Deserialize, Serialize};
+use std::sync::OnceLock;
+
+#[derive(Deserialize, Serialize, Default, Clone, Copy, PartialEq, Debug)]
+pub struct Rating {
+  #[serde(default)]
+  pub stars: u8, // 1-5, 0 when unrated
+  #[serde(default)]
+  pub favourite: bool,
+}
+
+// Kept in `ratings.toml` by path. With `rating_tags` on, files' own rating
+// tags, as the tagger read them, fill in what isn't stored, and changes are
+// written back to them.
+#[derive(Deserialize, Serialize, Default)]
+struct Ratings {
+  tracks: HashMap<String, Rating>,
+  #[serde(skip)]
+  rating_tags: bool, // from the config, as it was at start
+}
+
+static RATINGS: OnceLock<Mutex<Ratings>> = OnceLock::new();
+
+fn ratings() -> &'static Mutex<Ratings> {
+  RATINGS.get_or_init(|| {
+    let mut ratings = Ratings::load().unwrap_or_default();
+    ratings.rating_tags = Config::load().unwrap_or_default().rating_tags;
+    Mutex::new(ratings)
+  })
+}
+
+impl Ratings {
+  fn path() -> Result<PathBuf> {
+    Ok(Meta::config_dir()?.join("ratings.toml"))
+  }
+
+  fn load() -> Result<Self> {
+    Ok(toml::from_str(&fs::read_to_string(Self::path()?)?)?)
+  }
+
+  fn save(&self) -> Result<()> {
+    let path = Self::path()?;
+    if let Some(dir) = path.parent() {
+      let _ = fs::create_dir_all(dir);
+    }
+    fs::write(path, toml::to_string(self)?)?;
+    Ok(())
+  }
+}
+
+pub fn get(node: &Node) -> Rating {
+  rating(&node.path, node.stars())
+}
+
+// A file's rating, given the stars it's tagged with, if they've been read.
+pub fn rating(path: &Path, tagged: Option<u8>) -> Rating {
+  let ratings = ratings().lock();
+  match ratings.tracks.get(path.to_string_lossy().as_ref()) {
+    Some(rating) => *rating,
+    None => Rating {
+      stars: tagged.filter(|_| ratings.rating_tags).unwrap_or(0),
+      favourite: false,
+    },
+  }
+}
+
+pub fn set(path: &Path, rating: Rating) {
+  let mut ratings = ratings().lock();
+  match rating == Rating::default() {
+    true => ratings.tracks.remove(path.to_string_lossy().as_ref()),
+    false => ratings
+      .tracks
+      .insert(path.to_string_lossy().to_string(), rating),
+  };
+  let _ = ratings.save();
+  let rating_tags = ratings.rating_tags;
+  drop(ratings);
+
+  if rating_tags {
+    write_tag_stars(path, rating.stars);
+  }
+}
+
+// Carries a stored rating over to where its file's been moved, by the
+// organizer.
+#[cfg(feature = "metadata")]
+pub fn moved(from: &Path, to: &Path) {
+  let mut ratings = ratings().lock();
+  if let Some(rating) = ratings.tracks.remove(from.to_string_lossy().as_ref()) {
+    ratings
+      .tracks
+      .insert(to.to_string_lossy().to_string(), rating);
+    let _ = ratings.save();
+  }
+}
+
+// One star up or down, from 0 (unrated) to 5.
+pub fn rate(node: &Node, delta: i8) {
+  let mut rating = get(node);
+  rating.stars = (rating.stars as i8 + delta).clamp(0, 5) as u8;
+  set(&node.path, rating);
+}
+
+pub fn toggle_favourite(node: &Node) {
+  let mut rating = get(node);
+  rating.favourite = !rating.favourite;
+  set(&node.path, rating);
+}
+
+// How likely weighted shuffle is to pick a track early. Unrated tracks
+// count as three stars, favourites double.
+pub fn weight(node: &Node) -> f64 {
+  let rating = get(node);
+  let stars = match rating.stars {
+    0 => 3.,
+    stars => stars as f64,
+  };
+  match rating.favourite {
+    true => stars * 2.,
+    false => stars,
+  }
+}
+
+#[cfg(feature = "metadata")]
+fn write_tag_stars(path: &Path, stars: u8) {
+  let _ = metadata::write_stars(path, stars);
+}
+
+#[cfg(not(feature = "metadata"))]
+fn write_tag_stars(_path: &Path, _stars: u8) {}
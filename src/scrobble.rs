@@ -0,0 +1,434 @@
+use crate::config::{ScrobbleApi, Scrobbler};
+use crate::*;
+use md5::{Digest, Md5};
+use serde_derive::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::{io::Write, sync::OnceLock};
+
+// How long to wait before trying again when the server can't be reached.
+const RETRY: Duration = Duration::from_secs(5 * 60);
+// The most either api takes in one submission.
+const BATCH: usize = 50;
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct Scrobble {
+  pub artist: String,
+  pub track: String,
+  pub album: Option<String>,
+  pub timestamp: i64, // when it started, unix seconds
+  pub duration: u64,
+}
+
+// Scrobbles waiting to be submitted, oldest first, in `scrobbles.toml`.
+// New ones are appended as `[[scrobble]]` tables; the file is written out
+// whole only when submitted ones are taken off the front.
+#[derive(Deserialize, Serialize, Default)]
+struct Queue {
+  #[serde(default, rename = "scrobble")]
+  scrobbles: Vec<Scrobble>,
+}
+
+// Held while the queue file is read or written.
+static FILE: Mutex<()> = Mutex::new(());
+static WAKE: OnceLock<Sender<()>> = OnceLock::new();
+
+impl Queue {
+  fn path() -> Result<PathBuf> {
+    Ok(Meta::config_dir()?.join("scrobbles.toml"))
+  }
+
+  fn load() -> Result<Self> {
+    Ok(toml::from_str(&fs::read_to_string(Self::path()?)?)?)
+  }
+
+  fn save(&self) -> Result<()> {
+    fs::write(Self::path()?, toml::to_string(self)?)?;
+    Ok(())
+  }
+
+  fn append(scrobble: &Scrobble) -> Result<()> {
+    let path = Self::path()?;
+    if let Some(dir) = path.parent() {
+      let _ = fs::create_dir_all(dir);
+    }
+    let table = Queue {
+      scrobbles: vec![scrobble.clone()],
+    };
+    let mut file = fs::OpenOptions::new()
+      .create(true)
+      .append(true)
+      .open(path)?;
+    writeln!(file, "{}", toml::to_string(&table)?)?;
+    Ok(())
+  }
+}
+
+// The usual rule: tracks over 30 seconds, played for half their length or
+// four minutes, whichever comes first.
+pub fn eligible(listened: u64, duration: u64) -> bool {
+  duration > 30 && (listened * 2 >= duration || listened >= 240)
+}
+
+// Queues a finished play, when it counts and there's somewhere to send it.
+pub fn record(node: &Node, start: i64, listened: u64, duration: u64) {
+  if !eligible(listened, duration) || Config::load().unwrap_or_default().scrobbler.is_none() {
+    return;
+  }
+  let scrobble = match scrobble(node, start, duration) {
+    Some(scrobble) => scrobble,
+    None => return,
+  };
+
+  let guard = FILE.lock();
+  let _ = Queue::append(&scrobble);
+  drop(guard);
+  if let Some(wake) = WAKE.get() {
+    let _ = wake.send(());
+  }
+}
+
+// Without tags, the artist is taken to be the folder above the album's.
+fn scrobble(node: &Node, start: i64, duration: u64) -> Option<Scrobble> {
+  let folder = |levels: usize| {
+    node
+      .path
+      .ancestors()
+      .nth(levels)
+      .and_then(Path::file_name)
+      .map(|name| name.to_string_lossy().to_string())
+  };
+  #[allow(unused_mut)]
+  let mut scrobble = Scrobble {
+    artist: folder(2)?,
+    track: node.title().to_owned(),
+    album: folder(1),
+    timestamp: start,
+    duration,
+  };
+  #[cfg(feature = "metadata")]
+  if let Some(metadata) = &node.metadata {
+    if let Some(artist) = &metadata.artist {
+      scrobble.artist = artist.clone();
+    }
+    if metadata.album.is_some() {
+      scrobble.album = metadata.album.clone();
+    }
+  }
+  Some(scrobble)
+}
+
+// Submits the queue in the background: right away, whenever something's
+// added, and every so often after failing.
+pub fn start() {
+  let (sender, receiver) = crossbeam_channel::unbounded();
+  if WAKE.set(sender).is_err() {
+    return;
+  }
+  thread::spawn(move || loop {
+    let _ = submit_queued();
+    let _ = receiver.recv_timeout(RETRY);
+    while receiver.try_recv().is_ok() {}
+  });
+}
+
+fn submit_queued() -> Result<()> {
+  let scrobbler = match Config::load()?.scrobbler {
+    Some(scrobbler) => scrobbler,
+    None => return Ok(()),
+  };
+
+  loop {
+    let batch: Vec<Scrobble> = {
+      let _guard = FILE.lock();
+      let mut queue = Queue::load().unwrap_or_default();
+      queue.scrobbles.truncate(BATCH);
+      queue.scrobbles
+    };
+    if batch.is_empty() {
+      return Ok(());
+    }
+
+    let (done, result) = send(&scrobbler, &batch);
+
+    // only ever appended to meanwhile, so the batch is still at the front
+    let guard = FILE.lock();
+    let mut queue = Queue::load().unwrap_or_default();
+    queue.scrobbles.drain(..done.min(queue.scrobbles.len()));
+    queue.save()?;
+    drop(guard);
+    result?;
+  }
+}
+
+// How many of the batch are done with, sent or turned away, and whether the
+// server could be reached for the rest.
+fn send(scrobbler: &Scrobbler, batch: &[Scrobble]) -> (usize, Result<()>) {
+  match submit(scrobbler, batch) {
+    Ok(()) => (batch.len(), Ok(())),
+    // one bad scrobble turns the whole batch away, so find it by sending
+    // them one at a time
+    Err(error) if rejected(&error) && batch.len() > 1 => {
+      for (i, scrobble) in batch.iter().enumerate() {
+        match submit(scrobbler, std::slice::from_ref(scrobble)) {
+          Ok(()) => {}
+          Err(error) if rejected(&error) => {}
+          Err(error) => return (i, Err(error)),
+        }
+      }
+      (batch.len(), Ok(()))
+    }
+    // the server understood and won't take it, so don't hold up the rest
+    Err(error) if rejected(&error) => (batch.len(), Ok(())),
+    Err(error) => (0, Err(error)),
+  }
+}
+
+// An error Last.fm gives in an otherwise successful response.
+#[derive(Debug)]
+struct LastFmError {
+  code: u64,
+  message: String,
+}
+
+impl std::fmt::Display for LastFmError {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    write!(f, "last.fm error {}: {}", self.code, self.message)
+  }
+}
+
+impl std::error::Error for LastFmError {}
+
+fn rejected(error: &anyhow::Error) -> bool {
+  if let Some(error) = error.downcast_ref::<LastFmError>() {
+    // what was sent is wrong, and will be every time; anything else, like
+    // the service being down or the session key needing renewing, is worth
+    // waiting out with the scrobbles kept
+    return matches!(error.code, 2 | 3 | 5 | 6 | 7);
+  }
+  match error.downcast_ref::<ureq::Error>() {
+    Some(ureq::Error::Status(code, _)) => *code == 400,
+    _ => false,
+  }
+}
+
+fn submit(scrobbler: &Scrobbler, batch: &[Scrobble]) -> Result<()> {
+  match scrobbler.api {
+    ScrobbleApi::ListenBrainz => listenbrainz(scrobbler, batch),
+    ScrobbleApi::LastFm => lastfm(scrobbler, batch),
+  }
+}
+
+fn listenbrainz(scrobbler: &Scrobbler, batch: &[Scrobble]) -> Result<()> {
+  let payload: Vec<Value> = batch
+    .iter()
+    .map(|scrobble| {
+      let mut metadata = Map::new();
+      metadata.insert("artist_name".into(), json!(scrobble.artist));
+      metadata.insert("track_name".into(), json!(scrobble.track));
+      if let Some(album) = &scrobble.album {
+        metadata.insert("release_name".into(), json!(album));
+      }
+      metadata.insert(
+        "additional_info".into(),
+        json!({ "duration": scrobble.duration, "submission_client": "aquinas" }),
+      );
+      json!({ "listened_at": scrobble.timestamp, "track_metadata": metadata })
+    })
+    .collect();
+  let listen_type = match batch.len() {
+    1 => "single",
+    _ => "import",
+  };
+
+  ureq::post(&format!(
+    "{}/1/submit-listens",
+    scrobbler.url.trim_end_matches('/')
+  ))
+  .set("Authorization", &format!("Token {}", scrobbler.token))
+  .send_json(json!({ "listen_type": listen_type, "payload": payload }))?;
+  Ok(())
+}
+
+fn lastfm(scrobbler: &Scrobbler, batch: &[Scrobble]) -> Result<()> {
+  let mut params = vec![
+    ("method".to_owned(), "track.scrobble".to_owned()),
+    ("api_key".to_owned(), scrobbler.api_key.clone()),
+    ("sk".to_owned(), scrobbler.token.clone()),
+  ];
+  for (i, scrobble) in batch.iter().enumerate() {
+    params.push((format!("artist[{}]", i), scrobble.artist.clone()));
+    params.push((format!("track[{}]", i), scrobble.track.clone()));
+    params.push((format!("timestamp[{}]", i), scrobble.timestamp.to_string()));
+    params.push((format!("duration[{}]", i), scrobble.duration.to_string()));
+    if let Some(album) = &scrobble.album {
+      params.push((format!("album[{}]", i), album.clone()));
+    }
+  }
+  params.push((
+    "api_sig".to_owned(),
+    signature(&params, &scrobbler.api_secret),
+  ));
+  params.push(("format".to_owned(), "json".to_owned()));
+
+  let form: Vec<(&str, &str)> = params
+    .iter()
+    .map(|(name, value)| (name.as_str(), value.as_str()))
+    .collect();
+  let response: Value = ureq::post(&scrobbler.url).send_form(&form)?.into_json()?;
+  if let Some(code) = response.get("error") {
+    return Err(
+      LastFmError {
+        code: code.as_u64().unwrap_or_default(),
+        message: response["message"].as_str().unwrap_or_default().to_owned(),
+      }
+      .into(),
+    );
+  }
+  Ok(())
+}
+
+// md5 of the parameters sorted by name, each name then value, then the secret.
+fn signature(params: &[(String, String)], secret: &str) -> String {
+  let mut sorted: Vec<&(String, String)> = params.iter().collect();
+  sorted.sort();
+  let mut hasher = Md5::new();
+  for (name, value) in sorted {
+    hasher.update(name.as_bytes());
+    hasher.update(value.as_bytes());
+  }
+  hasher.update(secret.as_bytes());
+  format!("{:x}", hasher.finalize())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::io::{BufRead, BufReader, Read};
+  use std::net::TcpListener;
+
+  // Answers requests on a local port with what `respond` makes of each
+  // body, giving back its url.
+  fn serve(respond: impl Fn(&str) -> (u16, String) + Send + 'static) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    thread::spawn(move || {
+      for stream in listener.incoming() {
+        let mut stream = stream.unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut length = 0;
+        let mut line = String::new();
+        while reader.read_line(&mut line).unwrap() > 2 {
+          if let Some(value) = line.to_lowercase().strip_prefix("content-length:") {
+            length = value.trim().parse().unwrap();
+          }
+          line.clear();
+        }
+        let mut body = vec![0; length];
+        reader.read_exact(&mut body).unwrap();
+        let (status, reply) = respond(&String::from_utf8_lossy(&body));
+        let _ = write!(
+          stream,
+          "HTTP/1.1 {} -\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+          status,
+          reply.len(),
+          reply
+        );
+      }
+    });
+    url
+  }
+
+  fn scrobbles(tracks: &[&str]) -> Vec<Scrobble> {
+    tracks
+      .iter()
+      .map(|track| Scrobble {
+        artist: "Artist".to_owned(),
+        track: track.to_string(),
+        album: None,
+        timestamp: 0,
+        duration: 200,
+      })
+      .collect()
+  }
+
+  #[test]
+  fn rejected_batches_go_one_at_a_time() {
+    let (sent, received) = crossbeam_channel::unbounded();
+    let url = serve(move |body| {
+      let body: Value = serde_json::from_str(body).unwrap();
+      let payload = body["payload"].as_array().unwrap();
+      let track = payload[0]["track_metadata"]["track_name"].as_str().unwrap();
+      match payload.len() > 1 || track == "bad" {
+        true => (400, "{}".to_owned()),
+        false => {
+          sent.send(track.to_owned()).unwrap();
+          (200, "{}".to_owned())
+        }
+      }
+    });
+    let scrobbler = Scrobbler {
+      api: ScrobbleApi::ListenBrainz,
+      url,
+      token: String::new(),
+      api_key: String::new(),
+      api_secret: String::new(),
+    };
+
+    let (done, result) = send(&scrobbler, &scrobbles(&["one", "bad", "two"]));
+    assert!(result.is_ok());
+    assert_eq!(done, 3);
+    assert_eq!(received.try_iter().collect::<Vec<_>>(), ["one", "two"]);
+  }
+
+  #[test]
+  fn half_or_four_minutes_counts() {
+    assert!(!eligible(30, 30));
+    assert!(eligible(100, 200));
+    assert!(!eligible(99, 200));
+    assert!(eligible(240, 3600));
+    assert!(!eligible(239, 3600));
+  }
+
+  #[test]
+  fn lastfm_errors_about_the_request_are_rejections() {
+    let scrobbler = |url| Scrobbler {
+      api: ScrobbleApi::LastFm,
+      url,
+      token: String::new(),
+      api_key: String::new(),
+      api_secret: String::new(),
+    };
+    let invalid = serve(|_| {
+      (
+        200,
+        r#"{"error":6,"message":"Invalid parameters"}"#.to_owned(),
+      )
+    });
+    let offline = serve(|_| {
+      (
+        200,
+        r#"{"error":11,"message":"Service Offline"}"#.to_owned(),
+      )
+    });
+
+    let error = submit(&scrobbler(invalid), &scrobbles(&["one"])).unwrap_err();
+    assert!(rejected(&error));
+    let error = submit(&scrobbler(offline), &scrobbles(&["one"])).unwrap_err();
+    assert!(!rejected(&error));
+  }
+
+  #[test]
+  fn lastfm_signs_sorted_params() {
+    let params = vec![
+      ("sk".to_owned(), "b".to_owned()),
+      ("api_key".to_owned(), "a".to_owned()),
+    ];
+    // md5("api_keyaskbsecret")
+    let mut hasher = Md5::new();
+    hasher.update(b"api_keyaskbsecret");
+    assert_eq!(
+      signature(&params, "secret"),
+      format!("{:x}", hasher.finalize())
+    );
+  }
+}